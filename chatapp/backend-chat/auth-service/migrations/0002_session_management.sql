@@ -0,0 +1,17 @@
+-- Когда сессией пользовались в последний раз (вход или обновление токена)
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS last_used_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+-- Отзыв сессии = удаление строки. chat-service слушает канал и сразу закрывает её WebSocket-соединения
+CREATE OR REPLACE FUNCTION notify_session_revoked()
+RETURNS TRIGGER AS $$
+BEGIN
+  PERFORM pg_notify('session_revoked', OLD.id::text);
+  RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS trg_sessions_notify_revoked ON sessions;
+CREATE TRIGGER trg_sessions_notify_revoked
+AFTER DELETE ON sessions
+FOR EACH ROW
+EXECUTE FUNCTION notify_session_revoked();
@@ -0,0 +1,11 @@
+-- Сессия — семейство refresh-токенов: при каждом обновлении выдаётся новый токен,
+-- а хэш предъявленного запоминается. Повторное предъявление уже использованного токена
+-- означает утечку — семейство (сессия) отзывается целиком
+CREATE TABLE IF NOT EXISTS rotated_refresh_tokens (
+    token_hash TEXT PRIMARY KEY,
+    session_id UUID NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL,
+    rotated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_rotated_refresh_tokens_session_id ON rotated_refresh_tokens(session_id);
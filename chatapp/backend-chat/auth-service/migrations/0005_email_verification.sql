@@ -0,0 +1,21 @@
+-- Подтверждение адреса. Уже зарегистрированные пользователи считаются подтверждёнными,
+-- чтобы REQUIRE_VERIFIED_EMAIL не закрыл им вход
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_verified_at TIMESTAMPTZ;
+UPDATE users SET email_verified_at = created_at WHERE email_verified_at IS NULL;
+
+-- Адреса хранятся нормализованными (без пробелов, в нижнем регистре). Старые записи
+-- приводим к этому виду, если это не столкнёт их с другой учётной записью
+UPDATE users u
+SET email = lower(btrim(u.email))
+WHERE u.email <> lower(btrim(u.email))
+  AND (SELECT count(*) FROM users o WHERE lower(btrim(o.email)) = lower(btrim(u.email))) = 1;
+
+-- Токены из письма с подтверждением. Хранится только хэш, использованный токен удаляется
+CREATE TABLE IF NOT EXISTS email_verification_tokens (
+    token_hash TEXT PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_email_verification_tokens_user_id ON email_verification_tokens(user_id);
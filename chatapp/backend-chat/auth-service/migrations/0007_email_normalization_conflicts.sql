@@ -0,0 +1,30 @@
+-- 0005 не стал нормализовать адреса, которые после нормализации совпали бы с адресом
+-- другой учётной записи. Такие пользователи не могут войти: /login ищет нормализованный адрес.
+-- Записываем их сюда, чтобы разобрать вручную (объединить или переименовать учётные записи)
+CREATE TABLE IF NOT EXISTS email_normalization_conflicts (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    normalized_email TEXT NOT NULL,
+    detected_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+INSERT INTO email_normalization_conflicts (user_id, email, normalized_email)
+SELECT u.id, u.email, lower(btrim(u.email))
+FROM users u
+WHERE u.email <> lower(btrim(u.email))
+  AND EXISTS (
+      SELECT 1 FROM users o
+      WHERE o.id <> u.id AND lower(btrim(o.email)) = lower(btrim(u.email))
+  )
+ON CONFLICT (user_id) DO NOTHING;
+
+DO $$
+DECLARE
+    conflicts BIGINT;
+BEGIN
+    SELECT count(*) INTO conflicts FROM email_normalization_conflicts;
+    IF conflicts > 0 THEN
+        RAISE NOTICE '% account(s) keep a non-normalized email that collides with another account, see email_normalization_conflicts', conflicts;
+    END IF;
+END
+$$;
@@ -0,0 +1,5 @@
+-- Неверные вторые факторы при входе считаются на пользователя, а не только на токен второго шага:
+-- иначе перебор продолжается с новым токеном после каждого ввода пароля.
+-- После серии ошибок вход с 2FA закрыт до mfa_locked_until
+ALTER TABLE users ADD COLUMN IF NOT EXISTS mfa_failed_attempts INT NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS mfa_locked_until TIMESTAMPTZ;
@@ -4,21 +4,49 @@ use dotenvy::dotenv;
 use std::env;
 use anyhow::{Result, Context};
 use sqlx::postgres::PgPoolOptions;
-use redis::Client as RedisClient;
+
+/// Куда уходят письма
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MailTransport {
+    /// В лог сервиса
+    Log,
+    /// Файлами в каталог
+    File(String),
+}
 
 #[derive(Clone, Debug)]
 pub struct Config {
     // Database
     pub database_url: String,
-    pub redis_url: String,
 
-    // JWT secrets
-    pub jwt_access_secret: String,
-    pub jwt_refresh_secret: String,
+    // JWT (контракт общий с chat-service)
+    pub jwt_issuer: String,
+    pub jwt_audience: String,
+    /// Каталог с ключами подписи `<kid>.pem`
+    pub jwt_keys_dir: String,
+    /// `kid` ключа, которым подписываются новые токены
+    pub jwt_key_id: String,
 
     // Token TTL
     pub access_token_ttl: Duration,
     pub refresh_token_ttl: Duration,
+    pub password_reset_ttl: Duration,
+    pub email_verification_ttl: Duration,
+    /// Пауза между письмами с подтверждением на один адрес
+    pub email_verification_resend_cooldown: Duration,
+    /// Сколько живёт токен второго шага входа
+    pub mfa_challenge_ttl: Duration,
+
+    /// Не пускать в `/login`, пока адрес не подтверждён
+    pub require_verified_email: bool,
+    /// Название сервиса в приложении-аутентификаторе
+    pub totp_issuer: String,
+
+    // Mail
+    pub mail_transport: MailTransport,
+    pub mail_from: String,
+    /// Адрес фронтенда для ссылок в письмах
+    pub app_base_url: String,
 
     // HTTP server
     pub bind_addr: String,
@@ -39,16 +67,28 @@ impl Config {
         let database_url = env::var("DATABASE_URL")
             .context("Missing env: DATABASE_URL")?;
 
-        let redis_url = env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".into());
-
-        let jwt_access_secret = env::var("JWT_ACCESS_SECRET")
-            .context("Missing env: JWT_ACCESS_SECRET")?;
-
-        let jwt_refresh_secret = env::var("JWT_REFRESH_SECRET")
-            .context("Missing env: JWT_REFRESH_SECRET")?;
+        let jwt_issuer = env::var("JWT_ISSUER").unwrap_or_else(|_| "chatapp-auth".into());
+        let jwt_audience = env::var("JWT_AUDIENCE").unwrap_or_else(|_| "chatapp".into());
+        let jwt_keys_dir = env::var("JWT_KEYS_DIR").unwrap_or_else(|_| "./keys".into());
+        let jwt_key_id = env::var("JWT_KEY_ID")
+            .context("Missing env: JWT_KEY_ID")?;
 
         let access_token_ttl = Self::parse_duration("ACCESS_TOKEN_TTL_SECONDS", 900)?;
         let refresh_token_ttl = Self::parse_duration("REFRESH_TOKEN_TTL_SECONDS", 2_592_000)?; // 30 days
+        let password_reset_ttl = Self::parse_duration("PASSWORD_RESET_TTL_SECONDS", 3600)?;
+        let email_verification_ttl = Self::parse_duration("EMAIL_VERIFICATION_TTL_SECONDS", 86_400)?; // 1 day
+        let email_verification_resend_cooldown = Self::parse_duration("EMAIL_VERIFICATION_RESEND_COOLDOWN_SECONDS", 60)?;
+        let mfa_challenge_ttl = Self::parse_duration("MFA_CHALLENGE_TTL_SECONDS", 300)?;
+        let require_verified_email = Self::parse_bool("REQUIRE_VERIFIED_EMAIL", false)?;
+        let totp_issuer = env::var("TOTP_ISSUER").unwrap_or_else(|_| "ChatApp".into());
+
+        let mail_transport = match env::var("MAIL_TRANSPORT").unwrap_or_else(|_| "log".into()).as_str() {
+            "log" => MailTransport::Log,
+            "file" => MailTransport::File(env::var("MAIL_DIR").unwrap_or_else(|_| "./mail".into())),
+            other => anyhow::bail!("MAIL_TRANSPORT must be 'log' or 'file', got '{}'", other),
+        };
+        let mail_from = env::var("MAIL_FROM").unwrap_or_else(|_| "no-reply@chatapp.local".into());
+        let app_base_url = env::var("APP_BASE_URL").unwrap_or_else(|_| "http://localhost:8080".into());
 
         let bind_addr = env::var("BIND_ADDR").unwrap_or_else(|_| "127.0.0.1:8080".into());
 
@@ -62,11 +102,21 @@ impl Config {
 
         Ok(Self {
             database_url,
-            redis_url,
-            jwt_access_secret,
-            jwt_refresh_secret,
+            jwt_issuer,
+            jwt_audience,
+            jwt_keys_dir,
+            jwt_key_id,
             access_token_ttl: Duration::from_secs(access_token_ttl),
             refresh_token_ttl: Duration::from_secs(refresh_token_ttl),
+            password_reset_ttl: Duration::from_secs(password_reset_ttl),
+            email_verification_ttl: Duration::from_secs(email_verification_ttl),
+            email_verification_resend_cooldown: Duration::from_secs(email_verification_resend_cooldown),
+            mfa_challenge_ttl: Duration::from_secs(mfa_challenge_ttl),
+            require_verified_email,
+            totp_issuer,
+            mail_transport,
+            mail_from,
+            app_base_url: app_base_url.trim_end_matches('/').to_string(),
             bind_addr,
             google_client_id,
             google_client_secret,
@@ -83,6 +133,17 @@ impl Config {
             .with_context(|| format!("{} must be a valid integer representing seconds", var_name))
     }
 
+    fn parse_bool(var_name: &str, default: bool) -> Result<bool> {
+        match env::var(var_name) {
+            Err(_) => Ok(default),
+            Ok(value) => match value.trim().to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" => Ok(true),
+                "0" | "false" | "no" | "" => Ok(false),
+                _ => anyhow::bail!("{} must be true or false, got '{}'", var_name, value),
+            },
+        }
+    }
+
     /// Создать подключение к PostgreSQL через sqlx
     pub async fn init_pg_pool(&self) -> Result<sqlx::PgPool> {
         let pool = PgPoolOptions::new()
@@ -90,15 +151,7 @@ impl Config {
             .connect(&self.database_url)
             .await
             .context("Failed to connect to PostgreSQL")?;
-        tracing::info!("Connected to PostgreSQL at {}", self.database_url);
+        tracing::info!("Connected to PostgreSQL");
         Ok(pool)
     }
-
-    /// Создать клиент Redis
-    pub fn init_redis_client(&self) -> Result<RedisClient> {
-        let client = RedisClient::open(self.redis_url.clone())
-            .context("Failed to create Redis client")?;
-        tracing::info!("Redis client initialized at {}", self.redis_url);
-        Ok(client)
-    }
 }
\ No newline at end of file
@@ -1,7 +1,7 @@
 use axum::{
-    extract::Extension,
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{ConnectInfo, Extension},
+    http::{header::USER_AGENT, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
 use headers::{Authorization, HeaderMapExt, authorization::Bearer};
@@ -9,12 +9,17 @@ use serde::{Deserialize, Serialize};
 use sqlx::{PgPool};
 use uuid::Uuid;
 use chrono::{Utc, Duration};
-use tracing::{info, error};
-use crate::utils::hash::{hash_password, verify_password};
-use crate::utils::jwt::{create_access_token, decode_token};
+use tracing::{info, warn, error};
+use crate::handlers::email::{issue_verification_token, send_verification_mail};
+use crate::handlers::mfa::create_challenge;
+use crate::mail::Mailer;
+use crate::utils::email::normalize_email;
+use crate::utils::hash::{hash_password, hash_token, verify_password};
+use crate::utils::jwt::{create_access_token, decode_token, Claims};
+use crate::utils::keys::JwtKeys;
 use crate::config::Config;
-use crate::models::User;
-use redis::Commands;
+use crate::models::{Session, User};
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use axum::async_trait;
@@ -27,7 +32,7 @@ pub struct ErrorResponse {
     details: Option<String>,
 }
 
-fn err_json(code: StatusCode, msg: &str, details: Option<String>) -> (StatusCode, Json<ErrorResponse>) {
+pub(crate) fn err_json(code: StatusCode, msg: &str, details: Option<String>) -> (StatusCode, Json<ErrorResponse>) {
     (code, Json(ErrorResponse { error: msg.to_string(), details }))
 }
 
@@ -51,6 +56,57 @@ where
     }
 }
 
+/// Проверенный access-токен с живой сессией — те же проверки, что делает chat-service
+pub struct AuthClaims(pub Claims);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthClaims
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, Json<ErrorResponse>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let BearerToken(token) = BearerToken::from_request_parts(parts, state).await?;
+
+        let cfg = parts
+            .extensions
+            .get::<Config>()
+            .ok_or_else(|| err_json(StatusCode::INTERNAL_SERVER_ERROR, "config missing", None))?;
+        let keys = parts
+            .extensions
+            .get::<Arc<JwtKeys>>()
+            .ok_or_else(|| err_json(StatusCode::INTERNAL_SERVER_ERROR, "jwt keys missing", None))?;
+        let pool = parts
+            .extensions
+            .get::<PgPool>()
+            .ok_or_else(|| err_json(StatusCode::INTERNAL_SERVER_ERROR, "db pool missing", None))?;
+
+        let claims = decode_token(cfg, keys, &token)
+            .map_err(|e| err_json(StatusCode::UNAUTHORIZED, "invalid token", Some(e.to_string())))?;
+
+        let active = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM sessions
+                WHERE id = $1 AND user_id = $2 AND expires_at > NOW()
+            ) AS "active!"
+            "#,
+            claims.sid,
+            claims.sub
+        )
+        .fetch_one(pool)
+        .await
+        .map_err(|e| err_json(StatusCode::INTERNAL_SERVER_ERROR, "db error", Some(e.to_string())))?;
+
+        if !active {
+            return Err(err_json(StatusCode::UNAUTHORIZED, "session not found or expired", None));
+        }
+
+        Ok(Self(claims))
+    }
+}
+
 #[derive(Deserialize)]
 pub struct RegisterRequest {
     pub email: String,
@@ -74,6 +130,7 @@ pub struct LoginResponse {
     pub access_token: String,
     pub access_expires_at: i64,
     pub refresh_token: String,
+    pub refresh_expires_at: i64,
 }
 
 #[derive(Deserialize)]
@@ -86,12 +143,26 @@ pub struct RefreshResponse {
     pub access_token: String,
     pub access_expires_at: i64,
     pub refresh_token: String,
+    pub refresh_expires_at: i64,
 }
 
-#[derive(Serialize, Deserialize)]
-struct RedisSession {
-    user_id: Uuid,
-    expires_at: i64,
+fn refresh_ttl(cfg: &Config) -> Duration {
+    Duration::from_std(cfg.refresh_token_ttl).unwrap_or_else(|_| Duration::hours(24))
+}
+
+fn refresh_expires_at(cfg: &Config) -> chrono::DateTime<Utc> {
+    Utc::now() + refresh_ttl(cfg)
+}
+
+/// Адрес клиента: за ingress — первый адрес из `X-Forwarded-For`, иначе адрес соединения
+fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>) -> Option<String> {
+    headers
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| !ip.is_empty())
+        .or_else(|| peer.map(|addr| addr.ip().to_string()))
 }
 
 // ----------------------
@@ -99,8 +170,15 @@ struct RedisSession {
 // ----------------------
 pub async fn register(
     Extension(pool): Extension<PgPool>,
+    Extension(cfg): Extension<Config>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
     Json(payload): Json<RegisterRequest>,
 ) -> impl IntoResponse {
+    // Нормализуем до вставки: уникальность `email` не должна зависеть от регистра и пробелов
+    let Some(email) = normalize_email(&payload.email) else {
+        return err_json(StatusCode::BAD_REQUEST, "invalid email", None).into_response();
+    };
+
     if payload.password.len() < 8 {
         return err_json(StatusCode::BAD_REQUEST, "password too short", None).into_response();
     }
@@ -115,21 +193,30 @@ pub async fn register(
 
     let user_id = Uuid::new_v4();
 
-    let res = sqlx::query!(
-        r#"
-        INSERT INTO users (id, email, password_hash, provider, name)
-        VALUES ($1, $2, $3, 'local', $4)
-        "#,
-        user_id,
-        payload.email,
-        hashed,
-        payload.name
-    )
-    .execute(&pool)
+    // Пользователь и токен подтверждения создаются вместе
+    let res = async {
+        let mut tx = pool.begin().await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO users (id, email, password_hash, provider, name)
+            VALUES ($1, $2, $3, 'local', $4)
+            "#,
+            user_id,
+            email,
+            hashed,
+            payload.name
+        )
+        .execute(&mut *tx)
+        .await?;
+        let issued = issue_verification_token(&mut tx, &cfg, user_id).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(issued)
+    }
     .await;
 
     match res {
-        Ok(_) => {
+        Ok((token, expires_at)) => {
+            send_verification_mail(&cfg, mailer, email, token, expires_at);
             info!(user_id = %user_id, "user created");
             (StatusCode::CREATED, Json(RegisterResponse { user_id })).into_response()
         },
@@ -147,17 +234,23 @@ pub async fn register(
 
 pub async fn login(
     Extension(pool): Extension<PgPool>,
-    Extension(redis): Extension<Arc<redis::Client>>,
     Extension(cfg): Extension<Config>,
+    Extension(keys): Extension<Arc<JwtKeys>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     Json(payload): Json<LoginRequest>,
 ) -> impl IntoResponse {
+    let Some(email) = normalize_email(&payload.email) else {
+        return err_json(StatusCode::UNAUTHORIZED, "invalid credentials", None).into_response();
+    };
+
     let row = match sqlx::query!(
         r#"
-        SELECT id, password_hash
+        SELECT id, password_hash, email_verified_at, totp_enabled_at
         FROM users
         WHERE email = $1 AND is_active = true
         "#,
-        payload.email
+        email
     )
     .fetch_optional(&pool)
     .await
@@ -176,122 +269,222 @@ pub async fn login(
         return err_json(StatusCode::UNAUTHORIZED, "invalid credentials", None).into_response();
     }
 
-    let (access_token, access_exp) = match create_access_token(&cfg.jwt_access_secret, row.id, cfg.access_token_ttl.as_secs() as i64) {
-        Ok(t) => t,
-        Err(e) => return err_json(StatusCode::INTERNAL_SERVER_ERROR, "jwt error", Some(e.to_string())).into_response(),
-    };
+    // Проверяется после пароля, чтобы не раскрывать статус адреса без него
+    if cfg.require_verified_email && row.email_verified_at.is_none() {
+        return err_json(StatusCode::FORBIDDEN, "email not verified", None).into_response();
+    }
+
+    // С включённой 2FA пароль даёт только токен второго шага (`/login/mfa`)
+    if row.totp_enabled_at.is_some() {
+        return create_challenge(&pool, &cfg, row.id).await;
+    }
+
+    start_session(&pool, &cfg, &keys, row.id, &headers, peer.map(|ConnectInfo(addr)| addr)).await
+}
 
+/// Новая сессия после успешного входа: ответ `/login` (и `/login/mfa`) с парой токенов
+pub(crate) async fn start_session(
+    pool: &PgPool,
+    cfg: &Config,
+    keys: &JwtKeys,
+    user_id: Uuid,
+    headers: &HeaderMap,
+    peer: Option<SocketAddr>,
+) -> Response {
+    // Сессия в Postgres: по ней chat-service проверяет каждый access-токен (`sid`)
     let refresh_token = Uuid::new_v4().to_string();
-    let expires_at = Utc::now() + Duration::from_std(cfg.refresh_token_ttl).unwrap_or_else(|_| Duration::hours(24));
-    let expires_at_ts = expires_at.timestamp();
-
-    let session = RedisSession { user_id: row.id, expires_at: expires_at_ts };
-    let key = format!("refresh_token:{}", refresh_token);
-    let value = serde_json::to_string(&session).unwrap();
-    let ttl_secs = cfg.refresh_token_ttl.as_secs();
-
-    match redis.get_connection() {
-        Ok(mut conn) => {
-            let _: () = conn.set_ex(&key, &value, ttl_secs).unwrap_or_else(|e| {
-                error!("Redis set error: {:?}", e);
-                panic!("session storage failed")
-            });
-        },
+    let expires_at = refresh_expires_at(cfg);
+    let user_agent = headers.get(USER_AGENT).and_then(|v| v.to_str().ok());
+    let ip_address = client_ip(headers, peer);
+
+    let session_id = match sqlx::query_scalar!(
+        r#"
+        INSERT INTO sessions (user_id, refresh_token, user_agent, ip_address, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#,
+        user_id,
+        hash_token(&refresh_token),
+        user_agent,
+        ip_address,
+        expires_at
+    )
+    .fetch_one(pool)
+    .await
+    {
+        Ok(id) => id,
         Err(e) => {
-            error!("Redis connection error: {:?}", e);
+            error!("db insert session error: {:?}", e);
             return err_json(StatusCode::INTERNAL_SERVER_ERROR, "session storage failed", Some(e.to_string())).into_response();
         }
-    }
+    };
+
+    let (access_token, access_exp) = match create_access_token(cfg, keys, user_id, session_id) {
+        Ok(t) => t,
+        Err(e) => return err_json(StatusCode::INTERNAL_SERVER_ERROR, "jwt error", Some(e.to_string())).into_response(),
+    };
 
-    info!(user = %row.id, "user logged in");
+    info!(user = %user_id, session = %session_id, "user logged in");
 
     (StatusCode::OK, Json(LoginResponse {
         access_token,
         access_expires_at: access_exp,
         refresh_token,
+        refresh_expires_at: expires_at.timestamp(),
     })).into_response()
 }
 
+/// Итог предъявления refresh-токена
+enum Rotation {
+    /// Токен заменён новым в той же сессии
+    Rotated(Session),
+    /// Предъявлен уже использованный токен: сессия отозвана целиком
+    Reused { session_id: Uuid, user_id: Uuid },
+    /// Токен неизвестен, истёк или его сессия уже отозвана
+    Invalid,
+}
+
+/// Ротация в одной транзакции: из двух одновременных запросов с одним токеном
+/// `UPDATE` пройдёт только у первого, второй увидит токен уже использованным.
+/// Хэши, использованные раньше `forget_before`, удаляются: такие токены истекли бы и сами
+async fn rotate_refresh_token(
+    pool: &PgPool,
+    presented_hash: &str,
+    new_hash: &str,
+    expires_at: chrono::DateTime<Utc>,
+    forget_before: chrono::DateTime<Utc>,
+) -> Result<Rotation, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let session = sqlx::query_as!(
+        Session,
+        r#"
+        UPDATE sessions
+        SET refresh_token = $2, expires_at = $3, last_used_at = NOW()
+        WHERE refresh_token = $1
+          AND expires_at > NOW()
+          AND EXISTS (SELECT 1 FROM users u WHERE u.id = sessions.user_id AND u.is_active)
+        RETURNING id, user_id, expires_at
+        "#,
+        presented_hash,
+        new_hash,
+        expires_at
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if let Some(session) = session {
+        sqlx::query!(
+            r#"
+            INSERT INTO rotated_refresh_tokens (token_hash, session_id, user_id)
+            VALUES ($1, $2, $3)
+            "#,
+            presented_hash,
+            session.id,
+            session.user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // Живая сессия обновляется каждые несколько минут — без чистки история копилась бы до выхода
+        sqlx::query!(
+            r#"
+            DELETE FROM rotated_refresh_tokens
+            WHERE session_id = $1 AND rotated_at < $2
+            "#,
+            session.id,
+            forget_before
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        return Ok(Rotation::Rotated(session));
+    }
+
+    // Использованный токен — признак утечки: отзываем всё семейство, обоим участникам придётся войти заново
+    let revoked = sqlx::query!(
+        r#"
+        DELETE FROM sessions
+        WHERE id = (SELECT session_id FROM rotated_refresh_tokens WHERE token_hash = $1)
+        RETURNING id, user_id
+        "#,
+        presented_hash
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(match revoked {
+        Some(r) => Rotation::Reused { session_id: r.id, user_id: r.user_id },
+        None => Rotation::Invalid,
+    })
+}
+
 pub async fn refresh_token(
-    Extension(_pool): Extension<PgPool>,
-    Extension(redis): Extension<Arc<redis::Client>>,
+    Extension(pool): Extension<PgPool>,
     Extension(cfg): Extension<Config>,
+    Extension(keys): Extension<Arc<JwtKeys>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     Json(payload): Json<RefreshRequest>,
 ) -> impl IntoResponse {
-    let key = format!("refresh_token:{}", payload.refresh_token);
+    // Ротация в той же сессии: `sid` не меняется, открытые WebSocket переживают обновление токена
+    let new_refresh_token = Uuid::new_v4().to_string();
+
+    let rotation = rotate_refresh_token(
+        &pool,
+        &hash_token(&payload.refresh_token),
+        &hash_token(&new_refresh_token),
+        refresh_expires_at(&cfg),
+        Utc::now() - refresh_ttl(&cfg),
+    )
+    .await;
 
-    let session_json: Option<String> = match redis.get_connection() {
-        Ok(mut conn) => conn.get(&key).ok(),
+    let session = match rotation {
+        Ok(Rotation::Rotated(s)) => s,
+        Ok(Rotation::Reused { session_id, user_id }) => {
+            warn!(
+                target: "security",
+                event = "refresh_token_reuse",
+                user = %user_id,
+                session = %session_id,
+                ip = ?client_ip(&headers, peer.map(|ConnectInfo(addr)| addr)),
+                user_agent = ?headers.get(USER_AGENT).and_then(|v| v.to_str().ok()),
+                "rotated refresh token presented again, session revoked"
+            );
+            return err_json(StatusCode::UNAUTHORIZED, "refresh token reused, session revoked", None).into_response();
+        }
+        Ok(Rotation::Invalid) => return err_json(StatusCode::UNAUTHORIZED, "invalid refresh token", None).into_response(),
         Err(e) => {
-            error!("Redis connection error: {:?}", e);
+            error!("db refresh session error: {:?}", e);
             return err_json(StatusCode::INTERNAL_SERVER_ERROR, "session storage error", Some(e.to_string())).into_response();
         }
     };
 
-    let session: RedisSession = match session_json {
-        Some(json) => match serde_json::from_str(&json) {
-            Ok(s) => s,
-            Err(_) => return err_json(StatusCode::UNAUTHORIZED, "invalid session", None).into_response(),
-        },
-        None => return err_json(StatusCode::UNAUTHORIZED, "invalid refresh token", None).into_response(),
-    };
-
-    if session.expires_at <= Utc::now().timestamp() {
-        if let Ok(mut conn) = redis.get_connection() {
-            let _ = conn.del::<&str, ()>(&key);
-        }
-        return err_json(StatusCode::UNAUTHORIZED, "refresh token expired", None).into_response();
-    }
-
-    let (access_token, access_exp) = match create_access_token(&cfg.jwt_access_secret, session.user_id, cfg.access_token_ttl.as_secs() as i64) {
+    let (access_token, access_exp) = match create_access_token(&cfg, &keys, session.user_id, session.id) {
         Ok(t) => t,
         Err(e) => return err_json(StatusCode::INTERNAL_SERVER_ERROR, "jwt error", Some(e.to_string())).into_response(),
     };
 
-    let new_refresh_token = Uuid::new_v4().to_string();
-    let new_expires_at = Utc::now() + Duration::from_std(cfg.refresh_token_ttl).unwrap_or_else(|_| Duration::hours(24));
-    let new_expires_at_ts = new_expires_at.timestamp();
-    let new_session = RedisSession { user_id: session.user_id, expires_at: new_expires_at_ts };
-    let new_key = format!("refresh_token:{}", new_refresh_token);
-    let new_value = serde_json::to_string(&new_session).unwrap();
-    let ttl_secs = cfg.refresh_token_ttl.as_secs();
-
-    match redis.get_connection() {
-        Ok(mut conn) => {
-            let _: Result<(), _> = conn.set_ex::<&str, &str, ()>(&new_key, &new_value, ttl_secs);
-            let _: Result<(), _> = conn.del(&key);
-        },
-        Err(e) => {
-            error!("Redis connection error: {:?}", e);
-            return err_json(StatusCode::INTERNAL_SERVER_ERROR, "session storage failed", Some(e.to_string())).into_response();
-        }
-    };
-
     (StatusCode::OK, Json(RefreshResponse {
         access_token,
         access_expires_at: access_exp,
         refresh_token: new_refresh_token,
+        refresh_expires_at: session.expires_at.timestamp(),
     })).into_response()
 }
 
 pub async fn me(
     Extension(pool): Extension<PgPool>,
-    Extension(cfg): Extension<Config>,
-    BearerToken(token): BearerToken,
+    AuthClaims(claims): AuthClaims,
 ) -> impl IntoResponse {
-    let decoded = match decode_token(&cfg.jwt_access_secret, &token) {
-        Ok(d) => d,
-        Err(e) => return err_json(StatusCode::UNAUTHORIZED, "invalid token", Some(e.to_string())).into_response(),
-    };
-
-    let user_id = match Uuid::parse_str(&decoded.claims.sub) {
-        Ok(id) => id,
-        Err(_) => return err_json(StatusCode::UNAUTHORIZED, "invalid token", None).into_response(),
-    };
+    let user_id = claims.sub;
 
     let r = match sqlx::query!(
         r#"
-        SELECT id, email, provider, provider_id, name, avatar_url, is_active, created_at, updated_at
+        SELECT id, email, email_verified_at, provider, provider_id, name, avatar_url, is_active, created_at, updated_at
         FROM users
         WHERE id = $1
         "#,
@@ -307,6 +500,7 @@ pub async fn me(
     let user = User {
         id: r.id,
         email: r.email,
+        email_verified_at: r.email_verified_at,
         provider: r.provider,
         provider_id: r.provider_id,
         name: r.name,
@@ -329,19 +523,10 @@ pub struct UserSearchResult {
 
 pub async fn search_users(
     Extension(pool): Extension<PgPool>,
-    Extension(cfg): Extension<Config>,
-    BearerToken(token): BearerToken,
+    AuthClaims(claims): AuthClaims,
     query: axum::extract::Query<std::collections::HashMap<String, String>>,
 ) -> impl IntoResponse {
-    let decoded = match decode_token(&cfg.jwt_access_secret, &token) {
-        Ok(d) => d,
-        Err(e) => return err_json(StatusCode::UNAUTHORIZED, "invalid token", Some(e.to_string())).into_response(),
-    };
-
-    let current_user_id = match Uuid::parse_str(&decoded.claims.sub) {
-        Ok(id) => id,
-        Err(_) => return err_json(StatusCode::UNAUTHORIZED, "invalid token", None).into_response(),
-    };
+    let current_user_id = claims.sub;
 
     let q = query.get("q").map(|s| s.as_str()).unwrap_or("");
     if q.len() < 2 {
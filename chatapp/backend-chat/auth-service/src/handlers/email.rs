@@ -0,0 +1,224 @@
+use axum::{
+    extract::Extension,
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use sqlx::{PgConnection, PgPool};
+use std::sync::Arc;
+use tracing::{info, error};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::handlers::auth::err_json;
+use crate::mail::{MailMessage, Mailer};
+use crate::utils::email::normalize_email;
+use crate::utils::hash::{generate_token, hash_token};
+
+#[derive(Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResendVerificationRequest {
+    pub email: String,
+}
+
+/// Новый токен подтверждения; прежние неиспользованные перестают действовать
+pub(crate) async fn issue_verification_token(
+    conn: &mut PgConnection,
+    cfg: &Config,
+    user_id: Uuid,
+) -> Result<(String, DateTime<Utc>), sqlx::Error> {
+    let token = generate_token();
+    let expires_at = Utc::now() + Duration::from_std(cfg.email_verification_ttl).unwrap_or_else(|_| Duration::days(1));
+
+    sqlx::query!(
+        r#"
+        DELETE FROM email_verification_tokens
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO email_verification_tokens (token_hash, user_id, expires_at)
+        VALUES ($1, $2, $3)
+        "#,
+        hash_token(&token),
+        user_id,
+        expires_at
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok((token, expires_at))
+}
+
+/// Письмо со ссылкой подтверждения уходит в фоне, ответ его не ждёт
+pub(crate) fn send_verification_mail(
+    cfg: &Config,
+    mailer: Arc<dyn Mailer>,
+    to: String,
+    token: String,
+    expires_at: DateTime<Utc>,
+) {
+    let message = MailMessage {
+        to,
+        subject: "Подтверждение адреса".into(),
+        body: format!(
+            "Чтобы подтвердить адрес, перейдите по ссылке:\n{}/verify-email?token={}\n\nСсылка действует до {}.",
+            cfg.app_base_url,
+            token,
+            expires_at.format("%Y-%m-%d %H:%M UTC")
+        ),
+    };
+
+    tokio::spawn(async move {
+        if let Err(e) = mailer.send(message).await {
+            error!("failed to send verification mail: {:?}", e);
+        }
+    });
+}
+
+/// POST /email/verify — подтвердить адрес токеном из письма
+pub async fn verify_email(
+    Extension(pool): Extension<PgPool>,
+    Json(payload): Json<VerifyEmailRequest>,
+) -> impl IntoResponse {
+    let res = async {
+        let mut tx = pool.begin().await?;
+
+        let user_id = sqlx::query_scalar!(
+            r#"
+            DELETE FROM email_verification_tokens
+            WHERE token_hash = $1 AND expires_at > NOW()
+            RETURNING user_id
+            "#,
+            hash_token(&payload.token)
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(user_id) = user_id else {
+            return Ok(None);
+        };
+
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET email_verified_at = COALESCE(email_verified_at, NOW())
+            WHERE id = $1
+            "#,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some(user_id))
+    }
+    .await;
+
+    match res {
+        Ok(Some(user_id)) => {
+            info!(user = %user_id, "email verified");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(None) => err_json(StatusCode::BAD_REQUEST, "invalid or expired verification token", None).into_response(),
+        Err(e) => {
+            error!("db verify email error: {:?}", e);
+            err_json(StatusCode::INTERNAL_SERVER_ERROR, "db error", Some(e.to_string())).into_response()
+        }
+    }
+}
+
+/// POST /email/verify/resend — выслать письмо ещё раз. Без авторизации: при
+/// `REQUIRE_VERIFIED_EMAIL` войти до подтверждения нельзя. Ответ не зависит от того,
+/// есть ли такой неподтверждённый адрес и сработала ли пауза между письмами
+pub async fn resend_verification(
+    Extension(pool): Extension<PgPool>,
+    Extension(cfg): Extension<Config>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Json(payload): Json<ResendVerificationRequest>,
+) -> impl IntoResponse {
+    let Some(email) = normalize_email(&payload.email) else {
+        return err_json(StatusCode::BAD_REQUEST, "invalid email", None).into_response();
+    };
+
+    let user_id = match sqlx::query_scalar!(
+        r#"
+        SELECT id
+        FROM users
+        WHERE email = $1 AND is_active = true AND email_verified_at IS NULL
+        "#,
+        email
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(id)) => id,
+        Ok(None) => return StatusCode::ACCEPTED.into_response(),
+        Err(e) => return err_json(StatusCode::INTERNAL_SERVER_ERROR, "db error", Some(e.to_string())).into_response(),
+    };
+
+    let cooldown = Duration::from_std(cfg.email_verification_resend_cooldown).unwrap_or_else(|_| Duration::minutes(1));
+
+    let issued = async {
+        let mut tx = pool.begin().await?;
+
+        // Параллельные запросы на один адрес проверяют паузу по очереди
+        sqlx::query!(
+            r#"
+            SELECT id FROM users WHERE id = $1 FOR UPDATE
+            "#,
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let recently_sent = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM email_verification_tokens
+                WHERE user_id = $1 AND created_at > $2
+            ) AS "exists!"
+            "#,
+            user_id,
+            Utc::now() - cooldown
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if recently_sent {
+            return Ok(None);
+        }
+
+        let issued = issue_verification_token(&mut tx, &cfg, user_id).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some(issued))
+    }
+    .await;
+
+    match issued {
+        Ok(Some((token, expires_at))) => {
+            send_verification_mail(&cfg, mailer, email, token, expires_at);
+            info!(user = %user_id, "verification mail resent");
+            StatusCode::ACCEPTED.into_response()
+        }
+        Ok(None) => {
+            info!(user = %user_id, "verification mail not resent: cooldown");
+            StatusCode::ACCEPTED.into_response()
+        }
+        Err(e) => {
+            error!("db insert verification token error: {:?}", e);
+            err_json(StatusCode::INTERNAL_SERVER_ERROR, "db error", Some(e.to_string())).into_response()
+        }
+    }
+}
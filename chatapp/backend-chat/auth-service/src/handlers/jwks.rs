@@ -0,0 +1,21 @@
+use axum::{
+    extract::Extension,
+    http::header::CACHE_CONTROL,
+    response::IntoResponse,
+    Json,
+};
+use std::sync::Arc;
+
+use crate::utils::keys::JwtKeys;
+
+/// Сколько верификаторы могут кэшировать набор ключей. Новый ключ публикуется
+/// не меньше чем за это время до того, как станет активным
+pub const JWKS_MAX_AGE_SECONDS: u64 = 300;
+
+/// GET /.well-known/jwks.json — публичные ключи для проверки access-токенов
+pub async fn jwks(Extension(keys): Extension<Arc<JwtKeys>>) -> impl IntoResponse {
+    (
+        [(CACHE_CONTROL, format!("public, max-age={}", JWKS_MAX_AGE_SECONDS))],
+        Json(keys.jwks()),
+    )
+}
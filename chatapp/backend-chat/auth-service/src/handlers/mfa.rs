@@ -0,0 +1,571 @@
+use axum::{
+    extract::{ConnectInfo, Extension},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{info, warn, error};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::handlers::auth::{err_json, start_session, AuthClaims};
+use crate::utils::hash::{
+    generate_recovery_code, generate_token, hash_password, hash_token, normalize_recovery_code, verify_password,
+};
+use crate::utils::keys::JwtKeys;
+use crate::utils::totp;
+
+/// Столько кодов восстановления выдаётся за раз
+const RECOVERY_CODES: usize = 10;
+/// После стольких неверных кодов токен второго шага сгорает — вход начинается заново с пароля
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+/// После стольких неверных кодов подряд (по всем токенам второго шага) вход пользователя блокируется
+const MAX_USER_MFA_FAILURES: i32 = 10;
+/// На столько блокируется вход с 2FA
+const MFA_LOCKOUT_MINUTES: i64 = 15;
+
+#[derive(Serialize)]
+pub struct MfaChallengeResponse {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+    pub mfa_expires_at: i64,
+}
+
+#[derive(Deserialize)]
+pub struct LoginMfaRequest {
+    pub mfa_token: String,
+    /// Код из приложения или код восстановления
+    pub code: String,
+}
+
+#[derive(Serialize)]
+pub struct TotpSetupResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Deserialize)]
+pub struct TotpConfirmRequest {
+    pub code: String,
+}
+
+/// Повторная аутентификация перед отключением 2FA и сменой кодов: пароль и второй фактор
+#[derive(Deserialize)]
+pub struct ReauthRequest {
+    pub password: String,
+    pub code: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    /// Показываются один раз: в базе только хэши
+    pub recovery_codes: Vec<String>,
+}
+
+/// Отказ обработчика 2FA: ошибка базы или ответ клиенту
+enum MfaError {
+    Db(sqlx::Error),
+    Reject(StatusCode, &'static str),
+}
+
+impl From<sqlx::Error> for MfaError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Db(e)
+    }
+}
+
+impl IntoResponse for MfaError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Db(e) => {
+                error!("db mfa error: {:?}", e);
+                err_json(StatusCode::INTERNAL_SERVER_ERROR, "db error", Some(e.to_string())).into_response()
+            }
+            Self::Reject(code, msg) => err_json(code, msg, None).into_response(),
+        }
+    }
+}
+
+/// Токен второго шага входа. Ответ `/login` для пользователя с включённой 2FA
+pub(crate) async fn create_challenge(pool: &PgPool, cfg: &Config, user_id: Uuid) -> Response {
+    let token = generate_token();
+    let expires_at = Utc::now() + Duration::from_std(cfg.mfa_challenge_ttl).unwrap_or_else(|_| Duration::minutes(5));
+
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO mfa_challenges (token_hash, user_id, expires_at)
+        VALUES ($1, $2, $3)
+        "#,
+        hash_token(&token),
+        user_id,
+        expires_at
+    )
+    .execute(pool)
+    .await;
+
+    match res {
+        Ok(_) => {
+            info!(user = %user_id, "password accepted, waiting for second factor");
+            (StatusCode::OK, Json(MfaChallengeResponse {
+                mfa_required: true,
+                mfa_token: token,
+                mfa_expires_at: expires_at.timestamp(),
+            })).into_response()
+        }
+        Err(e) => {
+            error!("db insert mfa challenge error: {:?}", e);
+            err_json(StatusCode::INTERNAL_SERVER_ERROR, "db error", Some(e.to_string())).into_response()
+        }
+    }
+}
+
+/// Проверка второго фактора: кода TOTP (не старше последнего принятого) или
+/// неиспользованного кода восстановления, который при этом гасится
+async fn check_second_factor(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    secret: &str,
+    code: &str,
+) -> Result<bool, sqlx::Error> {
+    if let Some(step) = totp::verify(secret, code, Utc::now().timestamp() as u64) {
+        let accepted = sqlx::query!(
+            r#"
+            UPDATE users
+            SET totp_last_step = $2
+            WHERE id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)
+            "#,
+            user_id,
+            step as i64
+        )
+        .execute(&mut *conn)
+        .await?;
+        return Ok(accepted.rows_affected() == 1);
+    }
+
+    let code = normalize_recovery_code(code);
+    if code.len() != 10 {
+        return Ok(false);
+    }
+
+    let unused = sqlx::query!(
+        r#"
+        SELECT id, code_hash
+        FROM mfa_recovery_codes
+        WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        user_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let Some(matched) = unused.iter().find(|r| verify_password(&r.code_hash, &code).unwrap_or(false)) else {
+        return Ok(false);
+    };
+
+    let used = sqlx::query!(
+        r#"
+        UPDATE mfa_recovery_codes
+        SET used_at = NOW()
+        WHERE id = $1 AND used_at IS NULL
+        "#,
+        matched.id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    if used.rows_affected() == 1 {
+        info!(user = %user_id, remaining = unused.len() - 1, "recovery code used");
+    }
+    Ok(used.rows_affected() == 1)
+}
+
+/// Новый набор кодов восстановления взамен прежнего
+async fn replace_recovery_codes(conn: &mut PgConnection, user_id: Uuid) -> Result<Vec<String>, MfaError> {
+    sqlx::query!(
+        r#"
+        DELETE FROM mfa_recovery_codes
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let mut codes = Vec::with_capacity(RECOVERY_CODES);
+    for _ in 0..RECOVERY_CODES {
+        let code = generate_recovery_code();
+        let hashed = hash_password(&normalize_recovery_code(&code)).map_err(|e| {
+            error!("hash error: {:?}", e);
+            MfaError::Reject(StatusCode::INTERNAL_SERVER_ERROR, "hash error")
+        })?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO mfa_recovery_codes (user_id, code_hash)
+            VALUES ($1, $2)
+            "#,
+            user_id,
+            hashed
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        codes.push(code);
+    }
+
+    Ok(codes)
+}
+
+/// Пароль и второй фактор включённой 2FA. Строка пользователя блокируется до конца транзакции
+async fn reauthenticate(conn: &mut PgConnection, user_id: Uuid, req: &ReauthRequest) -> Result<(), MfaError> {
+    let user = sqlx::query!(
+        r#"
+        SELECT password_hash, totp_secret, totp_enabled_at
+        FROM users
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        user_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let (Some(secret), Some(_)) = (user.totp_secret, user.totp_enabled_at) else {
+        return Err(MfaError::Reject(StatusCode::CONFLICT, "2fa is not enabled"));
+    };
+
+    let password_ok = user
+        .password_hash
+        .map(|h| verify_password(&h, &req.password).unwrap_or(false))
+        .unwrap_or(false);
+    if !password_ok || !check_second_factor(conn, user_id, &secret, &req.code).await? {
+        warn!(target: "security", event = "mfa_reauth_failed", user = %user_id, "re-authentication failed");
+        return Err(MfaError::Reject(StatusCode::FORBIDDEN, "re-authentication failed"));
+    }
+
+    Ok(())
+}
+
+/// POST /login/mfa — второй шаг входа: токен из ответа `/login` и код
+pub async fn login_mfa(
+    Extension(pool): Extension<PgPool>,
+    Extension(cfg): Extension<Config>,
+    Extension(keys): Extension<Arc<JwtKeys>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(payload): Json<LoginMfaRequest>,
+) -> impl IntoResponse {
+    let res = async {
+        let mut tx = pool.begin().await?;
+
+        // Блокировка строк: параллельные попытки — с одним токеном или с разными — не обходят лимиты
+        let challenge = sqlx::query!(
+            r#"
+            SELECT c.user_id, c.attempts, u.totp_secret AS "totp_secret!", u.mfa_failed_attempts, u.mfa_locked_until
+            FROM mfa_challenges c
+            JOIN users u ON u.id = c.user_id
+            WHERE c.token_hash = $1
+              AND c.expires_at > NOW()
+              AND u.is_active
+              AND u.totp_enabled_at IS NOT NULL
+              AND u.totp_secret IS NOT NULL
+            FOR UPDATE OF c, u
+            "#,
+            hash_token(&payload.mfa_token)
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(MfaError::Reject(StatusCode::UNAUTHORIZED, "invalid or expired mfa token"))?;
+
+        // Во время блокировки код даже не проверяем: перебор не должен продвигаться
+        if challenge.mfa_locked_until.is_some_and(|until| until > Utc::now()) {
+            warn!(target: "security", event = "mfa_locked", user = %challenge.user_id, "second factor attempt while locked");
+            return Err(MfaError::Reject(StatusCode::TOO_MANY_REQUESTS, "too many failed attempts, try again later"));
+        }
+
+        if check_second_factor(&mut tx, challenge.user_id, &challenge.totp_secret, &payload.code).await? {
+            sqlx::query!(
+                r#"
+                DELETE FROM mfa_challenges
+                WHERE token_hash = $1
+                "#,
+                hash_token(&payload.mfa_token)
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(
+                r#"
+                UPDATE users
+                SET mfa_failed_attempts = 0, mfa_locked_until = NULL
+                WHERE id = $1
+                "#,
+                challenge.user_id
+            )
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            return Ok(challenge.user_id);
+        }
+
+        let failures = challenge.mfa_failed_attempts + 1;
+        if failures >= MAX_USER_MFA_FAILURES {
+            // Блокировка сжигает все токены второго шага: после неё вход начинается с пароля
+            sqlx::query!(
+                r#"
+                UPDATE users
+                SET mfa_failed_attempts = 0, mfa_locked_until = $2
+                WHERE id = $1
+                "#,
+                challenge.user_id,
+                Utc::now() + Duration::minutes(MFA_LOCKOUT_MINUTES)
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(
+                r#"
+                DELETE FROM mfa_challenges
+                WHERE user_id = $1
+                "#,
+                challenge.user_id
+            )
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+
+            warn!(target: "security", event = "mfa_locked", user = %challenge.user_id, minutes = MFA_LOCKOUT_MINUTES, "too many invalid second factors, login locked");
+            return Err(MfaError::Reject(StatusCode::TOO_MANY_REQUESTS, "too many failed attempts, try again later"));
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET mfa_failed_attempts = $2
+            WHERE id = $1
+            "#,
+            challenge.user_id,
+            failures
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if challenge.attempts + 1 >= MAX_CHALLENGE_ATTEMPTS {
+            sqlx::query!(
+                r#"
+                DELETE FROM mfa_challenges
+                WHERE token_hash = $1
+                "#,
+                hash_token(&payload.mfa_token)
+            )
+            .execute(&mut *tx)
+            .await?;
+        } else {
+            sqlx::query!(
+                r#"
+                UPDATE mfa_challenges
+                SET attempts = attempts + 1
+                WHERE token_hash = $1
+                "#,
+                hash_token(&payload.mfa_token)
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        warn!(target: "security", event = "mfa_code_rejected", user = %challenge.user_id, attempt = challenge.attempts + 1, "invalid second factor");
+        Err(MfaError::Reject(StatusCode::UNAUTHORIZED, "invalid code"))
+    }
+    .await;
+
+    match res {
+        Ok(user_id) => start_session(&pool, &cfg, &keys, user_id, &headers, peer.map(|ConnectInfo(addr)| addr)).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+/// POST /mfa/totp/setup — начать подключение: новый секрет и URI для QR-кода.
+/// 2FA включится только после `/mfa/totp/confirm`
+pub async fn setup_totp(
+    Extension(pool): Extension<PgPool>,
+    Extension(cfg): Extension<Config>,
+    AuthClaims(claims): AuthClaims,
+) -> impl IntoResponse {
+    let secret = totp::generate_secret();
+
+    let email = sqlx::query_scalar!(
+        r#"
+        UPDATE users
+        SET totp_secret = $2, totp_last_step = NULL
+        WHERE id = $1 AND totp_enabled_at IS NULL
+        RETURNING email
+        "#,
+        claims.sub,
+        secret
+    )
+    .fetch_optional(&pool)
+    .await;
+
+    match email {
+        Ok(Some(email)) => {
+            let otpauth_uri = totp::otpauth_uri(&cfg.totp_issuer, &email, &secret);
+            (StatusCode::OK, Json(TotpSetupResponse { secret, otpauth_uri })).into_response()
+        }
+        Ok(None) => err_json(StatusCode::CONFLICT, "2fa is already enabled", None).into_response(),
+        Err(e) => MfaError::Db(e).into_response(),
+    }
+}
+
+/// POST /mfa/totp/confirm — первый код из приложения включает 2FA. Возвращает коды
+/// восстановления; остальные сессии пользователя, открытые без второго фактора, завершаются
+pub async fn confirm_totp(
+    Extension(pool): Extension<PgPool>,
+    AuthClaims(claims): AuthClaims,
+    Json(payload): Json<TotpConfirmRequest>,
+) -> impl IntoResponse {
+    let res = async {
+        let mut tx = pool.begin().await?;
+
+        let user = sqlx::query!(
+            r#"
+            SELECT totp_secret, totp_enabled_at
+            FROM users
+            WHERE id = $1
+            FOR UPDATE
+            "#,
+            claims.sub
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if user.totp_enabled_at.is_some() {
+            return Err(MfaError::Reject(StatusCode::CONFLICT, "2fa is already enabled"));
+        }
+        let secret = user
+            .totp_secret
+            .ok_or(MfaError::Reject(StatusCode::BAD_REQUEST, "2fa setup not started"))?;
+
+        let step = totp::verify(&secret, &payload.code, Utc::now().timestamp() as u64)
+            .ok_or(MfaError::Reject(StatusCode::BAD_REQUEST, "invalid code"))?;
+
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET totp_enabled_at = NOW(), totp_last_step = $2
+            WHERE id = $1
+            "#,
+            claims.sub,
+            step as i64
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let codes = replace_recovery_codes(&mut tx, claims.sub).await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM sessions
+            WHERE user_id = $1 AND id <> $2
+            "#,
+            claims.sub,
+            claims.sid
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(codes)
+    }
+    .await;
+
+    match res {
+        Ok(recovery_codes) => {
+            info!(user = %claims.sub, "2fa enabled");
+            (StatusCode::OK, Json(RecoveryCodesResponse { recovery_codes })).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+/// POST /mfa/totp/disable — отключить 2FA; секрет и коды восстановления удаляются
+pub async fn disable_totp(
+    Extension(pool): Extension<PgPool>,
+    AuthClaims(claims): AuthClaims,
+    Json(payload): Json<ReauthRequest>,
+) -> impl IntoResponse {
+    let res = async {
+        let mut tx = pool.begin().await?;
+        reauthenticate(&mut tx, claims.sub, &payload).await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL
+            WHERE id = $1
+            "#,
+            claims.sub
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM mfa_recovery_codes
+            WHERE user_id = $1
+            "#,
+            claims.sub
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM mfa_challenges
+            WHERE user_id = $1
+            "#,
+            claims.sub
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok::<_, MfaError>(())
+    }
+    .await;
+
+    match res {
+        Ok(()) => {
+            info!(user = %claims.sub, "2fa disabled");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+/// POST /mfa/recovery-codes — выдать новые коды восстановления, прежние перестают действовать
+pub async fn regenerate_recovery_codes(
+    Extension(pool): Extension<PgPool>,
+    AuthClaims(claims): AuthClaims,
+    Json(payload): Json<ReauthRequest>,
+) -> impl IntoResponse {
+    let res = async {
+        let mut tx = pool.begin().await?;
+        reauthenticate(&mut tx, claims.sub, &payload).await?;
+        let codes = replace_recovery_codes(&mut tx, claims.sub).await?;
+        tx.commit().await?;
+        Ok::<_, MfaError>(codes)
+    }
+    .await;
+
+    match res {
+        Ok(recovery_codes) => {
+            info!(user = %claims.sub, "recovery codes regenerated");
+            (StatusCode::OK, Json(RecoveryCodesResponse { recovery_codes })).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
@@ -1 +1,6 @@
-pub mod auth;
\ No newline at end of file
+pub mod auth;
+pub mod email;
+pub mod jwks;
+pub mod mfa;
+pub mod password;
+pub mod sessions;
\ No newline at end of file
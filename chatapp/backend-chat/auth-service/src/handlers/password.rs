@@ -0,0 +1,203 @@
+use axum::{
+    extract::Extension,
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{info, error};
+
+use crate::config::Config;
+use crate::handlers::auth::err_json;
+use crate::mail::{MailMessage, Mailer};
+use crate::utils::email::normalize_email;
+use crate::utils::hash::{generate_token, hash_password, hash_token};
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+/// POST /password/forgot — письмо со ссылкой на сброс пароля.
+/// Ответ одинаковый, есть такой адрес или нет, чтобы по нему нельзя было перебирать пользователей
+pub async fn forgot_password(
+    Extension(pool): Extension<PgPool>,
+    Extension(cfg): Extension<Config>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Json(payload): Json<ForgotPasswordRequest>,
+) -> impl IntoResponse {
+    let Some(email) = normalize_email(&payload.email) else {
+        return err_json(StatusCode::BAD_REQUEST, "invalid email", None).into_response();
+    };
+
+    let user = match sqlx::query!(
+        r#"
+        SELECT id, email
+        FROM users
+        WHERE email = $1 AND is_active = true
+        "#,
+        email
+    )
+    .fetch_optional(&pool)
+    .await
+    {
+        Ok(Some(u)) => u,
+        Ok(None) => return StatusCode::ACCEPTED.into_response(),
+        Err(e) => return err_json(StatusCode::INTERNAL_SERVER_ERROR, "db error", Some(e.to_string())).into_response(),
+    };
+
+    let token = generate_token();
+    let expires_at = Utc::now() + Duration::from_std(cfg.password_reset_ttl).unwrap_or_else(|_| Duration::hours(1));
+
+    // Действует только последняя ссылка
+    let res = async {
+        let mut tx = pool.begin().await?;
+        sqlx::query!(
+            r#"
+            DELETE FROM password_reset_tokens
+            WHERE user_id = $1 AND used_at IS NULL
+            "#,
+            user.id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO password_reset_tokens (token_hash, user_id, expires_at)
+            VALUES ($1, $2, $3)
+            "#,
+            hash_token(&token),
+            user.id,
+            expires_at
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await
+    }
+    .await;
+
+    if let Err(e) = res {
+        error!("db insert reset token error: {:?}", e);
+        return err_json(StatusCode::INTERNAL_SERVER_ERROR, "db error", Some(e.to_string())).into_response();
+    }
+
+    let message = MailMessage {
+        to: user.email,
+        subject: "Сброс пароля".into(),
+        body: format!(
+            "Чтобы задать новый пароль, перейдите по ссылке:\n{}/reset-password?token={}\n\nСсылка действует до {}. Если вы не запрашивали сброс, просто проигнорируйте письмо.",
+            cfg.app_base_url,
+            token,
+            expires_at.format("%Y-%m-%d %H:%M UTC")
+        ),
+    };
+
+    // Отправка в фоне: время ответа не должно выдавать, существует ли адрес
+    let user_id = user.id;
+    tokio::spawn(async move {
+        if let Err(e) = mailer.send(message).await {
+            error!(user = %user_id, "failed to send password reset mail: {:?}", e);
+        }
+    });
+
+    info!(user = %user_id, "password reset requested");
+    StatusCode::ACCEPTED.into_response()
+}
+
+/// POST /password/reset — новый пароль по токену из письма. Все сессии пользователя завершаются
+pub async fn reset_password(
+    Extension(pool): Extension<PgPool>,
+    Json(payload): Json<ResetPasswordRequest>,
+) -> impl IntoResponse {
+    if payload.new_password.len() < 8 {
+        return err_json(StatusCode::BAD_REQUEST, "password too short", None).into_response();
+    }
+
+    let hashed = match hash_password(&payload.new_password) {
+        Ok(h) => h,
+        Err(e) => {
+            error!("hash error: {:?}", e);
+            return err_json(StatusCode::INTERNAL_SERVER_ERROR, "hash error", Some(e.to_string())).into_response();
+        }
+    };
+
+    let res = async {
+        let mut tx = pool.begin().await?;
+
+        // Токен гасится тем же запросом, что и проверяется: дважды им не воспользоваться
+        let user_id = sqlx::query_scalar!(
+            r#"
+            UPDATE password_reset_tokens
+            SET used_at = NOW()
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+            RETURNING user_id
+            "#,
+            hash_token(&payload.token)
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(user_id) = user_id else {
+            return Ok(None);
+        };
+
+        // Ссылка пришла на почту — заодно адрес подтверждён
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET password_hash = $2, email_verified_at = COALESCE(email_verified_at, NOW())
+            WHERE id = $1
+            "#,
+            user_id,
+            hashed
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // Пароль мог утечь вместе с сессиями: завершаем все (chat-service закроет их WebSocket)
+        sqlx::query!(
+            r#"
+            DELETE FROM sessions
+            WHERE user_id = $1
+            "#,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM password_reset_tokens
+            WHERE user_id = $1 AND used_at IS NULL
+            "#,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some(user_id))
+    }
+    .await;
+
+    match res {
+        Ok(Some(user_id)) => {
+            info!(user = %user_id, "password reset, all sessions revoked");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(None) => err_json(StatusCode::BAD_REQUEST, "invalid or expired reset token", None).into_response(),
+        Err(e) => {
+            error!("db reset password error: {:?}", e);
+            err_json(StatusCode::INTERNAL_SERVER_ERROR, "db error", Some(e.to_string())).into_response()
+        }
+    }
+}
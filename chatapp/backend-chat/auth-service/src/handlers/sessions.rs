@@ -0,0 +1,152 @@
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::{info, error};
+use uuid::Uuid;
+
+use crate::handlers::auth::{err_json, AuthClaims};
+
+// Отзыв сессии — удаление строки из `sessions`. Access-токены с этим `sid` перестают
+// приниматься сразу: и здесь, и в chat-service (он проверяет сессию на каждом запросе,
+// а открытые WebSocket закрывает по уведомлению `session_revoked`)
+
+#[derive(Serialize)]
+pub struct SessionInfo {
+    pub id: Uuid,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// Сессия, которой принадлежит токен запроса
+    pub current: bool,
+}
+
+#[derive(Serialize)]
+pub struct LogoutAllResponse {
+    pub revoked: u64,
+}
+
+/// POST /logout — завершить текущую сессию
+pub async fn logout(
+    Extension(pool): Extension<PgPool>,
+    AuthClaims(claims): AuthClaims,
+) -> impl IntoResponse {
+    match revoke(&pool, claims.sub, claims.sid).await {
+        Ok(_) => {
+            info!(user = %claims.sub, session = %claims.sid, "user logged out");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => {
+            error!("db delete session error: {:?}", e);
+            err_json(StatusCode::INTERNAL_SERVER_ERROR, "db error", Some(e.to_string())).into_response()
+        }
+    }
+}
+
+/// POST /logout/all — завершить все сессии пользователя, включая текущую
+pub async fn logout_all(
+    Extension(pool): Extension<PgPool>,
+    AuthClaims(claims): AuthClaims,
+) -> impl IntoResponse {
+    let res = sqlx::query!(
+        r#"
+        DELETE FROM sessions
+        WHERE user_id = $1
+        "#,
+        claims.sub
+    )
+    .execute(&pool)
+    .await;
+
+    match res {
+        Ok(done) => {
+            info!(user = %claims.sub, revoked = done.rows_affected(), "user logged out everywhere");
+            (StatusCode::OK, Json(LogoutAllResponse { revoked: done.rows_affected() })).into_response()
+        }
+        Err(e) => {
+            error!("db delete sessions error: {:?}", e);
+            err_json(StatusCode::INTERNAL_SERVER_ERROR, "db error", Some(e.to_string())).into_response()
+        }
+    }
+}
+
+/// GET /sessions — активные сессии пользователя, недавно использованные первыми
+pub async fn list_sessions(
+    Extension(pool): Extension<PgPool>,
+    AuthClaims(claims): AuthClaims,
+) -> impl IntoResponse {
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, user_agent, ip_address, created_at, last_used_at, expires_at
+        FROM sessions
+        WHERE user_id = $1 AND expires_at > NOW()
+        ORDER BY last_used_at DESC
+        "#,
+        claims.sub
+    )
+    .fetch_all(&pool)
+    .await;
+
+    match rows {
+        Ok(rows) => {
+            let sessions: Vec<SessionInfo> = rows
+                .into_iter()
+                .map(|r| SessionInfo {
+                    current: r.id == claims.sid,
+                    id: r.id,
+                    user_agent: r.user_agent,
+                    ip_address: r.ip_address,
+                    created_at: r.created_at,
+                    last_used_at: r.last_used_at,
+                    expires_at: r.expires_at,
+                })
+                .collect();
+            (StatusCode::OK, Json(sessions)).into_response()
+        }
+        Err(e) => {
+            error!("db list sessions error: {:?}", e);
+            err_json(StatusCode::INTERNAL_SERVER_ERROR, "db error", Some(e.to_string())).into_response()
+        }
+    }
+}
+
+/// DELETE /sessions/:id — завершить одну из своих сессий (например, на потерянном устройстве)
+pub async fn revoke_session(
+    Extension(pool): Extension<PgPool>,
+    AuthClaims(claims): AuthClaims,
+    Path(session_id): Path<Uuid>,
+) -> impl IntoResponse {
+    match revoke(&pool, claims.sub, session_id).await {
+        Ok(true) => {
+            info!(user = %claims.sub, session = %session_id, "session revoked");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => err_json(StatusCode::NOT_FOUND, "session not found", None).into_response(),
+        Err(e) => {
+            error!("db delete session error: {:?}", e);
+            err_json(StatusCode::INTERNAL_SERVER_ERROR, "db error", Some(e.to_string())).into_response()
+        }
+    }
+}
+
+async fn revoke(pool: &PgPool, user_id: Uuid, session_id: Uuid) -> Result<bool, sqlx::Error> {
+    let done = sqlx::query!(
+        r#"
+        DELETE FROM sessions
+        WHERE id = $1 AND user_id = $2
+        "#,
+        session_id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(done.rows_affected() > 0)
+}
@@ -0,0 +1,41 @@
+use axum::{Router, routing::{post, get, delete}, extract::Extension};
+use sqlx::PgPool;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::mail::Mailer;
+use crate::utils::keys::JwtKeys;
+
+pub mod config;
+pub mod handlers;
+pub mod mail;
+pub mod utils;
+pub mod models;
+
+/// Маршруты сервиса. CORS и прочие внешние слои навешивает `main`
+pub fn app(pool: PgPool, cfg: Config, keys: Arc<JwtKeys>, mailer: Arc<dyn Mailer>) -> Router {
+    Router::new()
+        .route("/register", post(handlers::auth::register))
+        .route("/login", post(handlers::auth::login))
+        .route("/login/mfa", post(handlers::mfa::login_mfa))
+        .route("/refresh", post(handlers::auth::refresh_token))
+        .route("/me", get(handlers::auth::me))
+        .route("/users/search", get(handlers::auth::search_users))
+        .route("/logout", post(handlers::sessions::logout))
+        .route("/logout/all", post(handlers::sessions::logout_all))
+        .route("/sessions", get(handlers::sessions::list_sessions))
+        .route("/sessions/:id", delete(handlers::sessions::revoke_session))
+        .route("/mfa/totp/setup", post(handlers::mfa::setup_totp))
+        .route("/mfa/totp/confirm", post(handlers::mfa::confirm_totp))
+        .route("/mfa/totp/disable", post(handlers::mfa::disable_totp))
+        .route("/mfa/recovery-codes", post(handlers::mfa::regenerate_recovery_codes))
+        .route("/email/verify", post(handlers::email::verify_email))
+        .route("/email/verify/resend", post(handlers::email::resend_verification))
+        .route("/password/forgot", post(handlers::password::forgot_password))
+        .route("/password/reset", post(handlers::password::reset_password))
+        .route("/.well-known/jwks.json", get(handlers::jwks::jwks))
+        .layer(Extension(pool))
+        .layer(Extension(cfg))
+        .layer(Extension(keys))
+        .layer(Extension(mailer))
+}
@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Result, Context};
+use axum::async_trait;
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::config::{Config, MailTransport};
+
+#[derive(Debug, Clone)]
+pub struct MailMessage {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Отправка писем. Реализация выбирается в конфигурации (`MAIL_TRANSPORT`)
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, message: MailMessage) -> Result<()>;
+}
+
+/// Пишет письма в лог — для локальной разработки
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, message: MailMessage) -> Result<()> {
+        tracing::info!(to = %message.to, subject = %message.subject, "mail:\n{}", message.body);
+        Ok(())
+    }
+}
+
+/// Складывает письма файлами `.eml` в каталог — для тестов и стендов без почты
+pub struct FileMailer {
+    dir: PathBuf,
+    from: String,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl Into<PathBuf>, from: &str) -> Self {
+        Self { dir: dir.into(), from: from.to_string() }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, message: MailMessage) -> Result<()> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("Failed to create mail dir {}", self.dir.display()))?;
+
+        let path = self.dir.join(format!("{}-{}.eml", Utc::now().format("%Y%m%dT%H%M%S"), Uuid::new_v4()));
+        let contents = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\n\r\n{}\r\n",
+            self.from,
+            message.to,
+            message.subject,
+            Utc::now().to_rfc2822(),
+            message.body
+        );
+
+        tokio::fs::write(&path, contents)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        tracing::debug!(to = %message.to, path = %path.display(), "mail written");
+        Ok(())
+    }
+}
+
+pub fn from_config(cfg: &Config) -> Arc<dyn Mailer> {
+    match &cfg.mail_transport {
+        MailTransport::Log => Arc::new(LogMailer),
+        MailTransport::File(dir) => Arc::new(FileMailer::new(dir, &cfg.mail_from)),
+    }
+}
@@ -1,20 +1,13 @@
-use axum::{Router, routing::{post, get}};
 use tokio::net::TcpListener;
 use tracing_subscriber::{EnvFilter};
-use sqlx::PgPool;
-use redis::Client as RedisClient;
 use dotenvy::dotenv;
+use auth_service::config::Config;
+use auth_service::utils::keys::JwtKeys;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use anyhow::Context;
-use crate::config::Config;
 use tower_http::cors::{Any, CorsLayer};
 use http::header::HeaderValue;
 
-mod config;
-mod handlers;
-mod utils;
-mod models;
-
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv().ok();
@@ -32,32 +25,18 @@ async fn main() -> anyhow::Result<()> {
         .init();
 
     let cfg = Config::from_env()?;
-
-    // Клонируем URL'ы, чтобы не трогать cfg
-    let db_url = cfg.database_url.clone();
-    let redis_url = cfg.redis_url.clone();
     let bind_addr = cfg.bind_addr.clone();
 
-    let pool = PgPool::connect(&db_url).await
-        .context("Failed to connect to PostgreSQL")?;
+    let pool = cfg.init_pg_pool().await?;
+    let keys = Arc::new(JwtKeys::load_dir(&cfg.jwt_keys_dir, &cfg.jwt_key_id)?);
 
-    let redis_client = RedisClient::open(redis_url)
-        .context("Failed to connect to Redis")?;
+    let mailer = auth_service::mail::from_config(&cfg);
 
-    let app = Router::new()
-        .route("/register", post(handlers::auth::register))
-        .route("/login", post(handlers::auth::login))
-        .route("/refresh", post(handlers::auth::refresh_token))
-        .route("/me", get(handlers::auth::me))
-        .route("/users/search", axum::routing::get(handlers::auth::search_users))
-        .layer(cors)
-        .layer(axum::extract::Extension(Arc::new(redis_client)))
-        .layer(axum::extract::Extension(pool))
-        .layer(axum::extract::Extension(cfg));
+    let app = auth_service::app(pool, cfg, keys, mailer).layer(cors);
 
     let listener = TcpListener::bind(&bind_addr).await?;
     tracing::info!("Server listening on {}", bind_addr);
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -6,6 +6,7 @@ use chrono::{DateTime, Utc};
 pub struct User {
     pub id: Uuid,
     pub email: String,
+    pub email_verified_at: Option<DateTime<Utc>>,
     pub provider: String,
     pub provider_id: Option<String>,
     pub name: Option<String>,
@@ -15,10 +16,11 @@ pub struct User {
     pub updated_at: DateTime<Utc>,
 }
 
+/// Сессия входа — семейство refresh-токенов: при ротации меняется токен, `id` остаётся.
+/// Refresh-токен хранится только в виде хэша
 #[derive(Debug)]
 pub struct Session {
     pub id: Uuid,
     pub user_id: Uuid,
-    pub refresh_token: String,
     pub expires_at: DateTime<Utc>,
 }
\ No newline at end of file
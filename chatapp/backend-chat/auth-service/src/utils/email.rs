@@ -0,0 +1,36 @@
+/// Приводит адрес к виду, в котором он хранится в `users.email`: без пробелов по краям,
+/// в нижнем регистре. `None`, если адрес синтаксически неверен.
+///
+/// Проверка намеренно простая — `local@domain.tld` без пробелов и управляющих символов;
+/// что адрес существует, подтверждает только письмо
+pub fn normalize_email(raw: &str) -> Option<String> {
+    let email = raw.trim().to_lowercase();
+    if email.len() > 254 || email.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return None;
+    }
+
+    let (local, domain) = email.split_once('@')?;
+    if local.is_empty() || local.len() > 64 || domain.contains('@') {
+        return None;
+    }
+    if local.starts_with('.') || local.ends_with('.') || local.contains("..") {
+        return None;
+    }
+
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.len() < 2 {
+        return None;
+    }
+    let valid_labels = labels.iter().all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+    });
+    if !valid_labels {
+        return None;
+    }
+
+    Some(email)
+}
@@ -1,6 +1,7 @@
+use sha2::{Digest, Sha256};
 use argon2::{
     password_hash::{
-        rand_core::OsRng,
+        rand_core::{OsRng, RngCore},
         SaltString,
         Error as PasswordHashError,
     },
@@ -30,4 +31,34 @@ pub fn verify_password(hash: &str, password: &str) -> Result<bool, PasswordHashE
             Err(e)
         }
     }
-}
\ No newline at end of file
+}
+
+/// Хэш случайного токена (refresh, сброс пароля) для хранения в БД: медленный KDF не нужен
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Случайный токен для ссылок в письмах, 256 бит
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Код восстановления 2FA, `xxxxx-xxxxx` (50 бит). Хранится как пароль — через `hash_password`
+pub fn generate_recovery_code() -> String {
+    // 32 символа без похожих `l`/`1`, `o`/`0`: по 5 бит на символ без смещения
+    const ALPHABET: &[u8; 32] = b"abcdefghijkmnpqrstuvwxyz23456789";
+    let mut bytes = [0u8; 10];
+    OsRng.fill_bytes(&mut bytes);
+    let chars: String = bytes.iter().map(|b| ALPHABET[(*b & 31) as usize] as char).collect();
+    format!("{}-{}", &chars[..5], &chars[5..])
+}
+
+/// Код восстановления в том виде, в каком он хэшируется: без дефисов, пробелов и регистра
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
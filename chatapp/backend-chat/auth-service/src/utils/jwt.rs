@@ -1,32 +1,60 @@
 use anyhow::{Result, Context};
-use jsonwebtoken::{EncodingKey, DecodingKey, Header, Validation, encode, decode, TokenData};
+use jsonwebtoken::{Header, Validation, Algorithm, encode, decode, decode_header};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 use chrono::{Utc};
 
+use crate::config::Config;
+use crate::utils::keys::JwtKeys;
+
+/// Контракт access-токена, общий с chat-service (зеркало `chat_news/src/auth.rs`):
+/// EdDSA, `kid` в заголовке (ключ из `/.well-known/jwks.json`), `iss`/`aud` из конфигурации,
+/// `sid` — строка в таблице `sessions`
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
-    pub sub: String, // store UUID as string
+    pub iss: String,
+    pub aud: String,
+    pub sub: Uuid,
+    pub sid: Uuid,
     pub exp: i64,
     pub iat: i64,
 }
 
-pub fn create_access_token(secret: &str, user_id: Uuid, ttl_seconds: i64) -> Result<(String, i64)> {
+pub fn create_access_token(cfg: &Config, keys: &JwtKeys, user_id: Uuid, session_id: Uuid) -> Result<(String, i64)> {
     let now = Utc::now().timestamp();
-    let exp = now + ttl_seconds;
+    let exp = now + cfg.access_token_ttl.as_secs() as i64;
     let claims = Claims {
-        sub: user_id.to_string(),
+        iss: cfg.jwt_issuer.clone(),
+        aud: cfg.jwt_audience.clone(),
+        sub: user_id,
+        sid: session_id,
         exp,
         iat: now,
     };
-    let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes()))
+
+    let (kid, key) = keys.active();
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(kid.to_string());
+
+    let token = encode(&header, &claims, key)
         .context("failed to encode jwt")?;
     Ok((token, exp))
 }
 
-pub fn decode_token(secret: &str, token: &str) -> Result<TokenData<Claims>> {
-    let validation = Validation::default();
-    let data = decode::<Claims>(token, &DecodingKey::from_secret(secret.as_bytes()), &validation)
+pub fn decode_token(cfg: &Config, keys: &JwtKeys, token: &str) -> Result<Claims> {
+    let header = decode_header(token).context("failed to decode jwt header")?;
+    let key = header
+        .kid
+        .as_deref()
+        .and_then(|kid| keys.decoding_key(kid))
+        .context("unknown jwt key id")?;
+
+    let mut validation = Validation::new(Algorithm::EdDSA);
+    validation.set_issuer(&[&cfg.jwt_issuer]);
+    validation.set_audience(&[&cfg.jwt_audience]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+    let data = decode::<Claims>(token, key, &validation)
         .context("failed to decode jwt")?;
-    Ok(data)
-}
\ No newline at end of file
+    Ok(data.claims)
+}
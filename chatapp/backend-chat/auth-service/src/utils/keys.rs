@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Result, Context};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, OctetKeyPairParameters,
+    OctetKeyPairType, PublicKeyUse,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+
+struct SigningKey {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    jwk: Jwk,
+}
+
+/// Ключи подписи access-токенов (Ed25519, PKCS#8).
+/// Подписывает только активный ключ, публикуются и принимаются все загруженные —
+/// так новый ключ можно выложить в JWKS заранее, а старый держать до истечения его токенов
+pub struct JwtKeys {
+    active_kid: String,
+    keys: HashMap<String, SigningKey>,
+}
+
+impl JwtKeys {
+    /// Читает каталог с ключами: каждый файл `<kid>.pem` — приватный ключ
+    /// (`openssl genpkey -algorithm ed25519 -out <kid>.pem`)
+    pub fn load_dir(dir: impl AsRef<Path>, active_kid: &str) -> Result<Self> {
+        let dir = dir.as_ref();
+        let mut keys = Vec::new();
+
+        for entry in std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read JWT keys dir {}", dir.display()))?
+        {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("pem") {
+                continue;
+            }
+            let kid = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .with_context(|| format!("Invalid key file name {}", path.display()))?
+                .to_string();
+
+            let pem = std::fs::read(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let der = pem::parse(pem)
+                .with_context(|| format!("Invalid PEM in {}", path.display()))?
+                .contents;
+            keys.push((kid, der));
+        }
+
+        Self::from_pkcs8(keys, active_kid)
+    }
+
+    pub fn from_pkcs8(keys: Vec<(String, Vec<u8>)>, active_kid: &str) -> Result<Self> {
+        let mut loaded = HashMap::new();
+
+        for (kid, der) in keys {
+            let pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der)
+                .map_err(|e| anyhow::anyhow!("key '{}' is not an Ed25519 PKCS#8 key: {}", kid, e))?;
+            let x = URL_SAFE_NO_PAD.encode(pair.public_key().as_ref());
+
+            let jwk = Jwk {
+                common: CommonParameters {
+                    public_key_use: Some(PublicKeyUse::Signature),
+                    algorithm: Some(Algorithm::EdDSA),
+                    key_id: Some(kid.clone()),
+                    ..Default::default()
+                },
+                algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: x.clone(),
+                }),
+            };
+
+            let key = SigningKey {
+                encoding: EncodingKey::from_ed_der(&der),
+                decoding: DecodingKey::from_ed_components(&x).context("invalid public key")?,
+                jwk,
+            };
+            loaded.insert(kid, key);
+        }
+
+        if !loaded.contains_key(active_kid) {
+            anyhow::bail!("active JWT key '{}' is not loaded", active_kid);
+        }
+
+        tracing::info!(active = %active_kid, total = loaded.len(), "JWT signing keys loaded");
+
+        Ok(Self { active_kid: active_kid.to_string(), keys: loaded })
+    }
+
+    /// Ключ, которым подписываются новые токены
+    pub fn active(&self) -> (&str, &EncodingKey) {
+        (&self.active_kid, &self.keys[&self.active_kid].encoding)
+    }
+
+    pub fn decoding_key(&self, kid: &str) -> Option<&DecodingKey> {
+        self.keys.get(kid).map(|k| &k.decoding)
+    }
+
+    /// Публичные ключи для `/.well-known/jwks.json`
+    pub fn jwks(&self) -> JwkSet {
+        let mut keys: Vec<Jwk> = self.keys.values().map(|k| k.jwk.clone()).collect();
+        keys.sort_by(|a, b| a.common.key_id.cmp(&b.common.key_id));
+        JwkSet { keys }
+    }
+}
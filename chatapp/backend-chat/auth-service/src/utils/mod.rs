@@ -1,2 +1,5 @@
+pub mod email;
 pub mod hash;
-pub mod jwt;
\ No newline at end of file
+pub mod jwt;
+pub mod keys;
+pub mod totp;
\ No newline at end of file
@@ -0,0 +1,68 @@
+use data_encoding::BASE32_NOPAD;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+
+// TOTP (RFC 6238): HMAC-SHA1, шаг 30 секунд, 6 цифр — параметры, которые понимают
+// все приложения-аутентификаторы
+pub const STEP_SECONDS: u64 = 30;
+pub const DIGITS: u32 = 6;
+/// Допустимое расхождение часов клиента, в шагах
+const SKEW_STEPS: u64 = 1;
+
+/// Новый секрет, 160 бит (рекомендация RFC 4226), в base32 — так его вводят в приложение вручную
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    SystemRandom::new().fill(&mut bytes).expect("system rng");
+    BASE32_NOPAD.encode(&bytes)
+}
+
+/// `otpauth://` URI для QR-кода
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account),
+        secret,
+        percent_encode(issuer),
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+/// Код для шага `step` (число 30-секундных интервалов от эпохи)
+pub fn code_at(secret: &[u8], step: u64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = hmac::sign(&key, &step.to_be_bytes());
+    let digest = tag.as_ref();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([digest[offset], digest[offset + 1], digest[offset + 2], digest[offset + 3]])
+        & 0x7fff_ffff;
+    binary % 10u32.pow(DIGITS)
+}
+
+/// Проверяет код на момент `unix_time` с допуском в один шаг. Возвращает шаг совпавшего кода:
+/// принимать стоит только шаги новее последнего использованного, иначе код можно повторить
+pub fn verify(secret_b32: &str, code: &str, unix_time: u64) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let secret = BASE32_NOPAD.decode(secret_b32.as_bytes()).ok()?;
+
+    let current = unix_time / STEP_SECONDS;
+    (current.saturating_sub(SKEW_STEPS)..=current + SKEW_STEPS)
+        .rev()
+        .find(|&step| code_at(&secret, step) == code)
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
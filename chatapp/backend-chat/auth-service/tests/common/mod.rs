@@ -0,0 +1,181 @@
+//! Общее для интеграционных тестов: конфигурация, ключи и запросы к роутеру без сети
+#![allow(dead_code)]
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use auth_service::config::{Config, MailTransport};
+use auth_service::mail::{MailMessage, Mailer};
+use auth_service::utils::keys::JwtKeys;
+use axum::{
+    async_trait,
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use http_body_util::BodyExt;
+use ring::{rand::SystemRandom, signature::Ed25519KeyPair};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+pub fn test_config(database_url: String) -> Config {
+    Config {
+        database_url,
+        jwt_issuer: std::env::var("JWT_ISSUER").unwrap_or_else(|_| "chatapp-auth".into()),
+        jwt_audience: std::env::var("JWT_AUDIENCE").unwrap_or_else(|_| "chatapp".into()),
+        jwt_keys_dir: std::env::var("JWT_KEYS_DIR").unwrap_or_default(),
+        jwt_key_id: std::env::var("JWT_KEY_ID").unwrap_or_else(|_| "test-key".into()),
+        access_token_ttl: Duration::from_secs(900),
+        refresh_token_ttl: Duration::from_secs(3600),
+        password_reset_ttl: Duration::from_secs(3600),
+        email_verification_ttl: Duration::from_secs(3600),
+        email_verification_resend_cooldown: Duration::from_secs(60),
+        mfa_challenge_ttl: Duration::from_secs(300),
+        require_verified_email: false,
+        totp_issuer: "ChatApp".into(),
+        mail_transport: MailTransport::Log,
+        mail_from: "no-reply@example.com".into(),
+        app_base_url: "http://localhost:8080".into(),
+        bind_addr: "127.0.0.1:0".into(),
+        google_client_id: None,
+        google_client_secret: None,
+        yandex_client_id: None,
+        yandex_client_secret: None,
+        keycloak_url: None,
+        keycloak_realm: None,
+    }
+}
+
+pub fn test_keys(cfg: &Config) -> Arc<JwtKeys> {
+    let keys = if cfg.jwt_keys_dir.is_empty() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        JwtKeys::from_pkcs8(vec![(cfg.jwt_key_id.clone(), pkcs8.as_ref().to_vec())], &cfg.jwt_key_id)
+    } else {
+        JwtKeys::load_dir(&cfg.jwt_keys_dir, &cfg.jwt_key_id)
+    };
+    Arc::new(keys.unwrap())
+}
+
+/// Складывает письма в память, чтобы тест мог достать из них токен
+#[derive(Default)]
+pub struct CapturingMailer {
+    sent: Mutex<Vec<MailMessage>>,
+}
+
+#[async_trait]
+impl Mailer for CapturingMailer {
+    async fn send(&self, message: MailMessage) -> anyhow::Result<()> {
+        self.sent.lock().unwrap().push(message);
+        Ok(())
+    }
+}
+
+impl CapturingMailer {
+    /// Отправленные на адрес письма с данной темой, по порядку
+    pub fn sent(&self, to: &str, subject: &str) -> Vec<MailMessage> {
+        self.sent
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|m| m.to == to && m.subject == subject)
+            .cloned()
+            .collect()
+    }
+
+    /// Ждёт `count`-е письмо с темой на адрес: обработчики отправляют почту в фоне
+    pub async fn wait_for_nth(&self, to: &str, subject: &str, count: usize) -> MailMessage {
+        for _ in 0..50 {
+            if let Some(message) = self.sent(to, subject).get(count - 1) {
+                return message.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("mail #{} {:?} was not sent to {}", count, subject, to);
+    }
+
+    pub async fn wait_for(&self, to: &str, subject: &str) -> MailMessage {
+        self.wait_for_nth(to, subject, 1).await
+    }
+}
+
+/// Токен из ссылки в письме (`...?token=<токен>`)
+pub fn token_from(body: &str) -> String {
+    let (_, rest) = body.split_once("token=").expect("link with token in mail");
+    rest.split_whitespace().next().unwrap().to_string()
+}
+
+pub struct TestApp {
+    pub pool: PgPool,
+    pub cfg: Config,
+    pub keys: Arc<JwtKeys>,
+    pub mailer: Arc<CapturingMailer>,
+    pub router: Router,
+}
+
+/// Поднимает сервис на базе из `TEST_DATABASE_URL` с применёнными миграциями
+pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// То же, с изменённой конфигурацией
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Config)) -> TestApp {
+    let database_url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+    sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+
+    let mut cfg = test_config(database_url);
+    configure(&mut cfg);
+    let keys = test_keys(&cfg);
+    let mailer = Arc::new(CapturingMailer::default());
+    let router = auth_service::app(pool.clone(), cfg.clone(), keys.clone(), mailer.clone());
+
+    TestApp { pool, cfg, keys, mailer, router }
+}
+
+impl TestApp {
+    pub async fn request(&self, method: Method, uri: &str, token: Option<&str>, body: Option<Value>) -> (StatusCode, Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .unwrap();
+
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    pub async fn get_json(&self, uri: &str) -> (StatusCode, Value) {
+        self.request(Method::GET, uri, None, None).await
+    }
+
+    pub async fn post_json(&self, uri: &str, body: Value) -> (StatusCode, Value) {
+        self.request(Method::POST, uri, None, Some(body)).await
+    }
+
+    /// Регистрирует нового пользователя, возвращает email и пароль
+    pub async fn register(&self) -> (String, String) {
+        let email = format!("test-{}@example.com", Uuid::new_v4());
+        let password = "correct horse battery staple".to_string();
+
+        let (status, body) = self.post_json("/register", json!({ "email": email, "password": password })).await;
+        assert_eq!(status, StatusCode::CREATED, "register failed: {}", body);
+        (email, password)
+    }
+
+    /// Ответ `/login`
+    pub async fn login(&self, email: &str, password: &str) -> Value {
+        let (status, body) = self.post_json("/login", json!({ "email": email, "password": password })).await;
+        assert_eq!(status, StatusCode::OK, "login failed: {}", body);
+        body
+    }
+}
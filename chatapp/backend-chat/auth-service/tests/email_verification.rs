@@ -0,0 +1,120 @@
+//! Подтверждение адреса. Нужен Postgres: `TEST_DATABASE_URL=postgres://... cargo test -- --ignored`
+
+mod common;
+
+use auth_service::utils::email::normalize_email;
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+use uuid::Uuid;
+
+const VERIFY_SUBJECT: &str = "Подтверждение адреса";
+
+#[test]
+fn emails_are_normalized_and_validated() {
+    assert_eq!(normalize_email("  Alice@Example.COM \n").as_deref(), Some("alice@example.com"));
+    assert_eq!(normalize_email("first.last+tag@mail.example.ru").as_deref(), Some("first.last+tag@mail.example.ru"));
+
+    for invalid in ["", "alice", "@example.com", "alice@", "alice@localhost", "a b@example.com",
+        "alice@@example.com", "alice@example..com", ".alice@example.com", "alice@-example.com"] {
+        assert_eq!(normalize_email(invalid), None, "{:?} accepted", invalid);
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_DATABASE_URL"]
+async fn registration_normalizes_email() {
+    let app = common::spawn_app().await;
+    let email = format!("test-{}@example.com", Uuid::new_v4());
+    let password = "correct horse battery staple";
+
+    let (status, _) = app
+        .post_json("/register", json!({ "email": format!("  {}  ", email.to_uppercase()), "password": password }))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // Тот же адрес в другом регистре — дубликат
+    let (status, _) = app.post_json("/register", json!({ "email": email, "password": password })).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, _) = app.post_json("/register", json!({ "email": "not an email", "password": password })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let tokens = app.login(&email.to_uppercase(), password).await;
+    let (_, me) = app
+        .request(Method::GET, "/me", tokens["access_token"].as_str(), None)
+        .await;
+    assert_eq!(me["email"], json!(email));
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_DATABASE_URL"]
+async fn login_requires_verified_email_when_enabled() {
+    let app = common::spawn_app_with(|cfg| cfg.require_verified_email = true).await;
+    let (email, password) = app.register().await;
+
+    let (status, body) = app.post_json("/login", json!({ "email": email, "password": password })).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"], json!("email not verified"));
+
+    // Неверный пароль не раскрывает статус адреса
+    let (status, _) = app.post_json("/login", json!({ "email": email, "password": "wrong password" })).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let mail = app.mailer.wait_for(&email, VERIFY_SUBJECT).await;
+    assert!(mail.body.contains(&format!("{}/verify-email?token=", app.cfg.app_base_url)));
+    let token = common::token_from(&mail.body);
+
+    let (status, _) = app.post_json("/email/verify", json!({ "token": token })).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let tokens = app.login(&email, &password).await;
+    let (_, me) = app
+        .request(Method::GET, "/me", tokens["access_token"].as_str(), None)
+        .await;
+    assert!(me["email_verified_at"].is_string());
+
+    // Токен одноразовый
+    let (status, _) = app.post_json("/email/verify", json!({ "token": token })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_DATABASE_URL"]
+async fn resend_replaces_verification_token() {
+    let app = common::spawn_app_with(|cfg| cfg.email_verification_resend_cooldown = std::time::Duration::ZERO).await;
+    let (email, _) = app.register().await;
+    let first = common::token_from(&app.mailer.wait_for(&email, VERIFY_SUBJECT).await.body);
+
+    let (status, _) = app.post_json("/email/verify/resend", json!({ "email": email })).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let second = common::token_from(&app.mailer.wait_for_nth(&email, VERIFY_SUBJECT, 2).await.body);
+    assert_ne!(first, second);
+
+    let (status, _) = app.post_json("/email/verify", json!({ "token": first })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = app.post_json("/email/verify", json!({ "token": second })).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    // Подтверждённому адресу письмо больше не шлём
+    app.post_json("/email/verify/resend", json!({ "email": email })).await;
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert_eq!(app.mailer.sent(&email, VERIFY_SUBJECT).len(), 2);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_DATABASE_URL"]
+async fn resend_respects_cooldown() {
+    let app = common::spawn_app().await;
+    let (email, _) = app.register().await;
+    let first = common::token_from(&app.mailer.wait_for(&email, VERIFY_SUBJECT).await.body);
+
+    // Сразу после регистрации: ответ тот же, но письма нет и ссылка из первого письма жива
+    let (status, _) = app.post_json("/email/verify/resend", json!({ "email": email })).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert_eq!(app.mailer.sent(&email, VERIFY_SUBJECT).len(), 1);
+
+    let (status, _) = app.post_json("/email/verify", json!({ "token": first })).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
@@ -0,0 +1,60 @@
+//! Ротация ключей подписи: новый ключ подписывает, старый остаётся в JWKS и принимается,
+//! пока не истекут выпущенные им токены
+
+mod common;
+
+use auth_service::utils::jwt::{create_access_token, decode_token};
+use auth_service::utils::keys::JwtKeys;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use ring::{rand::SystemRandom, signature::Ed25519KeyPair};
+use uuid::Uuid;
+
+fn generate_key() -> Vec<u8> {
+    Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap().as_ref().to_vec()
+}
+
+#[test]
+fn rotated_keys_stay_published_and_verifiable() {
+    let cfg = common::test_config(String::new());
+    let old_key = generate_key();
+    let new_key = generate_key();
+
+    let before = JwtKeys::from_pkcs8(vec![("2026-01".into(), old_key.clone())], "2026-01").unwrap();
+    let (old_token, _) = create_access_token(&cfg, &before, Uuid::new_v4(), Uuid::new_v4()).unwrap();
+
+    let after = JwtKeys::from_pkcs8(
+        vec![("2026-01".into(), old_key), ("2026-02".into(), new_key)],
+        "2026-02",
+    )
+    .unwrap();
+    let (new_token, _) = create_access_token(&cfg, &after, Uuid::new_v4(), Uuid::new_v4()).unwrap();
+
+    let header = jsonwebtoken::decode_header(&new_token).unwrap();
+    assert_eq!(header.kid.as_deref(), Some("2026-02"));
+
+    // Оба токена принимаются и самим сервисом, и по опубликованному JWKS
+    let jwks = after.jwks();
+    assert_eq!(jwks.keys.len(), 2);
+
+    let mut validation = Validation::new(Algorithm::EdDSA);
+    validation.set_issuer(&[&cfg.jwt_issuer]);
+    validation.set_audience(&[&cfg.jwt_audience]);
+
+    for token in [&old_token, &new_token] {
+        decode_token(&cfg, &after, token).unwrap();
+
+        let kid = jsonwebtoken::decode_header(token).unwrap().kid.unwrap();
+        let key = DecodingKey::from_jwk(jwks.find(&kid).unwrap()).unwrap();
+        jsonwebtoken::decode::<serde_json::Value>(token, &key, &validation).unwrap();
+    }
+
+    // Токен с неизвестным `kid` отклоняется
+    let foreign = JwtKeys::from_pkcs8(vec![("other".into(), generate_key())], "other").unwrap();
+    let (foreign_token, _) = create_access_token(&cfg, &foreign, Uuid::new_v4(), Uuid::new_v4()).unwrap();
+    assert!(decode_token(&cfg, &after, &foreign_token).is_err());
+}
+
+#[test]
+fn active_key_must_be_loaded() {
+    assert!(JwtKeys::from_pkcs8(vec![("2026-01".into(), generate_key())], "2026-02").is_err());
+}
@@ -0,0 +1,119 @@
+//! Вход через auth-service и подключение к `/ws` с выданным токеном.
+//!
+//! Нужен Postgres: `TEST_DATABASE_URL=postgres://... cargo test -- --ignored`.
+//! Если задан `CHAT_WS_URL` (например, `ws://127.0.0.1:8081/ws`), токен проверяет настоящий chat-service:
+//! ключи берутся из `JWT_KEYS_DIR`/`JWT_KEY_ID`, которые публикует auth-service из его `JWKS_URL`.
+//! Иначе ключ генерируется, а `/ws` делает те же проверки контракта (подпись по JWKS, `kid`, `iss`, `aud`,
+//! живая сессия `sid`)
+
+mod common;
+
+use std::sync::Arc;
+
+use auth_service::config::Config;
+use auth_service::handlers::auth::AuthClaims;
+use auth_service::utils::keys::JwtKeys;
+use axum::{
+    extract::{ws::WebSocketUpgrade, Extension},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use futures_util::StreamExt;
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest, http::HeaderValue};
+
+/// `/ws` с аутентификацией по заголовку, как у chat-service
+async fn spawn_ws_endpoint(pool: PgPool, cfg: Config, keys: Arc<JwtKeys>) -> String {
+    async fn ws(ws: WebSocketUpgrade, AuthClaims(_claims): AuthClaims) -> impl IntoResponse {
+        ws.on_upgrade(|mut socket| async move {
+            let _ = socket.recv().await;
+        })
+    }
+
+    let app = Router::new()
+        .route("/ws", get(ws))
+        .layer(Extension(pool))
+        .layer(Extension(cfg))
+        .layer(Extension(keys));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("ws://{}/ws", addr)
+}
+
+async fn open_ws(url: &str, access_token: &str) -> Result<(), tungstenite::Error> {
+    let mut request = url.into_client_request()?;
+    request.headers_mut().insert(
+        header::AUTHORIZATION.as_str(),
+        HeaderValue::from_str(&format!("Bearer {}", access_token)).unwrap(),
+    );
+
+    let (mut socket, response) = tokio_tungstenite::connect_async(request).await?;
+    assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS.as_u16());
+    socket.close(None).await?;
+    while socket.next().await.is_some() {}
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_DATABASE_URL"]
+async fn login_then_open_ws_with_issued_token() {
+    let app = common::spawn_app().await;
+    let cfg = &app.cfg;
+
+    let (email, password) = app.register().await;
+    let login = app.login(&email, &password).await;
+    let access_token = login["access_token"].as_str().unwrap().to_string();
+    let refresh_token = login["refresh_token"].as_str().unwrap().to_string();
+
+    // Токен проверяется публичным ключом из JWKS — так его проверяет chat-service
+    let header = jsonwebtoken::decode_header(&access_token).unwrap();
+    assert_eq!(header.alg, Algorithm::EdDSA);
+    assert_eq!(header.kid.as_deref(), Some(cfg.jwt_key_id.as_str()));
+
+    let (status, jwks) = app.get_json("/.well-known/jwks.json").await;
+    assert_eq!(status, StatusCode::OK);
+    let jwks: JwkSet = serde_json::from_value(jwks).unwrap();
+    let jwk = jwks.find(&cfg.jwt_key_id).expect("active key is published");
+
+    let mut validation = Validation::new(Algorithm::EdDSA);
+    validation.set_issuer(&[&cfg.jwt_issuer]);
+    validation.set_audience(&[&cfg.jwt_audience]);
+    jsonwebtoken::decode::<Value>(&access_token, &DecodingKey::from_jwk(jwk).unwrap(), &validation)
+        .expect("token verifies against JWKS");
+
+    // Сессия лежит в Postgres, где её видит chat-service
+    let sessions: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sessions s JOIN users u ON u.id = s.user_id WHERE u.email = $1")
+        .bind(&email)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(sessions, 1);
+
+    let ws_url = match std::env::var("CHAT_WS_URL") {
+        Ok(url) => url,
+        Err(_) => spawn_ws_endpoint(app.pool.clone(), cfg.clone(), app.keys.clone()).await,
+    };
+
+    open_ws(&ws_url, &access_token).await.expect("ws with access token from /login");
+
+    // Обновление токена сохраняет сессию — новый токен тоже открывает /ws
+    let (status, refreshed) = app.post_json("/refresh", json!({ "refresh_token": refresh_token })).await;
+    assert_eq!(status, StatusCode::OK, "refresh failed: {}", refreshed);
+    let refreshed_token = refreshed["access_token"].as_str().unwrap().to_string();
+    open_ws(&ws_url, &refreshed_token).await.expect("ws with access token from /refresh");
+
+    // После выхода токен сессии больше не принимается
+    let (status, _) = app.request(axum::http::Method::POST, "/logout", Some(&refreshed_token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    match open_ws(&ws_url, &refreshed_token).await {
+        Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), StatusCode::UNAUTHORIZED.as_u16()),
+        other => panic!("expected 401 for revoked session, got {:?}", other.map(|_| ())),
+    }
+}
@@ -0,0 +1,217 @@
+//! TOTP 2FA: подключение, вход в два шага, коды восстановления. Для тестов с базой нужен
+//! Postgres: `TEST_DATABASE_URL=postgres://... cargo test -- --ignored`
+
+mod common;
+
+use auth_service::utils::totp;
+use axum::http::{Method, StatusCode};
+use data_encoding::BASE32_NOPAD;
+use serde_json::{json, Value};
+
+fn code(secret: &str, step: u64) -> String {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).unwrap();
+    format!("{:06}", totp::code_at(&secret, step))
+}
+
+fn current_step() -> u64 {
+    chrono::Utc::now().timestamp() as u64 / totp::STEP_SECONDS
+}
+
+#[test]
+fn totp_matches_rfc6238_vectors() {
+    // RFC 6238, приложение B (SHA1), последние 6 цифр
+    let secret = BASE32_NOPAD.encode(b"12345678901234567890");
+    for (time, expected) in [
+        (59u64, "287082"),
+        (1_111_111_109, "081804"),
+        (1_111_111_111, "050471"),
+        (1_234_567_890, "005924"),
+        (2_000_000_000, "279037"),
+        (20_000_000_000, "353130"),
+    ] {
+        assert_eq!(code(&secret, time / totp::STEP_SECONDS), expected);
+        assert_eq!(totp::verify(&secret, expected, time), Some(time / totp::STEP_SECONDS));
+    }
+
+    // Допуск в один шаг в обе стороны, не больше
+    assert!(totp::verify(&secret, "287082", 59 + 30).is_some());
+    assert!(totp::verify(&secret, "287082", 59 + 60).is_none());
+    assert!(totp::verify(&secret, "28708", 59).is_none());
+}
+
+/// Вход с паролем, когда включена 2FA: токен второго шага
+async fn login_challenge(app: &common::TestApp, email: &str, password: &str) -> String {
+    let body = app.login(email, password).await;
+    assert_eq!(body["mfa_required"], json!(true), "expected mfa challenge: {}", body);
+    assert!(body.get("access_token").is_none());
+    body["mfa_token"].as_str().unwrap().to_string()
+}
+
+async fn login_mfa(app: &common::TestApp, mfa_token: &str, code: &str) -> (StatusCode, Value) {
+    app.post_json("/login/mfa", json!({ "mfa_token": mfa_token, "code": code })).await
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_DATABASE_URL"]
+async fn totp_enrollment_and_two_step_login() {
+    let app = common::spawn_app().await;
+    let (email, password) = app.register().await;
+    let other_device = app.login(&email, &password).await;
+    let tokens = app.login(&email, &password).await;
+    let access_token = tokens["access_token"].as_str().unwrap();
+
+    let (status, setup) = app.request(Method::POST, "/mfa/totp/setup", Some(access_token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let secret = setup["secret"].as_str().unwrap().to_string();
+    let uri = setup["otpauth_uri"].as_str().unwrap();
+    assert!(uri.starts_with("otpauth://totp/ChatApp:"));
+    assert!(uri.contains(&format!("secret={}", secret)));
+
+    // Пока код не подтверждён, 2FA не действует
+    let body = app.login(&email, &password).await;
+    assert!(body["access_token"].is_string());
+
+    let step = current_step();
+    let (status, _) = app
+        .request(Method::POST, "/mfa/totp/confirm", Some(access_token), Some(json!({ "code": "000000" })))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, confirmed) = app
+        .request(Method::POST, "/mfa/totp/confirm", Some(access_token), Some(json!({ "code": code(&secret, step) })))
+        .await;
+    assert_eq!(status, StatusCode::OK, "confirm failed: {}", confirmed);
+    assert_eq!(confirmed["recovery_codes"].as_array().unwrap().len(), 10);
+
+    // Сессии, открытые без второго фактора, завершены; текущая осталась
+    let (status, _) = app
+        .request(Method::GET, "/me", other_device["access_token"].as_str(), None)
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.request(Method::GET, "/me", Some(access_token), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mfa_token = login_challenge(&app, &email, &password).await;
+
+    // Уже принятый код повторно не принимается
+    let (status, _) = login_mfa(&app, &mfa_token, &code(&secret, step)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = login_mfa(&app, &mfa_token, &code(&secret, step + 1)).await;
+    assert_eq!(status, StatusCode::OK, "mfa login failed: {}", body);
+    assert!(body["access_token"].is_string());
+    let (status, _) = app.request(Method::GET, "/me", body["access_token"].as_str(), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Токен второго шага одноразовый
+    let (status, _) = login_mfa(&app, &mfa_token, &code(&secret, step + 1)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_DATABASE_URL"]
+async fn recovery_codes_and_disable() {
+    let app = common::spawn_app().await;
+    let (email, password) = app.register().await;
+    let tokens = app.login(&email, &password).await;
+    let access_token = tokens["access_token"].as_str().unwrap();
+
+    let (_, setup) = app.request(Method::POST, "/mfa/totp/setup", Some(access_token), None).await;
+    let secret = setup["secret"].as_str().unwrap().to_string();
+    let (_, confirmed) = app
+        .request(Method::POST, "/mfa/totp/confirm", Some(access_token), Some(json!({ "code": code(&secret, current_step()) })))
+        .await;
+    let recovery: Vec<String> = serde_json::from_value(confirmed["recovery_codes"].clone()).unwrap();
+
+    // Код восстановления вместо TOTP, регистр и дефис не важны; второй раз не подходит
+    let mfa_token = login_challenge(&app, &email, &password).await;
+    let (status, _) = login_mfa(&app, &mfa_token, &recovery[0].to_uppercase().replace('-', "")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mfa_token = login_challenge(&app, &email, &password).await;
+    let (status, _) = login_mfa(&app, &mfa_token, &recovery[0]).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // После пяти неверных кодов токен сгорает
+    for _ in 0..4 {
+        let (status, body) = login_mfa(&app, &mfa_token, "000000").await;
+        assert_eq!(body["error"], json!("invalid code"));
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+    let (_, body) = login_mfa(&app, &mfa_token, &recovery[1]).await;
+    assert_eq!(body["error"], json!("invalid or expired mfa token"));
+
+    // Новые коды — только с паролем и вторым фактором
+    let (status, _) = app
+        .request(Method::POST, "/mfa/recovery-codes", Some(access_token), Some(json!({ "password": "wrong password", "code": recovery[1] })))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, regenerated) = app
+        .request(Method::POST, "/mfa/recovery-codes", Some(access_token), Some(json!({ "password": password, "code": recovery[1] })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let fresh: Vec<String> = serde_json::from_value(regenerated["recovery_codes"].clone()).unwrap();
+    assert_eq!(fresh.len(), 10);
+
+    // Прежние коды больше не действуют
+    let (status, _) = app
+        .request(Method::POST, "/mfa/totp/disable", Some(access_token), Some(json!({ "password": password, "code": recovery[2] })))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = app
+        .request(Method::POST, "/mfa/totp/disable", Some(access_token), Some(json!({ "password": password, "code": fresh[0] })))
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let body = app.login(&email, &password).await;
+    assert!(body["access_token"].is_string());
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_DATABASE_URL"]
+async fn repeated_failures_lock_mfa_login_across_challenges() {
+    let app = common::spawn_app().await;
+    let (email, password) = app.register().await;
+    let tokens = app.login(&email, &password).await;
+    let access_token = tokens["access_token"].as_str().unwrap();
+
+    let (_, setup) = app.request(Method::POST, "/mfa/totp/setup", Some(access_token), None).await;
+    let secret = setup["secret"].as_str().unwrap().to_string();
+    let step = current_step();
+    let (status, _) = app
+        .request(Method::POST, "/mfa/totp/confirm", Some(access_token), Some(json!({ "code": code(&secret, step) })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Первый токен сгорает после пяти ошибок, но счётчик пользователя продолжает расти
+    let mfa_token = login_challenge(&app, &email, &password).await;
+    for _ in 0..5 {
+        let (status, _) = login_mfa(&app, &mfa_token, "000000").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    let mfa_token = login_challenge(&app, &email, &password).await;
+    for _ in 0..4 {
+        let (status, _) = login_mfa(&app, &mfa_token, "000000").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+    let (status, _) = login_mfa(&app, &mfa_token, "000000").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    // Во время блокировки не проходит и верный код
+    let mfa_token = login_challenge(&app, &email, &password).await;
+    let (status, body) = login_mfa(&app, &mfa_token, &code(&secret, step + 1)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body["error"], json!("too many failed attempts, try again later"));
+
+    sqlx::query("UPDATE users SET mfa_locked_until = NOW() - INTERVAL '1 second' WHERE email = $1")
+        .bind(&email)
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let (status, body) = login_mfa(&app, &mfa_token, &code(&secret, step + 1)).await;
+    assert_eq!(status, StatusCode::OK, "mfa login after lockout failed: {}", body);
+}
@@ -0,0 +1,87 @@
+//! Сброс пароля по ссылке из письма. Нужен Postgres: `TEST_DATABASE_URL=postgres://... cargo test -- --ignored`
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+use uuid::Uuid;
+
+const RESET_SUBJECT: &str = "Сброс пароля";
+
+#[tokio::test]
+#[ignore = "requires TEST_DATABASE_URL"]
+async fn reset_password_revokes_sessions() {
+    let app = common::spawn_app().await;
+    let (email, password) = app.register().await;
+    let tokens = app.login(&email, &password).await;
+    let access_token = tokens["access_token"].as_str().unwrap();
+
+    let (status, _) = app.post_json("/password/forgot", json!({ "email": email })).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let mail = app.mailer.wait_for(&email, RESET_SUBJECT).await;
+    assert!(mail.body.contains(&format!("{}/reset-password?token=", app.cfg.app_base_url)));
+    let reset_token = common::token_from(&mail.body);
+
+    let new_password = "a completely new password";
+    let (status, body) = app
+        .post_json("/password/reset", json!({ "token": reset_token, "new_password": new_password }))
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT, "reset failed: {}", body);
+
+    // Все сессии завершены
+    let (status, _) = app.request(Method::GET, "/me", Some(access_token), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app
+        .post_json("/refresh", json!({ "refresh_token": tokens["refresh_token"] }))
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Старый пароль больше не подходит, новый — подходит
+    let (status, _) = app.post_json("/login", json!({ "email": email, "password": password })).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    app.login(&email, new_password).await;
+
+    // Токен одноразовый
+    let (status, _) = app
+        .post_json("/password/reset", json!({ "token": reset_token, "new_password": "yet another password" }))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_DATABASE_URL"]
+async fn only_latest_reset_link_is_valid() {
+    let app = common::spawn_app().await;
+    let (email, _) = app.register().await;
+
+    app.post_json("/password/forgot", json!({ "email": email })).await;
+    let first = common::token_from(&app.mailer.wait_for(&email, RESET_SUBJECT).await.body);
+
+    app.post_json("/password/forgot", json!({ "email": email })).await;
+    let second = common::token_from(&app.mailer.wait_for_nth(&email, RESET_SUBJECT, 2).await.body);
+    assert_ne!(first, second);
+
+    let (status, _) = app
+        .post_json("/password/reset", json!({ "token": first, "new_password": "a completely new password" }))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = app
+        .post_json("/password/reset", json!({ "token": second, "new_password": "a completely new password" }))
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_DATABASE_URL"]
+async fn forgot_password_does_not_reveal_unknown_email() {
+    let app = common::spawn_app().await;
+    let email = format!("nobody-{}@example.com", Uuid::new_v4());
+
+    let (status, _) = app.post_json("/password/forgot", json!({ "email": email })).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert!(app.mailer.sent(&email, RESET_SUBJECT).is_empty());
+}
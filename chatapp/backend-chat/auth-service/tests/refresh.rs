@@ -0,0 +1,92 @@
+//! Ротация refresh-токенов и обнаружение повторного использования.
+//! Нужен Postgres: `TEST_DATABASE_URL=postgres://... cargo test -- --ignored`
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+
+#[tokio::test]
+#[ignore = "requires TEST_DATABASE_URL"]
+async fn reused_refresh_token_revokes_family() {
+    let app = common::spawn_app().await;
+    let (email, password) = app.register().await;
+
+    let login = app.login(&email, &password).await;
+    let stolen = login["refresh_token"].clone();
+
+    // Законный клиент обновляет токен
+    let (status, rotated) = app.post_json("/refresh", json!({ "refresh_token": stolen })).await;
+    assert_eq!(status, StatusCode::OK);
+    let access_token = rotated["access_token"].as_str().unwrap();
+
+    // Кто-то предъявляет старый токен — сессия отзывается целиком
+    let (status, body) = app.post_json("/refresh", json!({ "refresh_token": stolen })).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], json!("refresh token reused, session revoked"));
+
+    // Ни актуальный refresh-токен, ни access-токен семейства больше не действуют
+    let (status, _) = app.post_json("/refresh", json!({ "refresh_token": rotated["refresh_token"] })).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.request(Method::GET, "/me", Some(access_token), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Другие сессии пользователя не затронуты
+    let other = app.login(&email, &password).await;
+    let (status, _) = app.post_json("/refresh", json!({ "refresh_token": other["refresh_token"] })).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_DATABASE_URL"]
+async fn concurrent_refreshes_cannot_both_succeed() {
+    let app = common::spawn_app().await;
+    let (email, password) = app.register().await;
+
+    let login = app.login(&email, &password).await;
+    let body = json!({ "refresh_token": login["refresh_token"] });
+
+    let ((first, _), (second, _)) = tokio::join!(
+        app.post_json("/refresh", body.clone()),
+        app.post_json("/refresh", body.clone()),
+    );
+
+    let succeeded = [first, second].iter().filter(|s| **s == StatusCode::OK).count();
+    assert_eq!(succeeded, 1, "statuses: {} {}", first, second);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_DATABASE_URL"]
+async fn expired_rotated_tokens_are_pruned() {
+    let app = common::spawn_app().await;
+    let (email, password) = app.register().await;
+
+    let login = app.login(&email, &password).await;
+    let (status, first) = app.post_json("/refresh", json!({ "refresh_token": login["refresh_token"] })).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let session_id: uuid::Uuid = sqlx::query_scalar(
+        "SELECT s.id FROM sessions s JOIN users u ON u.id = s.user_id WHERE u.email = $1",
+    )
+    .bind(&email)
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+
+    // Первый использованный токен истёк бы ещё до ротации
+    sqlx::query("UPDATE rotated_refresh_tokens SET rotated_at = NOW() - INTERVAL '2 hours' WHERE session_id = $1")
+        .bind(session_id)
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let (status, _) = app.post_json("/refresh", json!({ "refresh_token": first["refresh_token"] })).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let remembered: i64 = sqlx::query_scalar("SELECT count(*) FROM rotated_refresh_tokens WHERE session_id = $1")
+        .bind(session_id)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(remembered, 1);
+}
@@ -0,0 +1,80 @@
+//! Список сессий, выход и отзыв сессий. Нужен Postgres: `TEST_DATABASE_URL=postgres://... cargo test -- --ignored`
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+use sqlx::postgres::PgListener;
+use std::time::Duration;
+use uuid::Uuid;
+
+#[tokio::test]
+#[ignore = "requires TEST_DATABASE_URL"]
+async fn list_and_revoke_sessions() {
+    let app = common::spawn_app().await;
+    let (email, password) = app.register().await;
+
+    let laptop = app.login(&email, &password).await;
+    let phone = app.login(&email, &password).await;
+    let laptop_token = laptop["access_token"].as_str().unwrap();
+    let phone_token = phone["access_token"].as_str().unwrap();
+
+    let (status, sessions) = app.request(Method::GET, "/sessions", Some(phone_token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let sessions = sessions.as_array().unwrap().clone();
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions.iter().filter(|s| s["current"] == json!(true)).count(), 1);
+
+    let laptop_session = sessions.iter().find(|s| s["current"] == json!(false)).unwrap()["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    // Отзыв сразу публикуется для chat-service
+    let mut listener = PgListener::connect_with(&app.pool).await.unwrap();
+    listener.listen("session_revoked").await.unwrap();
+
+    let (status, _) = app
+        .request(Method::DELETE, &format!("/sessions/{}", laptop_session), Some(phone_token), None)
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let notification = tokio::time::timeout(Duration::from_secs(5), listener.recv()).await.unwrap().unwrap();
+    assert_eq!(notification.payload(), laptop_session);
+
+    // Токен отозванной сессии не принимается, refresh-токен тоже
+    let (status, _) = app.request(Method::GET, "/me", Some(laptop_token), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.post_json("/refresh", json!({ "refresh_token": laptop["refresh_token"] })).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Чужую или несуществующую сессию отозвать нельзя
+    let (status, _) = app
+        .request(Method::DELETE, &format!("/sessions/{}", Uuid::new_v4()), Some(phone_token), None)
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = app.request(Method::GET, "/me", Some(phone_token), None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+#[ignore = "requires TEST_DATABASE_URL"]
+async fn logout_all_revokes_every_session() {
+    let app = common::spawn_app().await;
+    let (email, password) = app.register().await;
+
+    let first = app.login(&email, &password).await;
+    let second = app.login(&email, &password).await;
+    let first_token = first["access_token"].as_str().unwrap();
+    let second_token = second["access_token"].as_str().unwrap();
+
+    let (status, body) = app.request(Method::POST, "/logout/all", Some(first_token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["revoked"], json!(2));
+
+    for token in [first_token, second_token] {
+        let (status, _) = app.request(Method::GET, "/sessions", Some(token), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
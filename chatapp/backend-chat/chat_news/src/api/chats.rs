@@ -1,15 +1,436 @@
+// src/api/chats.rs
 use axum::{
     extract::{Path, State},
-    Json,
+    Json, http::StatusCode, response::{IntoResponse, Response}, routing::{get, post, put, delete}, Router,
 };
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use uuid::Uuid;
-use crate::{AppState, models::Message, auth::BearerToken};
+use crate::AppState;
+use crate::auth::AuthUser;
+use crate::db::chats::{Chat, ChatMember, ChatError};
+use crate::db::messages::Message;
+use crate::models::ServerEvent;
 
-pub async fn get_history(
+/// Максимальная длина названия группового чата
+const MAX_TITLE_LEN: usize = 128;
+/// Сколько участников можно добавить одним запросом (при создании группы или добавлении)
+const MAX_MEMBERS_PER_REQUEST: usize = 100;
+
+#[derive(Deserialize)]
+pub struct CreateDirectChatRequest {
+    pub user_id: Uuid,
+}
+
+#[derive(Deserialize)]
+pub struct CreateGroupChatRequest {
+    pub title: String,
+    pub avatar_url: Option<String>,
+    #[serde(default)]
+    pub member_ids: Vec<Uuid>,
+}
+
+#[derive(Deserialize)]
+pub struct AddMembersRequest {
+    pub user_ids: Vec<Uuid>,
+}
+
+#[derive(Deserialize)]
+pub struct RenameChatRequest {
+    pub title: String,
+}
+
+#[derive(Deserialize)]
+pub struct SetAvatarRequest {
+    pub avatar_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct MarkReadRequest {
+    pub message_id: Uuid,
+}
+
+#[derive(Serialize)]
+pub struct ChatSummary {
+    #[serde(flatten)]
+    pub chat: Chat,
+    pub last_message: Option<Message>,
+}
+
+#[derive(Serialize)]
+pub struct ChatDetails {
+    #[serde(flatten)]
+    pub chat: Chat,
+    pub members: Vec<ChatMember>,
+}
+
+#[derive(Serialize)]
+pub struct AddMembersResponse {
+    pub added: Vec<Uuid>,
+}
+
+#[derive(Serialize)]
+pub struct UnreadCounter {
+    pub chat_id: Uuid,
+    pub unread: i32,
+}
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/chats", get(list_chats))
+        .route("/chats/unread", get(unread_counters))
+        .route("/chats/direct", post(create_direct_chat))
+        .route("/chats/group", post(create_group_chat))
+        .route("/chats/:chat_id", get(get_chat))
+        .route("/chats/:chat_id/title", put(rename_chat))
+        .route("/chats/:chat_id/avatar", put(set_avatar))
+        .route("/chats/:chat_id/members", post(add_members))
+        .route("/chats/:chat_id/members/:user_id", delete(remove_member))
+        .route("/chats/:chat_id/leave", post(leave_chat))
+        .route("/chats/:chat_id/read", post(mark_read).get(get_read_markers))
+}
+
+fn chat_error_response(e: ChatError) -> Response {
+    match e {
+        ChatError::ChatNotFound => (StatusCode::NOT_FOUND, Json(serde_json::json!({"error":"chat not found"}))).into_response(),
+        ChatError::UserNotFound => (StatusCode::NOT_FOUND, Json(serde_json::json!({"error":"user not found"}))).into_response(),
+        ChatError::PermissionDenied => (StatusCode::FORBIDDEN, Json(serde_json::json!({"error":"permission denied"}))).into_response(),
+        ChatError::InvalidOperation(msg) => (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": msg}))).into_response(),
+        ChatError::InternalError(e) => {
+            tracing::error!("chats db error: {:?}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db error"}))).into_response()
+        }
+    }
+}
+
+/// Рассылает актуальные название и аватар чата
+async fn publish_chat_updated(state: &AppState, chat_id: Uuid, updated_by: Uuid) {
+    match state.chats.get_chat(chat_id).await {
+        Ok(Some(chat)) => {
+            state.kafka_producer.publish_or_warn(chat_id, ServerEvent::ChatUpdated {
+                title: chat.title,
+                avatar_url: chat.avatar_url,
+                updated_by,
+            }).await;
+        }
+        Ok(None) => {}
+        Err(e) => tracing::warn!("get_chat error: {:?}", e),
+    }
+}
+
+/// Доступ к чату только для участников; для остальных чата как будто нет
+async fn ensure_member(state: &AppState, chat_id: Uuid, user_id: Uuid) -> Result<(), Response> {
+    match state.scylla.is_user_in_chat(chat_id, user_id).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(chat_error_response(ChatError::ChatNotFound)),
+        Err(e) => {
+            tracing::error!("is_user_in_chat error: {:?}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db error"}))).into_response())
+        }
+    }
+}
+
+/// Убирает повторы и самого инициатора, ограничивает размер и проверяет,
+/// что все пользователи существуют в auth-service
+async fn validate_new_members(state: &AppState, requester: Uuid, user_ids: &[Uuid]) -> Result<Vec<Uuid>, Response> {
+    let mut members: Vec<Uuid> = Vec::with_capacity(user_ids.len());
+    for user_id in user_ids {
+        if *user_id != requester && !members.contains(user_id) {
+            members.push(*user_id);
+        }
+    }
+
+    if members.len() > MAX_MEMBERS_PER_REQUEST {
+        return Err(chat_error_response(ChatError::InvalidOperation("too many members in one request")));
+    }
+
+    for user_id in &members {
+        match crate::auth::user_exists(state, *user_id).await {
+            Ok(true) => {}
+            Ok(false) => return Err(chat_error_response(ChatError::UserNotFound)),
+            Err(e) => return Err(chat_error_response(ChatError::InternalError(e.into()))),
+        }
+    }
+
+    Ok(members)
+}
+
+fn normalize_title(title: &str) -> Option<String> {
+    let title = title.trim();
+    if title.is_empty() || title.chars().count() > MAX_TITLE_LEN {
+        return None;
+    }
+    Some(title.to_string())
+}
+
+/// GET /chats — чаты пользователя с превью последнего сообщения
+async fn list_chats(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+) -> impl IntoResponse {
+    let chat_ids = match state.scylla.get_user_chat_ids(user.id).await {
+        Ok(ids) => ids,
+        Err(e) => {
+            tracing::error!("get_user_chat_ids error: {:?}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db error"}))).into_response();
+        }
+    };
+
+    let mut summaries = Vec::with_capacity(chat_ids.len());
+    for chat_id in chat_ids {
+        let chat = match state.chats.get_chat(chat_id).await {
+            Ok(Some(chat)) => chat,
+            Ok(None) => continue,
+            Err(e) => return chat_error_response(e.into()),
+        };
+
+        let last_message = match state.scylla.fetch_recent_paged(chat_id, 1, None).await {
+            Ok((msgs, _)) => msgs.into_iter().next(),
+            Err(e) => {
+                tracing::error!("fetch_recent_paged error: {:?}", e);
+                None
+            }
+        };
+
+        summaries.push(ChatSummary { chat, last_message });
+    }
+
+    // Сначала чаты с самой свежей активностью
+    summaries.sort_by_key(|s| {
+        std::cmp::Reverse(s.last_message.as_ref().map(|m| m.created_at).unwrap_or(s.chat.updated_at))
+    });
+
+    (StatusCode::OK, Json(summaries)).into_response()
+}
+
+/// POST /chats/direct
+async fn create_direct_chat(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Json(payload): Json<CreateDirectChatRequest>,
+) -> impl IntoResponse {
+    // Пользователи живут в auth-service: чат с несуществующим собеседником не создаём
+    match crate::auth::user_exists(&state, payload.user_id).await {
+        Ok(true) => {}
+        Ok(false) => return chat_error_response(ChatError::UserNotFound),
+        Err(e) => return chat_error_response(ChatError::InternalError(e.into())),
+    }
+
+    match state.chats.get_or_create_direct_chat(user.id, payload.user_id).await {
+        Ok(chat) => (StatusCode::OK, Json(chat)).into_response(),
+        Err(e) => chat_error_response(e),
+    }
+}
+
+/// POST /chats/group
+async fn create_group_chat(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Json(payload): Json<CreateGroupChatRequest>,
+) -> impl IntoResponse {
+    let Some(title) = normalize_title(&payload.title) else {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error":"invalid title"}))).into_response();
+    };
+
+    let member_ids = match validate_new_members(&state, user.id, &payload.member_ids).await {
+        Ok(ids) => ids,
+        Err(resp) => return resp,
+    };
+
+    match state.chats.create_group_chat(user.id, title, payload.avatar_url, &member_ids).await {
+        Ok(chat) => {
+            // Как и при добавлении участников: открытые соединения подписываются на новую комнату
+            for member_id in std::iter::once(user.id).chain(member_ids) {
+                state.kafka_producer.publish_or_warn(chat.chat_id, ServerEvent::MemberJoined { user_id: member_id, added_by: user.id }).await;
+            }
+            (StatusCode::CREATED, Json(chat)).into_response()
+        }
+        Err(e) => chat_error_response(e.into()),
+    }
+}
+
+/// GET /chats/:chat_id
+async fn get_chat(
+    State(state): State<Arc<AppState>>,
+    Path(chat_id): Path<Uuid>,
+    AuthUser(user): AuthUser,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_member(&state, chat_id, user.id).await {
+        return resp;
+    }
+
+    let chat = match state.chats.get_chat(chat_id).await {
+        Ok(Some(chat)) => chat,
+        Ok(None) => return chat_error_response(ChatError::ChatNotFound),
+        Err(e) => return chat_error_response(e.into()),
+    };
+
+    match state.chats.get_members(chat_id).await {
+        Ok(members) => (StatusCode::OK, Json(ChatDetails { chat, members })).into_response(),
+        Err(e) => chat_error_response(e.into()),
+    }
+}
+
+/// PUT /chats/:chat_id/title
+async fn rename_chat(
+    State(state): State<Arc<AppState>>,
+    Path(chat_id): Path<Uuid>,
+    AuthUser(user): AuthUser,
+    Json(payload): Json<RenameChatRequest>,
+) -> impl IntoResponse {
+    let Some(title) = normalize_title(&payload.title) else {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error":"invalid title"}))).into_response();
+    };
+
+    match state.chats.rename_chat(chat_id, user.id, title).await {
+        Ok(()) => {
+            publish_chat_updated(&state, chat_id, user.id).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => chat_error_response(e),
+    }
+}
+
+/// PUT /chats/:chat_id/avatar
+async fn set_avatar(
+    State(state): State<Arc<AppState>>,
     Path(chat_id): Path<Uuid>,
-    State(state): State<AppState>,
-    _bearer: BearerToken,
-) -> Result<Json<Vec<Message>>, crate::auth::AppError> {
-    let history = state.db.get_chat_history(chat_id, 50).await?;
-    Ok(Json(history))
-}
\ No newline at end of file
+    AuthUser(user): AuthUser,
+    Json(payload): Json<SetAvatarRequest>,
+) -> impl IntoResponse {
+    match state.chats.set_avatar(chat_id, user.id, payload.avatar_url).await {
+        Ok(()) => {
+            publish_chat_updated(&state, chat_id, user.id).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => chat_error_response(e),
+    }
+}
+
+/// POST /chats/:chat_id/members
+async fn add_members(
+    State(state): State<Arc<AppState>>,
+    Path(chat_id): Path<Uuid>,
+    AuthUser(user): AuthUser,
+    Json(payload): Json<AddMembersRequest>,
+) -> impl IntoResponse {
+    let user_ids = match validate_new_members(&state, user.id, &payload.user_ids).await {
+        Ok(ids) => ids,
+        Err(resp) => return resp,
+    };
+
+    match state.chats.add_members(chat_id, user.id, &user_ids).await {
+        Ok(added) => {
+            for member_id in &added {
+                state.kafka_producer.publish_or_warn(chat_id, ServerEvent::MemberJoined { user_id: *member_id, added_by: user.id }).await;
+            }
+            (StatusCode::OK, Json(AddMembersResponse { added })).into_response()
+        }
+        Err(e) => chat_error_response(e),
+    }
+}
+
+/// DELETE /chats/:chat_id/members/:user_id
+async fn remove_member(
+    State(state): State<Arc<AppState>>,
+    Path((chat_id, member_id)): Path<(Uuid, Uuid)>,
+    AuthUser(user): AuthUser,
+) -> impl IntoResponse {
+    match state.chats.remove_member(chat_id, user.id, member_id).await {
+        Ok(()) => {
+            state.kafka_producer.publish_or_warn(chat_id, ServerEvent::MemberLeft { user_id: member_id, removed_by: user.id }).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => chat_error_response(e),
+    }
+}
+
+/// POST /chats/:chat_id/leave
+async fn leave_chat(
+    State(state): State<Arc<AppState>>,
+    Path(chat_id): Path<Uuid>,
+    AuthUser(user): AuthUser,
+) -> impl IntoResponse {
+    match state.chats.leave_chat(chat_id, user.id).await {
+        Ok(()) => {
+            state.kafka_producer.publish_or_warn(chat_id, ServerEvent::MemberLeft { user_id: user.id, removed_by: user.id }).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => chat_error_response(e),
+    }
+}
+
+/// POST /chats/:chat_id/read — сдвигает отметку прочтения до указанного сообщения
+async fn mark_read(
+    State(state): State<Arc<AppState>>,
+    Path(chat_id): Path<Uuid>,
+    AuthUser(user): AuthUser,
+    Json(payload): Json<MarkReadRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_member(&state, chat_id, user.id).await {
+        return resp;
+    }
+
+    let msg = match state.scylla.get_message_by_id(payload.message_id).await {
+        Ok(Some(msg)) if msg.chat_id == chat_id => msg,
+        Ok(_) => return (StatusCode::NOT_FOUND, Json(serde_json::json!({"error":"message not found"}))).into_response(),
+        Err(e) => return chat_error_response(e.into()),
+    };
+
+    match state.read_markers.mark_read(chat_id, user.id, msg.message_id, msg.created_at).await {
+        Ok(Some(marker)) => {
+            state.kafka_producer.publish_or_warn(chat_id, ServerEvent::MessagesRead {
+                user_id: user.id,
+                last_read_message_id: marker.last_read_message_id,
+                read_at: marker.updated_at,
+            }).await;
+            (StatusCode::OK, Json(marker)).into_response()
+        }
+        // Отметка уже стоит дальше — возвращаем текущую
+        Ok(None) => match state.read_markers.get_marker(chat_id, user.id).await {
+            Ok(marker) => (StatusCode::OK, Json(marker)).into_response(),
+            Err(e) => chat_error_response(e.into()),
+        },
+        Err(e) => chat_error_response(e.into()),
+    }
+}
+
+/// GET /chats/:chat_id/read — отметки прочтения участников чата
+async fn get_read_markers(
+    State(state): State<Arc<AppState>>,
+    Path(chat_id): Path<Uuid>,
+    AuthUser(user): AuthUser,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_member(&state, chat_id, user.id).await {
+        return resp;
+    }
+
+    match state.read_markers.get_chat_markers(chat_id).await {
+        Ok(markers) => (StatusCode::OK, Json(markers)).into_response(),
+        Err(e) => chat_error_response(e.into()),
+    }
+}
+
+/// GET /chats/unread — количество непрочитанных по каждому чату пользователя
+async fn unread_counters(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+) -> impl IntoResponse {
+    let chat_ids = match state.scylla.get_user_chat_ids(user.id).await {
+        Ok(ids) => ids,
+        Err(e) => {
+            tracing::error!("get_user_chat_ids error: {:?}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db error"}))).into_response();
+        }
+    };
+
+    let mut counters = Vec::with_capacity(chat_ids.len());
+    for chat_id in chat_ids {
+        match state.read_markers.unread_count(chat_id, user.id).await {
+            Ok(unread) => counters.push(UnreadCounter { chat_id, unread }),
+            Err(e) => return chat_error_response(e.into()),
+        }
+    }
+
+    (StatusCode::OK, Json(counters)).into_response()
+}
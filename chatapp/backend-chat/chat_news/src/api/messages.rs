@@ -1,22 +1,24 @@
 // src/api/messages.rs
 use axum::{
-    extract::{Path, Query, Extension},
-    Json, http::StatusCode, response::IntoResponse, routing::{get, post, put, delete}, Router,
+    extract::{Path, Query, State},
+    Json, http::StatusCode, response::{IntoResponse, Response}, routing::{get, post, put, delete}, Router,
 };
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use uuid::Uuid;
 use base64::{engine::general_purpose, Engine as _};
-use crate::db::messages::{ScyllaDb, Message, MessageEdit};
-use crate::auth::AuthUser; // экстрактор, возвращающий AuthUser(Uuid)
-use chrono::Utc;
-use anyhow::Result;
+use crate::AppState;
+use crate::models::{ChatEvent, ServerEvent};
+use crate::db::messages::{Message, DeleteError};
+use crate::db::reactions::{is_valid_emoji, ReactionError};
+use crate::auth::AuthUser;
 
 #[derive(Deserialize)]
 pub struct CreateMessageRequest {
     pub content: Option<String>,
     pub media_urls: Option<Vec<String>>,
     pub media_meta: Option<std::collections::HashMap<String,String>>,
+    pub reply_to: Option<Uuid>,
 }
 
 #[derive(Serialize)]
@@ -37,6 +39,13 @@ pub struct PagedMessages {
     pub next_paging_state: Option<String>, // base64
 }
 
+#[derive(Serialize)]
+pub struct ThreadPage {
+    pub root: Message,
+    pub replies: Vec<Message>,
+    pub next_paging_state: Option<String>, // base64
+}
+
 #[derive(Deserialize)]
 pub struct EditMessageRequest {
     pub new_content: Option<String>,
@@ -48,138 +57,434 @@ pub struct AttachMediaRequest {
     pub media_meta: Option<std::collections::HashMap<String,String>>,
 }
 
-pub fn router() -> Router {
+pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/chats/:chat_id/messages", post(create_message))
-        .route("/chats/:chat_id/messages", get(fetch_messages))
-        .route("/messages/:message_id", put(edit_message))
-        .route("/messages/:message_id", delete(delete_message))
+        .route("/chats/:chat_id/messages", post(create_message).get(fetch_messages))
+        .route("/messages/:message_id", put(edit_message).delete(delete_message))
+        .route("/messages/:message_id/restore", post(restore_message))
+        .route("/messages/:message_id/hard", delete(hard_delete_message))
         .route("/messages/:message_id/media", post(attach_media))
         .route("/messages/:message_id/edits", get(get_edits))
+        .route("/messages/:message_id/thread", get(fetch_thread))
+        .route("/messages/:message_id/reactions", get(get_reactions))
+        .route("/messages/:message_id/reactions/:emoji", put(add_reaction).delete(remove_reaction))
+}
+
+fn db_error() -> Response {
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db error"}))).into_response()
+}
+
+fn not_found() -> Response {
+    (StatusCode::NOT_FOUND, Json(serde_json::json!({"error":"not found"}))).into_response()
+}
+
+fn forbidden() -> Response {
+    (StatusCode::FORBIDDEN, Json(serde_json::json!({"error":"permission denied"}))).into_response()
+}
+
+fn conflict() -> Response {
+    (StatusCode::CONFLICT, Json(serde_json::json!({"error":"message was modified concurrently"}))).into_response()
+}
+
+fn delete_error_response(e: DeleteError) -> Response {
+    match e {
+        DeleteError::MessageNotFound => not_found(),
+        DeleteError::PermissionDenied => forbidden(),
+        DeleteError::Conflict => conflict(),
+        DeleteError::InternalError(e) => {
+            tracing::error!("message update error: {:?}", e);
+            db_error()
+        }
+    }
+}
+
+/// Проверяет, что пользователь состоит в чате
+async fn ensure_member(state: &AppState, chat_id: Uuid, user_id: Uuid) -> Result<(), Response> {
+    match state.scylla.is_user_in_chat(chat_id, user_id).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(forbidden()),
+        Err(e) => {
+            tracing::error!("is_user_in_chat error: {:?}", e);
+            Err(db_error())
+        }
+    }
+}
+
+/// Загружает сообщение и проверяет, что пользователь состоит в его чате
+async fn load_message_for_member(state: &AppState, message_id: Uuid, user_id: Uuid) -> Result<Message, Response> {
+    let msg = match state.scylla.get_message_by_id(message_id).await {
+        Ok(Some(msg)) => msg,
+        Ok(None) => return Err(not_found()),
+        Err(e) => {
+            tracing::error!("get_message_by_id error: {:?}", e);
+            return Err(db_error());
+        }
+    };
+
+    ensure_member(state, msg.chat_id, user_id).await?;
+    Ok(msg)
+}
+
+/// Является ли пользователь владельцем или админом чата
+async fn is_chat_manager(state: &AppState, chat_id: Uuid, user_id: Uuid) -> bool {
+    match state.chats.get_member(chat_id, user_id).await {
+        Ok(Some(member)) => member.role.can_manage(),
+        Ok(None) => false,
+        Err(e) => {
+            tracing::error!("get_member error: {:?}", e);
+            false
+        }
+    }
 }
 
 /// POST /chats/:chat_id/messages
 async fn create_message(
-    Extension(db): Extension<Arc<ScyllaDb>>,
+    State(state): State<Arc<AppState>>,
     Path(chat_id): Path<Uuid>,
+    AuthUser(user): AuthUser,
     Json(payload): Json<CreateMessageRequest>,
-    AuthUser(user_id): AuthUser,
 ) -> impl IntoResponse {
-    let msg = Message {
-        chat_id,
-        created_at: Utc::now(),
-        message_id: Uuid::new_v4(),
-        user_id,
-        content: payload.content,
-        media_urls: payload.media_urls,
-        media_meta: payload.media_meta,
-        is_deleted: false,
-        deleted_at: None,
-        edited_at: None,
-        edited_by: None,
-        version: 0,
-    };
-    if let Err(e) = db.insert_message(&msg).await {
-        tracing::error!("insert_message error: {:?}", e);
-        return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db error"}))).into_response();
+    if let Err(resp) = ensure_member(&state, chat_id, user.id).await {
+        return resp;
+    }
+
+    // Единый путь записи: событие уходит в Kafka, консьюмер сохраняет его в Scylla и рассылает подписчикам
+    let media_meta = payload.media_meta.and_then(|m| serde_json::to_value(m).ok());
+    let mut event = ChatEvent::new_message(chat_id, user.id, payload.content, payload.media_urls, media_meta);
+    if event.is_empty() {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error":"empty message"}))).into_response();
     }
-    (StatusCode::CREATED, Json(CreateMessageResponse { message_id: msg.message_id, created_at: msg.created_at.timestamp() })).into_response()
+
+    // Ответ попадает в тред корневого сообщения, а не в ленту чата
+    if let Some(reply_to) = payload.reply_to {
+        match state.scylla.resolve_thread_root(chat_id, reply_to).await {
+            Ok(Some(thread_root_id)) => event = event.in_thread(reply_to, thread_root_id),
+            Ok(None) => return not_found(),
+            Err(e) => {
+                tracing::error!("resolve_thread_root error: {:?}", e);
+                return db_error();
+            }
+        }
+    }
+
+    if let Err(e) = state.kafka_producer.publish(chat_id, ServerEvent::MessageCreated(event.clone())).await {
+        tracing::error!("kafka send error: {:?}", e);
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(serde_json::json!({"error":"message broker unavailable"}))).into_response();
+    }
+    (StatusCode::ACCEPTED, Json(CreateMessageResponse { message_id: event.message_id, created_at: event.created_at.timestamp() })).into_response()
 }
 
 /// GET /chats/:chat_id/messages?limit=50&paging_state=base64
 async fn fetch_messages(
-    Extension(db): Extension<Arc<ScyllaDb>>,
+    State(state): State<Arc<AppState>>,
     Path(chat_id): Path<Uuid>,
+    AuthUser(user): AuthUser,
     Query(q): Query<FetchQuery>,
 ) -> impl IntoResponse {
-    let limit = q.limit.unwrap_or(50).min(200);
+    if let Err(resp) = ensure_member(&state, chat_id, user.id).await {
+        return resp;
+    }
+
+    let limit = q.limit.unwrap_or(50).clamp(1, 200);
     let paging_state = q.paging_state.and_then(|s| general_purpose::STANDARD.decode(s).ok());
-    match db.fetch_recent_paged(chat_id, limit, paging_state).await {
+    match state.scylla.fetch_recent_paged(chat_id, limit, paging_state).await {
         Ok((msgs, next)) => {
-            let next_b64 = next.map(|b| general_purpose::STANDARD.encode(&b));
+            let next_b64 = next.map(|b| general_purpose::STANDARD.encode(b));
             (StatusCode::OK, Json(PagedMessages { messages: msgs, next_paging_state: next_b64 })).into_response()
         }
         Err(e) => {
             tracing::error!("fetch_recent_paged error: {:?}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db error"}))).into_response()
+            db_error()
         }
     }
 }
 
+/// GET /messages/:message_id/thread?limit=50&paging_state=base64
+async fn fetch_thread(
+    State(state): State<Arc<AppState>>,
+    Path(message_id): Path<Uuid>,
+    AuthUser(user): AuthUser,
+    Query(q): Query<FetchQuery>,
+) -> impl IntoResponse {
+    let msg = match load_message_for_member(&state, message_id, user.id).await {
+        Ok(msg) => msg,
+        Err(resp) => return resp,
+    };
+
+    // Для ответа открываем тред его корня
+    let mut root = match msg.thread_root_id {
+        Some(thread_root_id) => match state.scylla.get_message_by_id(thread_root_id).await {
+            Ok(Some(root)) => root,
+            Ok(None) => return not_found(),
+            Err(e) => {
+                tracing::error!("get_message_by_id error: {:?}", e);
+                return db_error();
+            }
+        },
+        None => msg,
+    };
+
+    let limit = q.limit.unwrap_or(50).clamp(1, 200);
+    let paging_state = q.paging_state.and_then(|s| general_purpose::STANDARD.decode(s).ok());
+    let (replies, next) = match state.scylla.fetch_thread_paged(root.message_id, limit, paging_state).await {
+        Ok(page) => page,
+        Err(e) => {
+            tracing::error!("fetch_thread_paged error: {:?}", e);
+            return db_error();
+        }
+    };
+
+    match state.scylla.threads.get_stats(root.message_id).await {
+        Ok(Some(stats)) => {
+            root.reply_count = stats.reply_count;
+            root.last_reply_at = stats.last_reply_at;
+        }
+        Ok(None) => {}
+        Err(e) => tracing::warn!("thread stats error: {:?}", e),
+    }
+    match state.scylla.reactions.get_reactions(root.message_id).await {
+        Ok(reactions) => root.reactions = reactions,
+        Err(e) => tracing::warn!("get_reactions error: {:?}", e),
+    }
+
+    let next_b64 = next.map(|b| general_purpose::STANDARD.encode(b));
+    (StatusCode::OK, Json(ThreadPage { root, replies, next_paging_state: next_b64 })).into_response()
+}
+
 /// PUT /messages/:message_id
 async fn edit_message(
-    Extension(db): Extension<Arc<ScyllaDb>>,
+    State(state): State<Arc<AppState>>,
     Path(message_id): Path<Uuid>,
+    AuthUser(user): AuthUser,
     Json(payload): Json<EditMessageRequest>,
-    AuthUser(editor): AuthUser,
 ) -> impl IntoResponse {
-    if let Err(e) = db.edit_message_with_history(message_id, payload.new_content.clone(), editor).await {
-        tracing::error!("edit_message error: {:?}", e);
-        return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db error"}))).into_response();
+    let msg = match load_message_for_member(&state, message_id, user.id).await {
+        Ok(msg) => msg,
+        Err(resp) => return resp,
+    };
+
+    // Редактировать может только автор
+    if msg.user_id != user.id {
+        return forbidden();
+    }
+
+    match state.scylla.edit_message_with_history(message_id, payload.new_content.clone(), user.id).await {
+        Ok(Some(updated)) => {
+            state.kafka_producer.publish_or_warn(updated.chat_id, updated.edited_event()).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(None) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => delete_error_response(e),
     }
-    StatusCode::NO_CONTENT.into_response()
 }
 
 /// DELETE /messages/:message_id  (soft delete)
 async fn delete_message(
-    Extension(db): Extension<Arc<ScyllaDb>>,
+    State(state): State<Arc<AppState>>,
     Path(message_id): Path<Uuid>,
-    AuthUser(_user): AuthUser,
+    AuthUser(user): AuthUser,
 ) -> impl IntoResponse {
-    // fetch message to obtain chat_id and created_at
-    match db.get_message_by_id(message_id).await {
-        Ok(Some(msg)) => {
-            if let Err(e) = db.soft_delete_message(msg.chat_id, msg.created_at, message_id).await {
-                tracing::error!("soft_delete error: {:?}", e);
-                return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db error"}))).into_response();
-            }
+    let msg = match load_message_for_member(&state, message_id, user.id).await {
+        Ok(msg) => msg,
+        Err(resp) => return resp,
+    };
+
+    // Чужие сообщения могут удалять только владелец и админы чата
+    let is_admin = is_chat_manager(&state, msg.chat_id, user.id).await;
+
+    match state.scylla.delete_message(message_id, user.id, is_admin).await {
+        Ok(Some(updated)) => {
+            state.kafka_producer.publish_or_warn(updated.chat_id, updated.deleted_event(user.id)).await;
             StatusCode::NO_CONTENT.into_response()
         }
-        Ok(None) => (StatusCode::NOT_FOUND, Json(serde_json::json!({"error":"not found"}))).into_response(),
-        Err(e) => {
-            tracing::error!("get_message_by_id error: {:?}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db error"}))).into_response()
+        Ok(None) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => delete_error_response(e),
+    }
+}
+
+/// POST /messages/:message_id/restore
+async fn restore_message(
+    State(state): State<Arc<AppState>>,
+    Path(message_id): Path<Uuid>,
+    AuthUser(user): AuthUser,
+) -> impl IntoResponse {
+    let msg = match load_message_for_member(&state, message_id, user.id).await {
+        Ok(msg) => msg,
+        Err(resp) => return resp,
+    };
+
+    let is_admin = is_chat_manager(&state, msg.chat_id, user.id).await;
+
+    match state.scylla.restore_message(message_id, user.id, is_admin).await {
+        Ok(Some(updated)) => {
+            state.kafka_producer.publish_or_warn(updated.chat_id, updated.restored_event(user.id)).await;
+            StatusCode::NO_CONTENT.into_response()
         }
+        Ok(None) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => delete_error_response(e),
+    }
+}
+
+/// DELETE /messages/:message_id/hard  (безвозвратно, только владелец/админ чата)
+async fn hard_delete_message(
+    State(state): State<Arc<AppState>>,
+    Path(message_id): Path<Uuid>,
+    AuthUser(user): AuthUser,
+) -> impl IntoResponse {
+    let msg = match load_message_for_member(&state, message_id, user.id).await {
+        Ok(msg) => msg,
+        Err(resp) => return resp,
+    };
+
+    let is_admin = is_chat_manager(&state, msg.chat_id, user.id).await;
+
+    match state.scylla.hard_delete_message(message_id, user.id, is_admin).await {
+        Ok(purged) => {
+            state.kafka_producer.publish_or_warn(purged.chat_id, ServerEvent::MessagePurged { message_id, purged_by: user.id }).await;
+
+            if let Some(thread_root_id) = purged.thread_root_id {
+                match state.scylla.threads.updated_event(thread_root_id).await {
+                    Ok(event) => state.kafka_producer.publish_or_warn(purged.chat_id, event).await,
+                    Err(e) => tracing::warn!("thread stats error: {:?}", e),
+                }
+            }
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => delete_error_response(e),
     }
 }
 
 /// POST /messages/:message_id/media
 async fn attach_media(
-    Extension(db): Extension<Arc<ScyllaDb>>,
+    State(state): State<Arc<AppState>>,
     Path(message_id): Path<Uuid>,
+    AuthUser(user): AuthUser,
     Json(payload): Json<AttachMediaRequest>,
-    AuthUser(_user): AuthUser,
 ) -> impl IntoResponse {
-    // Need chat_id + created_at -> get message
-    match db.get_message_by_id(message_id).await {
-        Ok(Some(msg)) => {
-            let meta = payload.media_meta.unwrap_or_default();
-            if let Err(e) = db.attach_media(msg.chat_id, msg.created_at, message_id, payload.media_urls.clone(), meta).await {
-                tracing::error!("attach_media error: {:?}", e);
-                return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db error"}))).into_response();
-            }
+    let msg = match load_message_for_member(&state, message_id, user.id).await {
+        Ok(msg) => msg,
+        Err(resp) => return resp,
+    };
+
+    if msg.user_id != user.id {
+        return forbidden();
+    }
+
+    let meta = payload.media_meta.unwrap_or_default();
+    match state.scylla.attach_media(&msg, payload.media_urls.clone(), meta).await {
+        Ok(updated) => {
+            state.kafka_producer.publish_or_warn(updated.chat_id, updated.media_attached_event()).await;
             StatusCode::NO_CONTENT.into_response()
         }
-        Ok(None) => (StatusCode::NOT_FOUND, Json(serde_json::json!({"error":"not found"}))).into_response(),
-        Err(e) => {
-            tracing::error!("get_message_by_id error: {:?}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db error"}))).into_response()
-        }
+        Err(e) => delete_error_response(e),
     }
 }
 
 /// GET /messages/:message_id/edits?limit=20
 async fn get_edits(
-    Extension(db): Extension<Arc<ScyllaDb>>,
+    State(state): State<Arc<AppState>>,
     Path(message_id): Path<Uuid>,
-    Query(q): Query<Option<FetchQuery>>,
+    AuthUser(user): AuthUser,
+    Query(q): Query<FetchQuery>,
 ) -> impl IntoResponse {
-    let limit = q.and_then(|qq| qq.limit).unwrap_or(50).min(500);
-    match db.fetch_edits_by_message(message_id, limit).await {
+    if let Err(resp) = load_message_for_member(&state, message_id, user.id).await {
+        return resp;
+    }
+
+    let limit = q.limit.unwrap_or(50).clamp(1, 500);
+    match state.scylla.fetch_edits_by_message(message_id, limit).await {
         Ok(edits) => (StatusCode::OK, Json(edits)).into_response(),
         Err(e) => {
             tracing::error!("fetch_edits_by_message error: {:?}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db error"}))).into_response()
+            db_error()
+        }
+    }
+}
+
+/// GET /messages/:message_id/reactions
+async fn get_reactions(
+    State(state): State<Arc<AppState>>,
+    Path(message_id): Path<Uuid>,
+    AuthUser(user): AuthUser,
+) -> impl IntoResponse {
+    if let Err(resp) = load_message_for_member(&state, message_id, user.id).await {
+        return resp;
+    }
+
+    match state.scylla.reactions.get_reactions(message_id).await {
+        Ok(reactions) => (StatusCode::OK, Json(reactions)).into_response(),
+        Err(e) => {
+            tracing::error!("get_reactions error: {:?}", e);
+            db_error()
         }
     }
-}
\ No newline at end of file
+}
+
+/// PUT /messages/:message_id/reactions/:emoji
+async fn add_reaction(
+    State(state): State<Arc<AppState>>,
+    Path((message_id, emoji)): Path<(Uuid, String)>,
+    AuthUser(user): AuthUser,
+) -> impl IntoResponse {
+    if !is_valid_emoji(&emoji) {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error":"invalid emoji"}))).into_response();
+    }
+
+    let msg = match load_message_for_member(&state, message_id, user.id).await {
+        Ok(msg) => msg,
+        Err(resp) => return resp,
+    };
+
+    if msg.is_deleted {
+        return not_found();
+    }
+
+    match state.scylla.add_reaction(&msg, user.id, &emoji).await {
+        Ok(Some(count)) => {
+            state.kafka_producer.publish_or_warn(msg.chat_id, ServerEvent::ReactionAdded {
+                message_id,
+                user_id: user.id,
+                emoji,
+                count,
+            }).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(None) => StatusCode::NO_CONTENT.into_response(),
+        Err(ReactionError::TooManyReactions) => {
+            (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error":"too many reactions"}))).into_response()
+        }
+        Err(ReactionError::InternalError(e)) => {
+            tracing::error!("add_reaction error: {:?}", e);
+            db_error()
+        }
+    }
+}
+
+/// DELETE /messages/:message_id/reactions/:emoji
+async fn remove_reaction(
+    State(state): State<Arc<AppState>>,
+    Path((message_id, emoji)): Path<(Uuid, String)>,
+    AuthUser(user): AuthUser,
+) -> impl IntoResponse {
+    let msg = match load_message_for_member(&state, message_id, user.id).await {
+        Ok(msg) => msg,
+        Err(resp) => return resp,
+    };
+
+    match state.scylla.remove_reaction(&msg, user.id, &emoji).await {
+        Ok(Some(count)) => {
+            state.kafka_producer.publish_or_warn(msg.chat_id, ServerEvent::ReactionRemoved {
+                message_id,
+                user_id: user.id,
+                emoji,
+                count,
+            }).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(None) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            tracing::error!("remove_reaction error: {:?}", e);
+            db_error()
+        }
+    }
+}
@@ -1,10 +1,21 @@
+// src/api/mod.rs
+
 use axum::Router;
+use std::sync::Arc;
+use crate::AppState;
 
 pub mod messages;
 pub mod chats;
+pub mod search;
+pub mod users;
+pub mod ws;
 
-pub fn api_routes() -> Router {
+/// REST API сервиса (монтируется под `/api/v1`)
+pub fn router() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/messages", axum::routing::post(messages::send_message))
-        .route("/chats/:id/history", axum::routing::get(chats::get_history))
-}
\ No newline at end of file
+        .merge(chats::router())
+        .merge(messages::router())
+        .merge(search::router())
+        .merge(users::router())
+        .merge(ws::router())
+}
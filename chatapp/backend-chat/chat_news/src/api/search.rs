@@ -0,0 +1,111 @@
+// src/api/search.rs
+use axum::{
+    extract::{Query, State},
+    Json, http::StatusCode, response::IntoResponse, routing::get, Router,
+};
+use chrono::{DateTime, Utc};
+use futures_util::future::join_all;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+use crate::AppState;
+use crate::auth::AuthUser;
+use crate::db::messages::Message;
+use crate::search::SearchFilter;
+
+/// Максимальная длина поискового запроса
+const MAX_QUERY_LEN: usize = 256;
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    pub chat_id: Option<Uuid>,
+    pub sender_id: Option<Uuid>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub has_media: Option<bool>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct SearchResult {
+    pub message: Message,
+    pub score: f32,
+}
+
+#[derive(Serialize)]
+pub struct SearchResponse {
+    pub results: Vec<SearchResult>,
+}
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/search/messages", get(search_messages))
+}
+
+/// GET /search/messages?q=...&chat_id=&sender_id=&from=&to=&has_media=&limit=20&offset=0
+async fn search_messages(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Query(q): Query<SearchQuery>,
+) -> impl IntoResponse {
+    let text = q.q.trim().to_string();
+    if text.is_empty() || text.chars().count() > MAX_QUERY_LEN {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error":"invalid query"}))).into_response();
+    }
+
+    // Ищем только по чатам, в которых состоит пользователь
+    let mut chat_ids = match state.scylla.get_user_chat_ids(user.id).await {
+        Ok(ids) => ids,
+        Err(e) => {
+            tracing::error!("get_user_chat_ids error: {:?}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db error"}))).into_response();
+        }
+    };
+    if let Some(chat_id) = q.chat_id {
+        if !chat_ids.contains(&chat_id) {
+            return (StatusCode::FORBIDDEN, Json(serde_json::json!({"error":"permission denied"}))).into_response();
+        }
+        chat_ids = vec![chat_id];
+    }
+
+    let filter = SearchFilter {
+        chat_ids,
+        sender_id: q.sender_id,
+        from: q.from,
+        to: q.to,
+        has_media: q.has_media,
+    };
+    let limit = q.limit.unwrap_or(20).clamp(1, 100);
+    let offset = q.offset.unwrap_or(0);
+
+    let index = state.search.clone();
+    let hits = match tokio::task::spawn_blocking(move || index.search(&text, &filter, limit, offset)).await {
+        Ok(Ok(hits)) => hits,
+        Ok(Err(e)) => {
+            tracing::error!("search error: {:?}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"search error"}))).into_response();
+        }
+        Err(e) => {
+            tracing::error!("search task error: {:?}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"search error"}))).into_response();
+        }
+    };
+
+    // Индекс обновляется асинхронно, поэтому сообщения берём из Scylla и отбрасываем удалённые
+    let messages = join_all(hits.iter().map(|hit| state.scylla.get_message_by_id(hit.message_id))).await;
+
+    let mut results = Vec::with_capacity(hits.len());
+    for (hit, message) in hits.into_iter().zip(messages) {
+        match message {
+            Ok(Some(message)) if !message.is_deleted && message.chat_id == hit.chat_id => {
+                results.push(SearchResult { message, score: hit.score });
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("get_message_by_id error: {:?}", e),
+        }
+    }
+
+    (StatusCode::OK, Json(SearchResponse { results })).into_response()
+}
@@ -0,0 +1,62 @@
+// src/api/users.rs
+use axum::{
+    extract::{Path, State},
+    Json, http::StatusCode, response::IntoResponse, routing::{get, post}, Router,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use uuid::Uuid;
+use crate::AppState;
+use crate::auth::AuthUser;
+
+/// Сколько пользователей можно запросить за раз
+const MAX_BATCH_SIZE: usize = 200;
+
+#[derive(Deserialize)]
+pub struct BatchPresenceRequest {
+    pub user_ids: Vec<Uuid>,
+}
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/users/presence", post(batch_presence))
+        .route("/users/:user_id/presence", get(get_presence))
+}
+
+/// GET /users/:user_id/presence
+async fn get_presence(
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<Uuid>,
+    AuthUser(_user): AuthUser,
+) -> impl IntoResponse {
+    match state.presence.get(user_id).await {
+        Ok(presence) => (StatusCode::OK, Json(presence)).into_response(),
+        Err(e) => {
+            tracing::error!("get presence error: {:?}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db error"}))).into_response()
+        }
+    }
+}
+
+/// POST /users/presence — статусы пачки пользователей
+async fn batch_presence(
+    State(state): State<Arc<AppState>>,
+    AuthUser(_user): AuthUser,
+    Json(payload): Json<BatchPresenceRequest>,
+) -> impl IntoResponse {
+    let mut user_ids = payload.user_ids;
+    user_ids.sort();
+    user_ids.dedup();
+
+    if user_ids.len() > MAX_BATCH_SIZE {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error":"too many users"}))).into_response();
+    }
+
+    match state.presence.get_many(&user_ids).await {
+        Ok(presence) => (StatusCode::OK, Json(presence)).into_response(),
+        Err(e) => {
+            tracing::error!("batch presence error: {:?}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db error"}))).into_response()
+        }
+    }
+}
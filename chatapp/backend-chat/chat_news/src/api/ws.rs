@@ -0,0 +1,37 @@
+// src/api/ws.rs
+use axum::{
+    extract::State,
+    Json, http::StatusCode, response::IntoResponse, routing::post, Router,
+};
+use serde::Serialize;
+use std::sync::Arc;
+use crate::AppState;
+use crate::auth::{create_ws_ticket, AuthSession, WS_TICKET_TTL_SECONDS};
+
+#[derive(Serialize)]
+pub struct WsTicketResponse {
+    pub ticket: String,
+    pub expires_in: i64,
+}
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/ws/ticket", post(issue_ticket))
+}
+
+/// POST /ws/ticket — короткоживущий билет для `GET /ws?ticket=...`
+async fn issue_ticket(
+    State(state): State<Arc<AppState>>,
+    AuthSession(auth): AuthSession,
+) -> impl IntoResponse {
+    // Билет не переживает access-токен, по которому выдан
+    let expires_in = WS_TICKET_TTL_SECONDS.min(auth.expires_at - chrono::Utc::now().timestamp());
+
+    match create_ws_ticket(&auth, &state.config.ws_ticket_secret) {
+        Ok(ticket) => (StatusCode::OK, Json(WsTicketResponse { ticket, expires_in })).into_response(),
+        Err(e) => {
+            tracing::error!("ws ticket error: {:?}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"token error"}))).into_response()
+        }
+    }
+}
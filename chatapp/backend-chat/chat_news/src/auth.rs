@@ -2,36 +2,38 @@
 
 use axum::{
     async_trait,
-    extract::{FromRequestParts, State},
+    extract::FromRequestParts,
     http::request::Parts,
     Json,
     response::{IntoResponse, Response},
 };
 use headers::{authorization::Bearer, Authorization, HeaderMapExt};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::{decode, decode_header, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use sha2::{Sha256, Digest}; // ✅ Оставлены: вдруг будут использоваться позже
 use std::sync::Arc;
 
 use crate::AppState;
 
+mod jwks;
+mod revocations;
+pub use jwks::JwksCache;
+pub use revocations::spawn_revocation_listener;
+
+/// Access-токен auth-service. Контракт общий для обоих сервисов
+/// (зеркало `auth-service/src/utils/jwt.rs`): EdDSA, `kid` в заголовке — ключ из JWKS auth-service,
+/// `iss`/`aud` из конфигурации
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
+    pub iss: String,
+    pub aud: String,
     pub sub: Uuid,
+    /// Сессия в таблице `sessions`
     pub sid: Uuid,
     pub exp: i64,
     pub iat: i64,
 }
 
-#[derive(Debug, Serialize)]
-pub struct TokenResponse {
-    pub access_token: String,
-    pub refresh_token: String,
-    pub token_type: &'static str,
-    pub expires_in: i64,
-}
-
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
     pub error: String,
@@ -51,27 +53,26 @@ fn err(msg: &str, details: Option<String>) -> ErrorResponse {
     }
 }
 
-pub fn create_access_token(
-    user_id: Uuid,
-    session_id: Uuid,
-    secret: &str,
-    duration_seconds: i64,
-) -> jsonwebtoken::errors::Result<String> {
-    use chrono::Utc;
+/// Проверяет подпись и стандартные поля access-токена. Токены выпускает только auth-service,
+/// у chat-service есть лишь его публичные ключи
+async fn decode_access_token(state: &AppState, token: &str) -> Result<Claims, ErrorResponse> {
+    let header = decode_header(token).map_err(|e| err("invalid token", Some(e.to_string())))?;
+    let kid = header.kid.ok_or_else(|| err("invalid token", Some("missing key id".into())))?;
+    let key = state
+        .jwks
+        .key(&kid)
+        .await
+        .ok_or_else(|| err("invalid token", Some("unknown key id".into())))?;
 
-    let now = Utc::now().timestamp();
-    let claims = Claims {
-        sub: user_id,
-        sid: session_id,
-        iat: now,
-        exp: now + duration_seconds,
-    };
+    let mut validation = Validation::new(jsonwebtoken::Algorithm::EdDSA);
+    validation.validate_exp = true;
+    validation.set_issuer(&[&state.config.jwt_issuer]);
+    validation.set_audience(&[&state.config.jwt_audience]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
 
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_bytes()),
-    )
+    decode::<Claims>(token, &key, &validation)
+        .map(|data| data.claims)
+        .map_err(|e| err("invalid token", Some(e.to_string())))
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -84,6 +85,170 @@ pub struct CurrentUser {
 
 pub struct AuthUser(pub CurrentUser);
 
+/// Проверенная сессия: пользователь, сессия и момент, когда истекает предъявленный токен
+#[derive(Debug, Clone)]
+pub struct Authenticated {
+    pub user: CurrentUser,
+    pub session_id: Uuid,
+    pub expires_at: i64,
+}
+
+/// Как `AuthUser`, но с данными сессии (нужно, например, для выдачи билета на WebSocket)
+pub struct AuthSession(pub Authenticated);
+
+/// Срок жизни билета на подключение к WebSocket
+pub const WS_TICKET_TTL_SECONDS: i64 = 30;
+
+const WS_TICKET_AUDIENCE: &str = "ws";
+
+/// Билет на WebSocket: браузер не может передать заголовок `Authorization` при апгрейде.
+/// Билет одноразовый: он передаётся в query string и может осесть в логах прокси
+#[derive(Debug, Serialize, Deserialize)]
+struct WsTicketClaims {
+    /// Идентификатор билета, отмечается в `ws_ticket_uses` при подключении
+    jti: Uuid,
+    sub: Uuid,
+    sid: Uuid,
+    aud: String,
+    iat: i64,
+    exp: i64,
+    /// Когда истекает access-токен, по которому выдан билет
+    access_exp: i64,
+}
+
+/// Билеты подписываются собственным секретом chat-service (`WS_TICKET_SECRET`):
+/// общий между репликами, но к access-токенам отношения не имеет
+pub fn create_ws_ticket(auth: &Authenticated, secret: &str) -> jsonwebtoken::errors::Result<String> {
+    let now = chrono::Utc::now().timestamp();
+    let claims = WsTicketClaims {
+        jti: Uuid::new_v4(),
+        sub: auth.user.id,
+        sid: auth.session_id,
+        aud: WS_TICKET_AUDIENCE.to_string(),
+        iat: now,
+        exp: (now + WS_TICKET_TTL_SECONDS).min(auth.expires_at),
+        access_exp: auth.expires_at,
+    };
+
+    encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes()))
+}
+
+/// Активна ли сессия (не отозвана и не истекла)
+pub async fn session_active(state: &AppState, session_id: Uuid, user_id: Uuid) -> Result<bool, sqlx::Error> {
+    let session = sqlx::query!(
+        r#"
+            SELECT user_id FROM sessions
+            WHERE id = $1 AND user_id = $2 AND expires_at > NOW()
+            "#,
+        session_id,
+        user_id
+    )
+    .fetch_optional(&state.postgres_pool)
+    .await?;
+
+    Ok(session.is_some())
+}
+
+/// Есть ли активный пользователь с таким id
+pub async fn user_exists(state: &AppState, user_id: Uuid) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+            SELECT EXISTS (SELECT 1 FROM users WHERE id = $1 AND is_active = TRUE) AS "exists!"
+            "#,
+        user_id
+    )
+    .fetch_one(&state.postgres_pool)
+    .await
+}
+
+/// Проверки сессии и пользователя — общие для REST и WebSocket
+async fn load_session(state: &AppState, user_id: Uuid, session_id: Uuid, expires_at: i64) -> Result<Authenticated, ErrorResponse> {
+    if !session_active(state, session_id, user_id).await.map_err(|_| err("database error", None))? {
+        return Err(err("session not found or expired", None));
+    }
+
+    let user = sqlx::query!(
+        r#"
+            SELECT id, email, name, avatar_url, is_active
+            FROM users
+            WHERE id = $1 AND is_active = TRUE
+            "#,
+        user_id
+    )
+    .fetch_optional(&state.postgres_pool)
+    .await
+    .map_err(|_| err("database error", None))?;
+
+    let user = user.ok_or_else(|| err("user not found or inactive", None))?;
+
+    let current_user = CurrentUser {
+        id: user.id,
+        email: user.email,
+        name: user.name,
+        avatar_url: user.avatar_url,
+    };
+
+    Ok(Authenticated { user: current_user, session_id, expires_at })
+}
+
+/// Проверяет access-токен
+pub async fn authenticate(state: &AppState, token: &str) -> Result<Authenticated, ErrorResponse> {
+    let claims = decode_access_token(state, token).await?;
+    load_session(state, claims.sub, claims.sid, claims.exp).await
+}
+
+/// Проверяет подпись, аудиторию и срок билета
+fn decode_ws_ticket(ticket: &str, secret: &str) -> Result<WsTicketClaims, ErrorResponse> {
+    let mut validation = Validation::new(jsonwebtoken::Algorithm::HS256);
+    validation.validate_exp = true;
+    validation.set_audience(&[WS_TICKET_AUDIENCE]);
+    validation.set_required_spec_claims(&["exp", "aud", "sub"]);
+
+    decode::<WsTicketClaims>(ticket, &DecodingKey::from_secret(secret.as_bytes()), &validation)
+        .map(|data| data.claims)
+        .map_err(|e| err("invalid ticket", Some(e.to_string())))
+}
+
+/// Проверяет билет на WebSocket и гасит его: второе подключение с тем же билетом отклоняется
+pub async fn authenticate_ws_ticket(state: &AppState, ticket: &str) -> Result<Authenticated, ErrorResponse> {
+    let claims = decode_ws_ticket(ticket, &state.config.ws_ticket_secret)?;
+
+    let fresh = state
+        .scylla
+        .consume_ws_ticket(claims.jti)
+        .await
+        .map_err(|_| err("database error", None))?;
+    if !fresh {
+        return Err(err("invalid ticket", Some("ticket already used".into())));
+    }
+
+    load_session(state, claims.sub, claims.sid, claims.access_exp).await
+}
+
+/// Кадр `auth` на WebSocket: подходит и access-токен, и билет
+pub async fn authenticate_any(state: &AppState, token: &str) -> Result<Authenticated, ErrorResponse> {
+    match authenticate(state, token).await {
+        Ok(auth) => Ok(auth),
+        Err(access_err) => authenticate_ws_ticket(state, token).await.map_err(|_| access_err),
+    }
+}
+
+async fn authenticate_parts(parts: &Parts) -> Result<Authenticated, ErrorResponse> {
+    let token = parts
+        .headers
+        .typed_get::<Authorization<Bearer>>()
+        .ok_or_else(|| err("missing bearer token", None))?
+        .token()
+        .to_string();
+
+    let app_state = parts
+        .extensions
+        .get::<Arc<AppState>>()
+        .ok_or_else(|| err("app_state missing", None))?;
+
+    authenticate(app_state, &token).await
+}
+
 #[async_trait]
 impl<S> FromRequestParts<S> for AuthUser
 where
@@ -95,68 +260,120 @@ where
         parts: &mut Parts,
         _state: &S,
     ) -> Result<Self, Self::Rejection> {
-        let token = parts
-            .headers
-            .typed_get::<Authorization<Bearer>>()
-            .ok_or_else(|| err("missing bearer token", None))?
-            .token()
-            .to_string();
-
-        let app_state = parts
-            .extensions
-            .get::<Arc<AppState>>()
-            .ok_or_else(|| err("app_state missing", None))?;
-
-        let mut validation = Validation::new(jsonwebtoken::Algorithm::HS256);
-        validation.validate_exp = true;
-
-        let data = decode::<Claims>(
-            &token,
-            &DecodingKey::from_secret(app_state.config.jwt_secret.as_bytes()),
-            &validation,
-        )
-        .map_err(|e| err("invalid token", Some(e.to_string())))?;
-
-        let user_id = data.claims.sub;
-        let session_id = data.claims.sid;
-
-        let session = sqlx::query!(
-            r#"
-            SELECT user_id FROM sessions
-            WHERE id = $1 AND user_id = $2 AND expires_at > NOW()
-            "#,
-            session_id,
-            user_id
-        )
-        .fetch_optional(&app_state.postgres_pool)
-        .await
-        .map_err(|_| err("database error", None))?;
+        Ok(AuthUser(authenticate_parts(parts).await?.user))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthSession
+where
+    S: Send + Sync,
+{
+    type Rejection = ErrorResponse;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(AuthSession(authenticate_parts(parts).await?))
+    }
+}
 
-        if session.is_none() {
-            return Err(err("session not found or expired", None));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "test-ws-ticket-secret";
+
+    fn session(expires_at: i64) -> Authenticated {
+        Authenticated {
+            user: CurrentUser {
+                id: Uuid::new_v4(),
+                email: "user@example.com".into(),
+                name: None,
+                avatar_url: None,
+            },
+            session_id: Uuid::new_v4(),
+            expires_at,
         }
+    }
 
-        let user = sqlx::query!(
-            r#"
-            SELECT id, email, name, avatar_url, is_active
-            FROM users
-            WHERE id = $1 AND is_active = TRUE
-            "#,
-            user_id
-        )
-        .fetch_optional(&app_state.postgres_pool)
-        .await
-        .map_err(|_| err("database error", None))?;
+    fn sign(claims: &WsTicketClaims, secret: &str) -> String {
+        encode(&Header::default(), claims, &EncodingKey::from_secret(secret.as_bytes())).unwrap()
+    }
 
-        let user = user.ok_or_else(|| err("user not found or inactive", None))?;
+    #[test]
+    fn ticket_round_trip() {
+        let auth = session(chrono::Utc::now().timestamp() + 900);
+        let ticket = create_ws_ticket(&auth, SECRET).unwrap();
 
-        let current_user = CurrentUser {
-            id: user.id,
-            email: user.email,
-            name: user.name,
-            avatar_url: user.avatar_url,
+        let claims = decode_ws_ticket(&ticket, SECRET).unwrap();
+        assert_eq!(claims.sub, auth.user.id);
+        assert_eq!(claims.sid, auth.session_id);
+        assert_eq!(claims.access_exp, auth.expires_at);
+        assert_eq!(claims.exp, claims.iat + WS_TICKET_TTL_SECONDS);
+    }
+
+    #[test]
+    fn every_ticket_gets_its_own_jti() {
+        let auth = session(chrono::Utc::now().timestamp() + 900);
+        let first = decode_ws_ticket(&create_ws_ticket(&auth, SECRET).unwrap(), SECRET).unwrap();
+        let second = decode_ws_ticket(&create_ws_ticket(&auth, SECRET).unwrap(), SECRET).unwrap();
+
+        assert_ne!(first.jti, second.jti);
+    }
+
+    #[test]
+    fn ticket_does_not_outlive_access_token() {
+        let expires_at = chrono::Utc::now().timestamp() + 5;
+        let ticket = create_ws_ticket(&session(expires_at), SECRET).unwrap();
+
+        assert_eq!(decode_ws_ticket(&ticket, SECRET).unwrap().exp, expires_at);
+    }
+
+    #[test]
+    fn rejects_foreign_secret() {
+        let auth = session(chrono::Utc::now().timestamp() + 900);
+        let ticket = create_ws_ticket(&auth, "another-secret").unwrap();
+
+        assert_eq!(decode_ws_ticket(&ticket, SECRET).unwrap_err().error, "invalid ticket");
+    }
+
+    #[test]
+    fn rejects_expired_ticket() {
+        // Больше стандартного допуска jsonwebtoken (60 секунд)
+        let now = chrono::Utc::now().timestamp() - 300;
+        let claims = WsTicketClaims {
+            jti: Uuid::new_v4(),
+            sub: Uuid::new_v4(),
+            sid: Uuid::new_v4(),
+            aud: WS_TICKET_AUDIENCE.to_string(),
+            iat: now,
+            exp: now + WS_TICKET_TTL_SECONDS,
+            access_exp: now + 900,
         };
 
-        Ok(AuthUser(current_user))
+        assert!(decode_ws_ticket(&sign(&claims, SECRET), SECRET).is_err());
+    }
+
+    #[test]
+    fn rejects_other_audience() {
+        let now = chrono::Utc::now().timestamp();
+        let claims = WsTicketClaims {
+            jti: Uuid::new_v4(),
+            sub: Uuid::new_v4(),
+            sid: Uuid::new_v4(),
+            aud: "chat-api".to_string(),
+            iat: now,
+            exp: now + WS_TICKET_TTL_SECONDS,
+            access_exp: now + 900,
+        };
+
+        assert!(decode_ws_ticket(&sign(&claims, SECRET), SECRET).is_err());
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(decode_ws_ticket("not-a-jwt", SECRET).is_err());
     }
 }
@@ -0,0 +1,112 @@
+// src/auth/jwks.rs
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, JwkSet};
+use jsonwebtoken::DecodingKey;
+use tokio::sync::{Mutex, RwLock};
+
+/// Не чаще этого перезапрашиваем JWKS из-за незнакомого `kid`
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Кэш публичных ключей auth-service (`/.well-known/jwks.json`).
+/// Обновляется по таймеру и при встрече незнакомого `kid` — так ротация ключа
+/// не требует перезапуска chat-service
+pub struct JwksCache {
+    url: String,
+    http: reqwest::Client,
+    keys: RwLock<HashMap<String, DecodingKey>>,
+    last_fetch: Mutex<Option<Instant>>,
+}
+
+impl JwksCache {
+    pub fn new(url: &str) -> Arc<Self> {
+        Arc::new(Self {
+            url: url.to_string(),
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()
+                .expect("reqwest client"),
+            keys: RwLock::new(HashMap::new()),
+            last_fetch: Mutex::new(None),
+        })
+    }
+
+    /// Загружает набор ключей и целиком заменяет кэш: ключи, убранные из JWKS, перестают приниматься
+    pub async fn refresh(&self) -> Result<()> {
+        *self.last_fetch.lock().await = Some(Instant::now());
+
+        let set: JwkSet = self
+            .http
+            .get(&self.url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .with_context(|| format!("Failed to fetch JWKS from {}", self.url))?
+            .json()
+            .await
+            .context("Invalid JWKS")?;
+
+        let mut keys = HashMap::new();
+        for jwk in &set.keys {
+            // Принимаем только Ed25519: симметричный ключ из JWKS позволил бы подделать токен
+            let AlgorithmParameters::OctetKeyPair(params) = &jwk.algorithm else { continue };
+            if params.curve != EllipticCurve::Ed25519 {
+                continue;
+            }
+            let Some(kid) = &jwk.common.key_id else { continue };
+
+            match DecodingKey::from_ed_components(&params.x) {
+                Ok(key) => {
+                    keys.insert(kid.clone(), key);
+                }
+                Err(e) => tracing::warn!(kid = %kid, "skipping invalid JWK: {:?}", e),
+            }
+        }
+
+        tracing::debug!(count = keys.len(), "JWKS refreshed");
+        *self.keys.write().await = keys;
+        Ok(())
+    }
+
+    /// Ключ по `kid`. Незнакомый `kid` — повод перечитать JWKS (ключ могли только что добавить)
+    pub async fn key(&self, kid: &str) -> Option<DecodingKey> {
+        if let Some(key) = self.keys.read().await.get(kid) {
+            return Some(key.clone());
+        }
+
+        let recently_fetched = self
+            .last_fetch
+            .lock()
+            .await
+            .is_some_and(|at| at.elapsed() < MIN_REFETCH_INTERVAL);
+        if recently_fetched {
+            return None;
+        }
+
+        if let Err(e) = self.refresh().await {
+            tracing::warn!("JWKS refresh failed: {:?}", e);
+            return None;
+        }
+
+        self.keys.read().await.get(kid).cloned()
+    }
+
+    /// Периодическое обновление в фоне
+    pub fn spawn_refresher(self: &Arc<Self>, interval: Duration) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = this.refresh().await {
+                    tracing::warn!("JWKS refresh failed: {:?}", e);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,47 @@
+// src/auth/revocations.rs
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::websocket::manager::{ConnectionManager, RoomControl};
+
+/// Канал, в который auth-service (триггер на `sessions`) публикует id удалённой сессии
+const SESSION_REVOKED_CHANNEL: &str = "session_revoked";
+
+/// Пауза перед повторным подключением слушателя
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Закрывает WebSocket-соединения отозванных сессий сразу, не дожидаясь периодической проверки.
+/// Уведомления, пропущенные при обрыве связи с базой, подберёт та же периодическая проверка
+pub fn spawn_revocation_listener(pool: PgPool, manager: Arc<ConnectionManager>) {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = listen(&pool, &manager).await {
+                tracing::warn!("Session revocation listener failed: {:?}", e);
+            }
+            tokio::time::sleep(RETRY_DELAY).await;
+        }
+    });
+}
+
+async fn listen(pool: &PgPool, manager: &ConnectionManager) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(SESSION_REVOKED_CHANNEL).await?;
+
+    loop {
+        let notification = listener.recv().await?;
+        let Ok(session_id) = notification.payload().parse::<Uuid>() else {
+            tracing::warn!("Invalid session_revoked payload: {}", notification.payload());
+            continue;
+        };
+
+        let closed = manager.control_session(session_id, RoomControl::SessionRevoked(session_id)).await;
+        if closed > 0 {
+            tracing::info!("Session {} revoked, closing {} connection(s)", session_id, closed);
+        }
+    }
+}
@@ -0,0 +1,523 @@
+// src/db/chats.rs
+
+use std::sync::Arc;
+use scylla::{Session, IntoTypedRows, prepared_statement::PreparedStatement};
+
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use anyhow::{Result, Context};
+use serde::{Serialize, Deserialize};
+
+use crate::db::reactions::lwt_applied;
+
+/// Тип чата
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatKind {
+    Direct,
+    Group,
+}
+
+impl ChatKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChatKind::Direct => "direct",
+            ChatKind::Group => "group",
+        }
+    }
+
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "direct" => ChatKind::Direct,
+            _ => ChatKind::Group,
+        }
+    }
+}
+
+/// Роль участника в чате
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MemberRole {
+    Owner,
+    Admin,
+    Member,
+}
+
+impl MemberRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MemberRole::Owner => "owner",
+            MemberRole::Admin => "admin",
+            MemberRole::Member => "member",
+        }
+    }
+
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "owner" => MemberRole::Owner,
+            "admin" => MemberRole::Admin,
+            _ => MemberRole::Member,
+        }
+    }
+
+    /// Может ли участник управлять составом и настройками чата
+    pub fn can_manage(&self) -> bool {
+        matches!(self, MemberRole::Owner | MemberRole::Admin)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chat {
+    pub chat_id: Uuid,
+    pub kind: ChatKind,
+    pub title: Option<String>,
+    pub avatar_url: Option<String>,
+    /// Может отсутствовать у старых записей
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMember {
+    pub chat_id: Uuid,
+    pub user_id: Uuid,
+    pub role: MemberRole,
+    pub joined_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum ChatError {
+    ChatNotFound,
+    UserNotFound,
+    PermissionDenied,
+    InvalidOperation(&'static str),
+    InternalError(anyhow::Error),
+}
+
+impl From<anyhow::Error> for ChatError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::InternalError(e)
+    }
+}
+
+#[derive(Clone)]
+pub struct ChatsDb {
+    pub session: Arc<Session>,
+
+    // Чаты
+    insert_chat_stmt: PreparedStatement,
+    delete_chat_stmt: PreparedStatement,
+    get_chat_stmt: PreparedStatement,
+    update_title_stmt: PreparedStatement,
+    update_avatar_stmt: PreparedStatement,
+
+    // Участники
+    insert_member_stmt: PreparedStatement,
+    insert_user_chat_stmt: PreparedStatement,
+    delete_member_stmt: PreparedStatement,
+    delete_user_chat_stmt: PreparedStatement,
+    get_members_stmt: PreparedStatement,
+    get_member_stmt: PreparedStatement,
+
+    // Личные чаты
+    insert_direct_stmt: PreparedStatement,
+    get_direct_stmt: PreparedStatement,
+}
+
+impl ChatsDb {
+    pub async fn new(session: Arc<Session>) -> Result<Self> {
+        let insert_chat_stmt = session.prepare(
+            "INSERT INTO chats (chat_id, kind, title, avatar_url, created_by, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?)"
+        ).await.context("prepare insert_chat")?;
+
+        let delete_chat_stmt = session.prepare(
+            "DELETE FROM chats WHERE chat_id = ?"
+        ).await.context("prepare delete_chat")?;
+
+        let get_chat_stmt = session.prepare(
+            "SELECT chat_id, kind, title, avatar_url, created_by, created_at, updated_at FROM chats WHERE chat_id = ?"
+        ).await.context("prepare get_chat")?;
+
+        let update_title_stmt = session.prepare(
+            "UPDATE chats SET title = ?, updated_at = ? WHERE chat_id = ?"
+        ).await.context("prepare update_title")?;
+
+        let update_avatar_stmt = session.prepare(
+            "UPDATE chats SET avatar_url = ?, updated_at = ? WHERE chat_id = ?"
+        ).await.context("prepare update_avatar")?;
+
+        let insert_member_stmt = session.prepare(
+            "INSERT INTO chat_members (chat_id, user_id, role, joined_at) VALUES (?, ?, ?, ?)"
+        ).await.context("prepare insert_member")?;
+
+        let insert_user_chat_stmt = session.prepare(
+            "INSERT INTO user_chats (user_id, chat_id, joined_at) VALUES (?, ?, ?)"
+        ).await.context("prepare insert_user_chat")?;
+
+        let delete_member_stmt = session.prepare(
+            "DELETE FROM chat_members WHERE chat_id = ? AND user_id = ?"
+        ).await.context("prepare delete_member")?;
+
+        let delete_user_chat_stmt = session.prepare(
+            "DELETE FROM user_chats WHERE user_id = ? AND chat_id = ?"
+        ).await.context("prepare delete_user_chat")?;
+
+        let get_members_stmt = session.prepare(
+            "SELECT chat_id, user_id, role, joined_at FROM chat_members WHERE chat_id = ?"
+        ).await.context("prepare get_members")?;
+
+        let get_member_stmt = session.prepare(
+            "SELECT chat_id, user_id, role, joined_at FROM chat_members WHERE chat_id = ? AND user_id = ?"
+        ).await.context("prepare get_member")?;
+
+        let insert_direct_stmt = session.prepare(
+            "INSERT INTO direct_chats (user_low, user_high, chat_id) VALUES (?, ?, ?) IF NOT EXISTS"
+        ).await.context("prepare insert_direct")?;
+
+        let get_direct_stmt = session.prepare(
+            "SELECT chat_id FROM direct_chats WHERE user_low = ? AND user_high = ?"
+        ).await.context("prepare get_direct")?;
+
+        Ok(Self {
+            session,
+
+            insert_chat_stmt,
+            delete_chat_stmt,
+            get_chat_stmt,
+            update_title_stmt,
+            update_avatar_stmt,
+            insert_member_stmt,
+            insert_user_chat_stmt,
+            delete_member_stmt,
+            delete_user_chat_stmt,
+            get_members_stmt,
+            get_member_stmt,
+            insert_direct_stmt,
+            get_direct_stmt,
+        })
+    }
+
+    pub async fn get_chat(&self, chat_id: Uuid) -> Result<Option<Chat>> {
+        let qr = self.session.execute(&self.get_chat_stmt, (chat_id,))
+            .await.context("query chat by id")?;
+
+        if let Some(rows) = qr.rows {
+            if let Some(row) = rows.into_typed::<(
+                Uuid, Option<String>, Option<String>, Option<String>, Option<Uuid>, Option<DateTime<Utc>>, Option<DateTime<Utc>>
+            )>().next() {
+                let (chat_id, kind, title, avatar_url, created_by, created_at, updated_at) = row?;
+                let created_at = created_at.unwrap_or_else(Utc::now);
+                return Ok(Some(Chat {
+                    chat_id,
+                    kind: ChatKind::parse(kind.as_deref().unwrap_or("group")),
+                    title,
+                    avatar_url,
+                    created_by,
+                    created_at,
+                    updated_at: updated_at.unwrap_or(created_at),
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub async fn get_members(&self, chat_id: Uuid) -> Result<Vec<ChatMember>> {
+        let qr = self.session.execute(&self.get_members_stmt, (chat_id,))
+            .await.context("query chat members")?;
+
+        let mut members = Vec::new();
+        if let Some(rows) = qr.rows {
+            for row in rows.into_typed::<(Uuid, Uuid, Option<String>, Option<DateTime<Utc>>)>() {
+                let (chat_id, user_id, role, joined_at) = row?;
+                members.push(ChatMember {
+                    chat_id,
+                    user_id,
+                    role: MemberRole::parse(role.as_deref().unwrap_or("member")),
+                    joined_at: joined_at.unwrap_or_else(Utc::now),
+                });
+            }
+        }
+
+        Ok(members)
+    }
+
+    pub async fn get_member(&self, chat_id: Uuid, user_id: Uuid) -> Result<Option<ChatMember>> {
+        let qr = self.session.execute(&self.get_member_stmt, (chat_id, user_id))
+            .await.context("query chat member")?;
+
+        if let Some(rows) = qr.rows {
+            if let Some(row) = rows.into_typed::<(Uuid, Uuid, Option<String>, Option<DateTime<Utc>>)>().next() {
+                let (chat_id, user_id, role, joined_at) = row?;
+                return Ok(Some(ChatMember {
+                    chat_id,
+                    user_id,
+                    role: MemberRole::parse(role.as_deref().unwrap_or("member")),
+                    joined_at: joined_at.unwrap_or_else(Utc::now),
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Создаёт групповой чат; создатель становится владельцем.
+    /// `member_ids` проверяет вызывающий: без повторов и только существующие пользователи
+    pub async fn create_group_chat(
+        &self,
+        creator: Uuid,
+        title: String,
+        avatar_url: Option<String>,
+        member_ids: &[Uuid],
+    ) -> Result<Chat> {
+        let now = Utc::now();
+        let chat = Chat {
+            chat_id: Uuid::new_v4(),
+            kind: ChatKind::Group,
+            title: Some(title),
+            avatar_url,
+            created_by: Some(creator),
+            created_at: now,
+            updated_at: now,
+        };
+
+        self.insert_chat(&chat).await?;
+        self.insert_member(chat.chat_id, creator, MemberRole::Owner).await?;
+
+        for user_id in member_ids {
+            if *user_id != creator {
+                self.insert_member(chat.chat_id, *user_id, MemberRole::Member).await?;
+            }
+        }
+
+        Ok(chat)
+    }
+
+    /// Возвращает личный чат пары пользователей, создавая его при первом обращении.
+    /// Что `peer_id` — существующий пользователь, проверяет вызывающий
+    pub async fn get_or_create_direct_chat(&self, user_id: Uuid, peer_id: Uuid) -> Result<Chat, ChatError> {
+        if user_id == peer_id {
+            return Err(ChatError::InvalidOperation("cannot start a direct chat with yourself"));
+        }
+
+        let (low, high) = if user_id < peer_id { (user_id, peer_id) } else { (peer_id, user_id) };
+
+        if let Some(chat_id) = self.get_direct_chat_id(low, high).await? {
+            return self.ensure_direct_chat(chat_id, user_id, peer_id).await;
+        }
+
+        // Сначала чат и участники, затем привязка пары: привязка никогда не указывает
+        // на недописанный чат, даже если запись оборвётся на середине
+        let chat = Self::new_direct_chat(Uuid::new_v4(), user_id);
+        self.insert_direct_chat(&chat, user_id, peer_id).await?;
+
+        // LWT защищает от гонки двух одновременных запросов на создание
+        let qr = self.session.execute(&self.insert_direct_stmt, (low, high, chat.chat_id))
+            .await
+            .context("insert direct chat")?;
+
+        if lwt_applied(&qr) {
+            return Ok(chat);
+        }
+
+        // Гонку выиграл другой запрос: убираем свой черновик и отдаём его чат
+        self.delete_member(chat.chat_id, user_id).await?;
+        self.delete_member(chat.chat_id, peer_id).await?;
+        self.session.execute(&self.delete_chat_stmt, (chat.chat_id,))
+            .await
+            .context("delete orphaned direct chat")?;
+
+        let winner = self.get_direct_chat_id(low, high)
+            .await?
+            .ok_or(ChatError::ChatNotFound)?;
+
+        self.ensure_direct_chat(winner, user_id, peer_id).await
+    }
+
+    /// Чат, на который указывает привязка пары. Привязки, записанные раньше самого чата
+    /// (так работала прежняя версия), долечиваются: чат и участники создаются заново
+    async fn ensure_direct_chat(&self, chat_id: Uuid, user_id: Uuid, peer_id: Uuid) -> Result<Chat, ChatError> {
+        if let Some(chat) = self.get_chat(chat_id).await? {
+            return Ok(chat);
+        }
+
+        tracing::warn!(chat_id = %chat_id, "direct chat mapping without chat row, recreating");
+        let chat = Self::new_direct_chat(chat_id, user_id);
+        self.insert_direct_chat(&chat, user_id, peer_id).await?;
+        Ok(chat)
+    }
+
+    fn new_direct_chat(chat_id: Uuid, created_by: Uuid) -> Chat {
+        let now = Utc::now();
+        Chat {
+            chat_id,
+            kind: ChatKind::Direct,
+            title: None,
+            avatar_url: None,
+            created_by: Some(created_by),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    async fn insert_direct_chat(&self, chat: &Chat, user_id: Uuid, peer_id: Uuid) -> Result<()> {
+        self.insert_chat(chat).await?;
+        self.insert_member(chat.chat_id, user_id, MemberRole::Member).await?;
+        self.insert_member(chat.chat_id, peer_id, MemberRole::Member).await?;
+        Ok(())
+    }
+
+    /// Добавляет участников в групповой чат (только owner/admin)
+    pub async fn add_members(&self, chat_id: Uuid, requester: Uuid, user_ids: &[Uuid]) -> Result<Vec<Uuid>, ChatError> {
+        let chat = self.get_group_chat(chat_id).await?;
+        self.require_manager(chat.chat_id, requester).await?;
+
+        let mut added = Vec::new();
+        for user_id in user_ids {
+            if self.get_member(chat_id, *user_id).await?.is_some() {
+                continue;
+            }
+            self.insert_member(chat_id, *user_id, MemberRole::Member).await?;
+            added.push(*user_id);
+        }
+
+        Ok(added)
+    }
+
+    /// Удаляет участника из группового чата (только owner/admin, владельца удалить нельзя)
+    pub async fn remove_member(&self, chat_id: Uuid, requester: Uuid, user_id: Uuid) -> Result<(), ChatError> {
+        if requester == user_id {
+            return self.leave_chat(chat_id, user_id).await;
+        }
+
+        let chat = self.get_group_chat(chat_id).await?;
+        self.require_manager(chat.chat_id, requester).await?;
+
+        let member = self.get_member(chat_id, user_id)
+            .await?
+            .ok_or(ChatError::InvalidOperation("user is not a member of this chat"))?;
+
+        if member.role == MemberRole::Owner {
+            return Err(ChatError::PermissionDenied);
+        }
+
+        self.delete_member(chat_id, user_id).await?;
+        Ok(())
+    }
+
+    /// Выход из группового чата. Если уходит владелец — владение передаётся
+    /// самому давнему из оставшихся участников
+    pub async fn leave_chat(&self, chat_id: Uuid, user_id: Uuid) -> Result<(), ChatError> {
+        self.get_group_chat(chat_id).await?;
+
+        let member = self.get_member(chat_id, user_id)
+            .await?
+            .ok_or(ChatError::InvalidOperation("user is not a member of this chat"))?;
+
+        self.delete_member(chat_id, user_id).await?;
+
+        if member.role == MemberRole::Owner {
+            let mut rest = self.get_members(chat_id).await?;
+            rest.sort_by_key(|m| m.joined_at);
+            if let Some(next_owner) = rest.first() {
+                self.session.execute(
+                    &self.insert_member_stmt,
+                    (chat_id, next_owner.user_id, MemberRole::Owner.as_str(), next_owner.joined_at),
+                ).await.context("transfer ownership")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Переименовывает групповой чат (только owner/admin)
+    pub async fn rename_chat(&self, chat_id: Uuid, requester: Uuid, title: String) -> Result<(), ChatError> {
+        self.get_group_chat(chat_id).await?;
+        self.require_manager(chat_id, requester).await?;
+
+        self.session.execute(&self.update_title_stmt, (title, Utc::now(), chat_id))
+            .await
+            .context("update chat title")?;
+
+        Ok(())
+    }
+
+    /// Устанавливает (или сбрасывает) аватар группового чата (только owner/admin)
+    pub async fn set_avatar(&self, chat_id: Uuid, requester: Uuid, avatar_url: Option<String>) -> Result<(), ChatError> {
+        self.get_group_chat(chat_id).await?;
+        self.require_manager(chat_id, requester).await?;
+
+        self.session.execute(&self.update_avatar_stmt, (avatar_url, Utc::now(), chat_id))
+            .await
+            .context("update chat avatar")?;
+
+        Ok(())
+    }
+
+    async fn get_group_chat(&self, chat_id: Uuid) -> Result<Chat, ChatError> {
+        let chat = self.get_chat(chat_id).await?.ok_or(ChatError::ChatNotFound)?;
+        if chat.kind == ChatKind::Direct {
+            return Err(ChatError::InvalidOperation("direct chats cannot be modified"));
+        }
+        Ok(chat)
+    }
+
+    async fn require_manager(&self, chat_id: Uuid, user_id: Uuid) -> Result<(), ChatError> {
+        match self.get_member(chat_id, user_id).await? {
+            Some(member) if member.role.can_manage() => Ok(()),
+            Some(_) => Err(ChatError::PermissionDenied),
+            None => Err(ChatError::ChatNotFound),
+        }
+    }
+
+    async fn get_direct_chat_id(&self, low: Uuid, high: Uuid) -> Result<Option<Uuid>> {
+        let qr = self.session.execute(&self.get_direct_stmt, (low, high))
+            .await.context("query direct chat")?;
+
+        if let Some(rows) = qr.rows {
+            if let Some(row) = rows.into_typed::<(Uuid,)>().next() {
+                let (chat_id,) = row?;
+                return Ok(Some(chat_id));
+            }
+        }
+
+        Ok(None)
+    }
+
+    async fn insert_chat(&self, chat: &Chat) -> Result<()> {
+        self.session.execute(&self.insert_chat_stmt, (
+            chat.chat_id,
+            chat.kind.as_str(),
+            chat.title.as_deref(),
+            chat.avatar_url.as_deref(),
+            chat.created_by,
+            chat.created_at,
+            chat.updated_at,
+        )).await.context("insert chat")?;
+
+        Ok(())
+    }
+
+    async fn insert_member(&self, chat_id: Uuid, user_id: Uuid, role: MemberRole) -> Result<()> {
+        let now = Utc::now();
+
+        self.session.execute(&self.insert_member_stmt, (chat_id, user_id, role.as_str(), now))
+            .await.context("insert chat member")?;
+
+        self.session.execute(&self.insert_user_chat_stmt, (user_id, chat_id, now))
+            .await.context("insert user chat")?;
+
+        Ok(())
+    }
+
+    async fn delete_member(&self, chat_id: Uuid, user_id: Uuid) -> Result<()> {
+        self.session.execute(&self.delete_member_stmt, (chat_id, user_id))
+            .await.context("delete chat member")?;
+
+        self.session.execute(&self.delete_user_chat_stmt, (user_id, chat_id))
+            .await.context("delete user chat")?;
+
+        Ok(())
+    }
+}
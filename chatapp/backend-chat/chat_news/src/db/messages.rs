@@ -1,6 +1,6 @@
 // src/db/messages.rs
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use scylla::{
     Session, SessionBuilder, IntoTypedRows, prepared_statement::PreparedStatement, 
@@ -13,8 +13,12 @@ use anyhow::{Result, Context};
 use tracing::debug;
 use serde::{Serialize, Deserialize};
 use scylla::transport::errors::QueryError;
+use scylla::frame::value::ValueList;
+use futures_util::{Stream, StreamExt, future::join_all};
 
-use crate::models::ChatEvent;
+use crate::models::{ChatEvent, ServerEvent};
+use crate::db::reactions::{ReactionsDb, Reaction, ReactionError, lwt_applied};
+use crate::db::threads::ThreadsDb;
 
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,6 +35,17 @@ pub struct Message {
     pub edited_at: Option<DateTime<Utc>>,
     pub edited_by: Option<Uuid>,
     pub version: i64,
+    #[serde(default)]
+    pub reply_to: Option<Uuid>,
+    #[serde(default)]
+    pub thread_root_id: Option<Uuid>,
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+    // Сводка по ответам (заполняется только у корня треда)
+    #[serde(default)]
+    pub reply_count: i64,
+    #[serde(default)]
+    pub last_reply_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,10 +59,13 @@ pub struct MessageEdit {
     pub meta: Option<HashMap<String, String>>,
 }
 
+/// Ошибки изменения сообщения: правка, медиа, удаление и восстановление
 #[derive(Debug)]
 pub enum DeleteError {
     MessageNotFound,
     PermissionDenied,
+    /// Сообщение успели изменить параллельно — версия уже не та, что была прочитана
+    Conflict,
     InternalError(anyhow::Error),
 }
 
@@ -62,26 +80,40 @@ pub struct ScyllaDb {
     pub session: Arc<Session>,
     pub keyspace: String,
 
+    // Реакции
+    pub reactions: ReactionsDb,
+
+    // Треды
+    pub threads: ThreadsDb,
+
     // Вставка
     insert_stmt: PreparedStatement,
     insert_by_id_stmt: PreparedStatement,
+    insert_thread_stmt: PreparedStatement,
 
     // Чтение
     get_by_chat_stmt: PreparedStatement,
+    get_by_thread_stmt: PreparedStatement,
     get_by_id_stmt: PreparedStatement,
+    scan_by_id_stmt: PreparedStatement,
 
-    // Редактирование
+    // Изменения строки messages_by_id условные (`IF version = ?`): она решает,
+    // чья правка прошла, ленты чата и треда получают уже принятую версию
     update_edit_stmt: PreparedStatement,
+    update_edit_thread_stmt: PreparedStatement,
     update_edit_by_id_stmt: PreparedStatement,
 
     // Медиа
     attach_media_stmt: PreparedStatement,
+    attach_media_thread_stmt: PreparedStatement,
     attach_media_by_id_stmt: PreparedStatement,
 
     // Удаление
     soft_delete_stmt: PreparedStatement,
+    soft_delete_thread_stmt: PreparedStatement,
     soft_delete_by_id_stmt: PreparedStatement,
     restore_stmt: PreparedStatement,
+    restore_thread_stmt: PreparedStatement,
     restore_by_id_stmt: PreparedStatement,
 
     // История правок
@@ -93,10 +125,23 @@ pub struct ScyllaDb {
     hard_delete_by_id_stmt: PreparedStatement,
     hard_delete_edits_stmt: PreparedStatement,
     
+    // Журнал изменений для resync
+    insert_change_stmt: PreparedStatement,
+    changes_since_stmt: PreparedStatement,
+
+    // Догонка поискового индекса после простоя
+    scan_chat_ids_stmt: PreparedStatement,
+    scan_thread_roots_stmt: PreparedStatement,
+    created_since_stmt: PreparedStatement,
+    thread_created_since_stmt: PreparedStatement,
+
     // User chats
     get_user_chats_stmt: PreparedStatement,
     check_user_in_chat_stmt: PreparedStatement,
 
+    // Одноразовые билеты на WebSocket
+    consume_ws_ticket_stmt: PreparedStatement,
+
 }
 
 #[derive(Debug)]
@@ -119,6 +164,46 @@ impl From<anyhow::Error> for ScyllaError {
 }
 
 impl Message {
+    /// Событие `message.edited` для уже применённой правки
+    pub fn edited_event(&self) -> ServerEvent {
+        ServerEvent::MessageEdited {
+            message_id: self.message_id,
+            content: self.content.clone(),
+            edited_by: self.edited_by.unwrap_or(self.user_id),
+            edited_at: self.edited_at.unwrap_or_else(Utc::now),
+            version: self.version,
+        }
+    }
+
+    /// Событие `message.deleted` для уже применённого мягкого удаления
+    pub fn deleted_event(&self, deleted_by: Uuid) -> ServerEvent {
+        ServerEvent::MessageDeleted {
+            message_id: self.message_id,
+            deleted_by,
+            deleted_at: self.deleted_at.unwrap_or_else(Utc::now),
+            version: self.version,
+        }
+    }
+
+    /// Событие `message.media_attached` для уже добавленных медиа
+    pub fn media_attached_event(&self) -> ServerEvent {
+        ServerEvent::MessageMediaAttached {
+            message_id: self.message_id,
+            media_urls: self.media_urls.clone().unwrap_or_default(),
+            media_meta: self.media_meta.clone().unwrap_or_default(),
+            version: self.version,
+        }
+    }
+
+    /// Событие `message.restored` для уже применённого восстановления
+    pub fn restored_event(&self, restored_by: Uuid) -> ServerEvent {
+        ServerEvent::MessageRestored {
+            message_id: self.message_id,
+            restored_by,
+            version: self.version,
+        }
+    }
+
     pub fn from_chat_event(ev: ChatEvent) -> Self {
         let media_meta = match ev.media_meta {
             Some(value) => {
@@ -150,10 +235,56 @@ impl Message {
             edited_at: ev.edited_at,
             edited_by: ev.edited_by,
             version: ev.version.map(|v| v as i64).unwrap_or(0),
+            reply_to: ev.reply_to,
+            thread_root_id: ev.thread_root_id,
+            reactions: Vec::new(),
+            reply_count: 0,
+            last_reply_at: None,
         }
     }
 }
 
+/// Строка сообщения из ленты чата или треда
+type MessageRow = (
+    Uuid, DateTime<Utc>, Uuid, Uuid, Option<String>, Option<Vec<String>>, Option<HashMap<String, String>>,
+    Option<bool>, Option<DateTime<Utc>>, Option<DateTime<Utc>>, Option<Uuid>, Option<i64>, Option<Uuid>, Option<Uuid>,
+);
+
+fn message_from_row(row: MessageRow) -> Message {
+    let (
+        chat_id, created_at, message_id, user_id, content, media_urls, media_meta,
+        is_deleted_opt, deleted_at, edited_at, edited_by, version_opt, reply_to, thread_root_id,
+    ) = row;
+
+    Message {
+        chat_id,
+        created_at,
+        message_id,
+        user_id,
+        content,
+        media_urls,
+        media_meta,
+        is_deleted: is_deleted_opt.unwrap_or(false),
+        deleted_at,
+        edited_at,
+        edited_by,
+        version: version_opt.unwrap_or(0),
+        reply_to,
+        thread_root_id,
+        reactions: Vec::new(),
+        reply_count: 0,
+        last_reply_at: None,
+    }
+}
+
+/// Ответы хранятся в ленте треда, остальные сообщения — в ленте чата.
+/// Возвращает выражение и ключ партиции для строки сообщения
+fn timeline_target<'a>(msg: &Message, main: &'a PreparedStatement, thread: &'a PreparedStatement) -> (&'a PreparedStatement, Uuid) {
+    match msg.thread_root_id {
+        Some(root) => (thread, root),
+        None => (main, msg.chat_id),
+    }
+}
 
 impl ScyllaDb {
     pub async fn connect(nodes: &[String], keyspace: &str) -> Result<Self> {
@@ -170,54 +301,85 @@ impl ScyllaDb {
 
         let insert_stmt = arc.prepare(
             "INSERT INTO messages (chat_id, created_at, message_id, user_id, content, media_urls, media_meta, is_deleted, deleted_at, edited_at, edited_by, version) \
-            VALUES (?, ?, ?, ?, ?, ?, ?, false, null, null, null, 0)"
+            VALUES (?, ?, ?, ?, ?, ?, ?, false, null, null, null, 0) IF NOT EXISTS"
         ).await.context("prepare insert")?;
 
         let insert_by_id_stmt = arc.prepare(
-            "INSERT INTO messages_by_id (message_id, chat_id, created_at, user_id, content, media_urls, media_meta, is_deleted, deleted_at, edited_at, edited_by, version) \
-            VALUES (?, ?, ?, ?, ?, ?, ?, false, null, null, null, 0)"
+            "INSERT INTO messages_by_id (message_id, chat_id, created_at, user_id, content, media_urls, media_meta, is_deleted, deleted_at, edited_at, edited_by, version, reply_to, thread_root_id) \
+            VALUES (?, ?, ?, ?, ?, ?, ?, false, null, null, null, 0, ?, ?) IF NOT EXISTS"
         ).await.context("prepare insert_by_id")?;
 
+        let insert_thread_stmt = arc.prepare(
+            "INSERT INTO thread_messages (thread_root_id, created_at, message_id, chat_id, user_id, content, media_urls, media_meta, is_deleted, deleted_at, edited_at, edited_by, version, reply_to) \
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, false, null, null, null, 0, ?) IF NOT EXISTS"
+        ).await.context("prepare insert_thread")?;
+
         let get_by_chat_stmt = arc.prepare(
-            "SELECT chat_id, created_at, message_id, user_id, content, media_urls, media_meta, is_deleted, deleted_at, edited_at, edited_by, version \
+            "SELECT chat_id, created_at, message_id, user_id, content, media_urls, media_meta, is_deleted, deleted_at, edited_at, edited_by, version, reply_to, thread_root_id \
             FROM messages WHERE chat_id = ? LIMIT ?"
         ).await.context("prepare get_by_chat")?;
 
+        let get_by_thread_stmt = arc.prepare(
+            "SELECT chat_id, created_at, message_id, user_id, content, media_urls, media_meta, is_deleted, deleted_at, edited_at, edited_by, version, reply_to, thread_root_id \
+            FROM thread_messages WHERE thread_root_id = ? LIMIT ?"
+        ).await.context("prepare get_by_thread")?;
+
         let get_by_id_stmt = arc.prepare(
-            "SELECT message_id, chat_id, created_at, user_id, content, media_urls, media_meta, is_deleted, deleted_at, edited_at, edited_by, version \
+            "SELECT chat_id, created_at, message_id, user_id, content, media_urls, media_meta, is_deleted, deleted_at, edited_at, edited_by, version, reply_to, thread_root_id \
             FROM messages_by_id WHERE message_id = ?"
         ).await.context("prepare get_by_id")?;
 
+        let scan_by_id_stmt = arc.prepare(
+            "SELECT chat_id, created_at, message_id, user_id, content, media_urls, media_meta, is_deleted, deleted_at, edited_at, edited_by, version, reply_to, thread_root_id \
+            FROM messages_by_id"
+        ).await.context("prepare scan_by_id")?;
+
         let update_edit_stmt = arc.prepare(
             "UPDATE messages SET content = ?, edited_at = ?, edited_by = ?, version = ? WHERE chat_id = ? AND created_at = ? AND message_id = ?"
         ).await.context("prepare update_edit")?;
 
+        let update_edit_thread_stmt = arc.prepare(
+            "UPDATE thread_messages SET content = ?, edited_at = ?, edited_by = ?, version = ? WHERE thread_root_id = ? AND created_at = ? AND message_id = ?"
+        ).await.context("prepare update_edit_thread")?;
+
         let update_edit_by_id_stmt = arc.prepare(
-            "UPDATE messages_by_id SET content = ?, edited_at = ?, edited_by = ?, version = ? WHERE message_id = ?"
+            "UPDATE messages_by_id SET content = ?, edited_at = ?, edited_by = ?, version = ? WHERE message_id = ? IF version = ?"
         ).await.context("prepare update_edit_by_id")?;
 
         let attach_media_stmt = arc.prepare(
-            "UPDATE messages SET media_urls = media_urls + ?, media_meta = media_meta + ? WHERE chat_id = ? AND created_at = ? AND message_id = ?"
+            "UPDATE messages SET media_urls = media_urls + ?, media_meta = media_meta + ?, version = ? WHERE chat_id = ? AND created_at = ? AND message_id = ?"
         ).await.context("prepare attach_media")?;
 
+        let attach_media_thread_stmt = arc.prepare(
+            "UPDATE thread_messages SET media_urls = media_urls + ?, media_meta = media_meta + ?, version = ? WHERE thread_root_id = ? AND created_at = ? AND message_id = ?"
+        ).await.context("prepare attach_media_thread")?;
+
         let attach_media_by_id_stmt = arc.prepare(
-            "UPDATE messages_by_id SET media_urls = media_urls + ?, media_meta = media_meta + ? WHERE message_id = ?"
+            "UPDATE messages_by_id SET media_urls = media_urls + ?, media_meta = media_meta + ?, version = ? WHERE message_id = ? IF version = ?"
         ).await.context("prepare attach_media_by_id")?;
 
         let soft_delete_stmt = arc.prepare(
-            "UPDATE messages SET is_deleted = true, deleted_at = ? WHERE chat_id = ? AND created_at = ? AND message_id = ?"
+            "UPDATE messages SET is_deleted = true, deleted_at = ?, version = ? WHERE chat_id = ? AND created_at = ? AND message_id = ?"
         ).await.context("prepare soft_delete")?;
 
+        let soft_delete_thread_stmt = arc.prepare(
+            "UPDATE thread_messages SET is_deleted = true, deleted_at = ?, version = ? WHERE thread_root_id = ? AND created_at = ? AND message_id = ?"
+        ).await.context("prepare soft_delete_thread")?;
+
         let soft_delete_by_id_stmt = arc.prepare(
-            "UPDATE messages_by_id SET is_deleted = true, deleted_at = ? WHERE message_id = ?"
+            "UPDATE messages_by_id SET is_deleted = true, deleted_at = ?, version = ? WHERE message_id = ? IF version = ?"
         ).await.context("prepare soft_delete_by_id")?;
 
         let restore_stmt = arc.prepare(
-            "UPDATE messages SET is_deleted = false, deleted_at = null WHERE chat_id = ? AND created_at = ? AND message_id = ?"
+            "UPDATE messages SET is_deleted = false, deleted_at = null, version = ? WHERE chat_id = ? AND created_at = ? AND message_id = ?"
         ).await.context("prepare restore")?;
 
+        let restore_thread_stmt = arc.prepare(
+            "UPDATE thread_messages SET is_deleted = false, deleted_at = null, version = ? WHERE thread_root_id = ? AND created_at = ? AND message_id = ?"
+        ).await.context("prepare restore_thread")?;
+
         let restore_by_id_stmt = arc.prepare(
-            "UPDATE messages_by_id SET is_deleted = false, deleted_at = null WHERE message_id = ?"
+            "UPDATE messages_by_id SET is_deleted = false, deleted_at = null, version = ? WHERE message_id = ? IF version = ?"
         ).await.context("prepare restore_by_id")?;
 
         let insert_edit_stmt = arc.prepare(
@@ -248,53 +410,132 @@ impl ScyllaDb {
             "SELECT 1 FROM user_chats WHERE user_id = ? AND chat_id = ?"
         ).await.context("prepare check_user_in_chat_stmt")?;
 
+        let insert_change_stmt = arc.prepare(
+            "INSERT INTO message_changes (chat_id, changed_at, message_id) VALUES (?, ?, ?)"
+        ).await.context("prepare insert_change")?;
+
+        let changes_since_stmt = arc.prepare(
+            "SELECT message_id FROM message_changes WHERE chat_id = ? AND changed_at > ?"
+        ).await.context("prepare changes_since")?;
+
+        let scan_chat_ids_stmt = arc.prepare(
+            "SELECT DISTINCT chat_id FROM messages"
+        ).await.context("prepare scan_chat_ids")?;
+
+        let scan_thread_roots_stmt = arc.prepare(
+            "SELECT DISTINCT thread_root_id FROM thread_messages"
+        ).await.context("prepare scan_thread_roots")?;
+
+        let created_since_stmt = arc.prepare(
+            "SELECT message_id FROM messages WHERE chat_id = ? AND created_at > ?"
+        ).await.context("prepare created_since")?;
+
+        let thread_created_since_stmt = arc.prepare(
+            "SELECT message_id FROM thread_messages WHERE thread_root_id = ? AND created_at > ?"
+        ).await.context("prepare thread_created_since")?;
+
+        let consume_ws_ticket_stmt = arc.prepare(
+            "INSERT INTO ws_ticket_uses (jti, used_at) VALUES (?, ?) IF NOT EXISTS"
+        ).await.context("prepare consume_ws_ticket")?;
+
+        let reactions = ReactionsDb::new(arc.clone()).await?;
+        let threads = ThreadsDb::new(arc.clone()).await?;
+
         Ok(Self {
             session: arc,
             keyspace: keyspace.to_string(),
+            reactions,
+            threads,
 
             insert_stmt,
             insert_by_id_stmt,
+            insert_thread_stmt,
             get_by_chat_stmt,
+            get_by_thread_stmt,
             get_by_id_stmt,
+            scan_by_id_stmt,
             update_edit_stmt,
+            update_edit_thread_stmt,
             update_edit_by_id_stmt,
             attach_media_stmt,
+            attach_media_thread_stmt,
             attach_media_by_id_stmt,
             soft_delete_stmt,
+            soft_delete_thread_stmt,
             soft_delete_by_id_stmt,
             restore_stmt,
+            restore_thread_stmt,
             restore_by_id_stmt,
             insert_edit_stmt,
             fetch_edits_stmt,
             hard_delete_main_stmt,
             hard_delete_by_id_stmt,
             hard_delete_edits_stmt,
+            insert_change_stmt,
+            changes_since_stmt,
+            scan_chat_ids_stmt,
+            scan_thread_roots_stmt,
+            created_since_stmt,
+            thread_created_since_stmt,
             get_user_chats_stmt,
             check_user_in_chat_stmt,
+            consume_ws_ticket_stmt,
 
         })
     }
 
+    /// Сохраняет новое сообщение. Все вставки условные (`IF NOT EXISTS`): Kafka может доставить
+    /// событие повторно, и неприменённая вставка значит, что сообщение уже записано —
+    /// повтор не должен затереть правки, удаление и версию
     pub async fn insert_message(&self, m: &Message) -> Result<()> {
     let media_urls = m.media_urls.clone().unwrap_or_default();
     let media_meta = m.media_meta.clone().unwrap_or_default();
 
-    // Вставка в основную таблицу: messages (chat_id, created_at, message_id, ...)
-    self.session
-        .execute(
-            &self.insert_stmt,
-            (
-                m.chat_id,
-                m.created_at,
-                m.message_id,
-                m.user_id,
-                m.content.as_deref(), // &str или None
-                &media_urls,
-                &media_meta,
-            ),
-        )
-        .await
-        .context("Failed to insert into 'messages' table")?;
+    match m.thread_root_id {
+        // Ответ в треде: thread_messages (thread_root_id, created_at, message_id, ...)
+        Some(thread_root_id) => {
+            let qr = self.session
+                .execute(
+                    &self.insert_thread_stmt,
+                    (
+                        thread_root_id,
+                        m.created_at,
+                        m.message_id,
+                        m.chat_id,
+                        m.user_id,
+                        m.content.as_deref(),
+                        &media_urls,
+                        &media_meta,
+                        m.reply_to,
+                    ),
+                )
+                .await
+                .context("Failed to insert into 'thread_messages' table")?;
+
+            // Повторная доставка из Kafka не должна увеличивать счётчик ответов
+            if lwt_applied(&qr) {
+                self.threads.increment(thread_root_id).await?;
+            }
+        }
+        // Вставка в основную таблицу: messages (chat_id, created_at, message_id, ...)
+        None => {
+            self.session
+                .execute(
+                    &self.insert_stmt,
+                    (
+                        m.chat_id,
+                        m.created_at,
+                        m.message_id,
+                        m.user_id,
+                        m.content.as_deref(), // &str или None
+                        &media_urls,
+                        &media_meta,
+                    ),
+                )
+                .await
+                .context("Failed to insert into 'messages' table")?;
+        }
+    }
 
     // Вставка в таблицу по message_id: messages_by_id (message_id, chat_id, created_at, ...)
     self.session
@@ -308,6 +549,8 @@ impl ScyllaDb {
                 m.content.as_deref(), // &str или None
                 &media_urls,
                 &media_meta,
+                m.reply_to,
+                m.thread_root_id,
             ),
         )
         .await
@@ -317,105 +560,206 @@ impl ScyllaDb {
 }
 
 
-
     pub async fn get_message_by_id(&self, message_id: Uuid) -> Result<Option<Message>> {
         let qr = self.session.execute(&self.get_by_id_stmt, (message_id,))
             .await.context("query by id")?;
 
         if let Some(rows) = qr.rows {
-            if !rows.is_empty() {
-                let row = rows.into_typed::<(
-                    Uuid, Uuid, DateTime<Utc>, Uuid, Option<String>, Option<Vec<String>>, Option<HashMap<String, String>>,
-                    Option<bool>, Option<DateTime<Utc>>, Option<DateTime<Utc>>, Option<Uuid>, Option<i64>
-                )>().next();
-
-                if let Some(Ok((
-                    msg_id, chat_id, created_at, user_id, content, media_urls, media_meta,
-                    is_deleted_opt, deleted_at, edited_at, edited_by, version_opt
-                ))) = row
-                {
-                    return Ok(Some(Message {
-                        chat_id,
-                        created_at,
-                        message_id: msg_id,
-                        user_id,
-                        content,
-                        media_urls,
-                        media_meta,
-                        is_deleted: is_deleted_opt.unwrap_or(false),
-                        deleted_at,
-                        edited_at,
-                        edited_by,
-                        version: version_opt.unwrap_or(0),
-                    }));
-                }
+            if let Some(row) = rows.into_typed::<MessageRow>().next() {
+                return Ok(Some(message_from_row(row?)));
             }
         }
 
         Ok(None)
     }
 
+    /// Все сообщения всех чатов (включая ответы в тредах), постранично.
+    /// Полный проход по таблице — только для перестройки поискового индекса
+    pub async fn scan_messages(&self) -> Result<impl Stream<Item = Result<Message>>> {
+        let rows = self.session.execute_iter(self.scan_by_id_stmt.clone(), ())
+            .await.context("scan messages")?;
+
+        Ok(rows.into_typed::<MessageRow>().map(|row| -> Result<Message> { Ok(message_from_row(row?)) }))
+    }
+
+    /// Сообщения по списку id вместе с реакциями. Отсутствующих (удалённых безвозвратно) в ответе нет
+    pub async fn get_messages_by_ids(&self, message_ids: &[Uuid]) -> Result<Vec<Message>> {
+        let results = join_all(message_ids.iter().map(|id| self.get_message_by_id(*id))).await;
+
+        let mut out = Vec::with_capacity(message_ids.len());
+        for msg in results {
+            if let Some(msg) = msg? {
+                out.push(msg);
+            }
+        }
+
+        let ids: Vec<Uuid> = out.iter().map(|m| m.message_id).collect();
+        let mut reactions = self.reactions.get_reactions_for(&ids).await?;
+        for msg in out.iter_mut() {
+            if let Some(r) = reactions.remove(&msg.message_id) {
+                msg.reactions = r;
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Отмечает в журнале чата, что сообщение изменилось в момент `changed_at`.
+    /// Пишется до самого изменения: лишняя запись в журнале безвредна (resync отдаст текущее
+    /// состояние), а пропущенная означала бы, что при потере события клиент его не догонит
+    pub async fn record_change(&self, chat_id: Uuid, message_id: Uuid, changed_at: DateTime<Utc>) -> Result<()> {
+        self.session.execute(&self.insert_change_stmt, (chat_id, changed_at, message_id))
+            .await.context("insert message change")?;
+        Ok(())
+    }
+
+    /// Ставит реакцию на сообщение `msg` и отмечает изменение в журнале чата.
+    /// Возвращает новое число таких реакций или `None`, если реакция уже стояла
+    pub async fn add_reaction(&self, msg: &Message, user_id: Uuid, emoji: &str) -> Result<Option<i64>, ReactionError> {
+        self.record_change(msg.chat_id, msg.message_id, Utc::now()).await?;
+        self.reactions.add_reaction(msg.message_id, user_id, emoji).await
+    }
+
+    /// Снимает реакцию с сообщения `msg`, как `add_reaction`
+    pub async fn remove_reaction(&self, msg: &Message, user_id: Uuid, emoji: &str) -> Result<Option<i64>> {
+        self.record_change(msg.chat_id, msg.message_id, Utc::now()).await?;
+        self.reactions.remove_reaction(msg.message_id, user_id, emoji).await
+    }
+
+    /// Сообщения чата, изменённые позже `since`, без повторов
+    pub async fn changed_since(&self, chat_id: Uuid, since: DateTime<Utc>) -> Result<Vec<Uuid>> {
+        let mut rows = self.session.execute_iter(self.changes_since_stmt.clone(), (chat_id, since))
+            .await.context("query message changes")?
+            .into_typed::<(Uuid,)>();
+
+        let mut seen = HashSet::new();
+        let mut ids = Vec::new();
+        while let Some(row) = rows.next().await {
+            let (message_id,) = row?;
+            if seen.insert(message_id) {
+                ids.push(message_id);
+            }
+        }
+
+        Ok(ids)
+    }
+
+    /// Сообщения всех чатов и тредов, созданные или изменённые позже `since`, без повторов.
+    /// Обходит ключи партиций `messages` и `thread_messages` — только для догонки поискового индекса
+    pub async fn messages_since(&self, since: DateTime<Utc>) -> Result<Vec<Uuid>> {
+        let mut seen = HashSet::new();
+        let mut ids = Vec::new();
+
+        for chat_id in self.scan_partition_keys(&self.scan_chat_ids_stmt).await? {
+            let created = self.ids_since(&self.created_since_stmt, chat_id, since).await?;
+            for message_id in created.into_iter().chain(self.changed_since(chat_id, since).await?) {
+                if seen.insert(message_id) {
+                    ids.push(message_id);
+                }
+            }
+        }
+
+        for thread_root_id in self.scan_partition_keys(&self.scan_thread_roots_stmt).await? {
+            for message_id in self.ids_since(&self.thread_created_since_stmt, thread_root_id, since).await? {
+                if seen.insert(message_id) {
+                    ids.push(message_id);
+                }
+            }
+        }
+
+        Ok(ids)
+    }
+
+    async fn scan_partition_keys(&self, stmt: &PreparedStatement) -> Result<Vec<Uuid>> {
+        let mut rows = self.session.execute_iter(stmt.clone(), ())
+            .await.context("scan partition keys")?
+            .into_typed::<(Uuid,)>();
+
+        let mut keys = Vec::new();
+        while let Some(row) = rows.next().await {
+            keys.push(row?.0);
+        }
+        Ok(keys)
+    }
+
+    async fn ids_since(&self, stmt: &PreparedStatement, partition: Uuid, since: DateTime<Utc>) -> Result<Vec<Uuid>> {
+        let mut rows = self.session.execute_iter(stmt.clone(), (partition, since))
+            .await.context("query messages since")?
+            .into_typed::<(Uuid,)>();
+
+        let mut ids = Vec::new();
+        while let Some(row) = rows.next().await {
+            ids.push(row?.0);
+        }
+        Ok(ids)
+    }
+
     pub async fn fetch_recent_paged(&self, chat_id: Uuid, limit: i32, paging_state: Option<Vec<u8>>) -> Result<(Vec<Message>, Option<Vec<u8>>)> {
+        self.fetch_timeline_paged(&self.get_by_chat_stmt, chat_id, limit, paging_state).await
+    }
+
+    /// Ответы треда, от новых к старым (как и лента чата)
+    pub async fn fetch_thread_paged(&self, thread_root_id: Uuid, limit: i32, paging_state: Option<Vec<u8>>) -> Result<(Vec<Message>, Option<Vec<u8>>)> {
+        self.fetch_timeline_paged(&self.get_by_thread_stmt, thread_root_id, limit, paging_state).await
+    }
+
+    async fn fetch_timeline_paged(&self, stmt: &PreparedStatement, key: Uuid, limit: i32, paging_state: Option<Vec<u8>>) -> Result<(Vec<Message>, Option<Vec<u8>>)> {
         let qr = match paging_state {
             Some(state) => {
                 let state = Some(Bytes::from(state));
-                self.session.execute_paged(&self.get_by_chat_stmt, (chat_id, limit), state).await?
+                self.session.execute_paged(stmt, (key, limit), state).await?
             }
             None => {
-                self.session.execute(&self.get_by_chat_stmt, (chat_id, limit)).await?
+                self.session.execute(stmt, (key, limit)).await?
             }
         };
 
         let rows = qr.rows.unwrap_or_default();
         let mut out = Vec::with_capacity(rows.len());
 
-        for row in rows.into_typed::<(
-            Uuid, DateTime<Utc>, Uuid, Uuid, Option<String>, Option<Vec<String>>, Option<HashMap<String, String>>,
-            Option<bool>, Option<DateTime<Utc>>, Option<DateTime<Utc>>, Option<Uuid>, Option<i64>
-        )>() {
-            let (chat_id, created_at, message_id, user_id, content, media_urls, media_meta, is_deleted_opt, deleted_at, edited_at, edited_by, version_opt) = row?;
-            out.push(Message {
-                chat_id,
-                created_at,
-                message_id,
-                user_id,
-                content,
-                media_urls,
-                media_meta,
-                is_deleted: is_deleted_opt.unwrap_or(false),
-                deleted_at,
-                edited_at,
-                edited_by,
-                version: version_opt.unwrap_or(0),
-            });
+        for row in rows.into_typed::<MessageRow>() {
+            out.push(message_from_row(row?));
         }
 
-        Ok((out, qr.paging_state.map(|b| b.to_vec())))
-    }
+        // Подмешиваем реакции к странице сообщений
+        let ids: Vec<Uuid> = out.iter().map(|m| m.message_id).collect();
+        let mut reactions = self.reactions.get_reactions_for(&ids).await?;
 
-    pub async fn get_message_timestamp(&self, message_id: Uuid) -> Result<Option<(Uuid, DateTime<Utc>)>> {
-        let qr = self.session.execute(&self.get_by_id_stmt, (message_id,))
-            .await.context("query timestamp by id")?;
+        // Сводки по тредам нужны только сообщениям из ленты чата
+        let root_ids: Vec<Uuid> = out.iter().filter(|m| m.thread_root_id.is_none()).map(|m| m.message_id).collect();
+        let mut threads = self.threads.get_stats_for(&root_ids).await?;
 
-        if let Some(rows) = qr.rows {
-            for row in rows.into_typed::<(
-                Uuid, Uuid, DateTime<Utc>, Uuid, Option<String>, Option<Vec<String>>, Option<HashMap<String, String>>,
-                Option<bool>, Option<DateTime<Utc>>, Option<DateTime<Utc>>, Option<Uuid>, Option<i64>
-            )>() {
-                let (msg_id, chat_id, created_at, _, _, _, _, _, _, _, _, _) = row?;
-                if msg_id == message_id {
-                    return Ok(Some((chat_id, created_at)));
-                }
+        for msg in out.iter_mut() {
+            if let Some(r) = reactions.remove(&msg.message_id) {
+                msg.reactions = r;
+            }
+            if let Some(stats) = threads.remove(&msg.message_id) {
+                msg.reply_count = stats.reply_count;
+                msg.last_reply_at = stats.last_reply_at;
             }
         }
 
-        Ok(None)
+        Ok((out, qr.paging_state.map(|b| b.to_vec())))
     }
 
-    pub async fn edit_message_with_history(&self, message_id: Uuid, new_content: Option<String>, editor: Uuid) -> Result<()> {
-        let msg = self.get_message_by_id(message_id)
+    /// Определяет корень треда для ответа на `reply_to`.
+    /// `None` — если исходного сообщения нет в этом чате
+    pub async fn resolve_thread_root(&self, chat_id: Uuid, reply_to: Uuid) -> Result<Option<Uuid>> {
+        Ok(self.get_message_by_id(reply_to)
             .await?
-            .ok_or_else(|| anyhow::anyhow!("message not found"))?;
+            .filter(|parent| parent.chat_id == chat_id && !parent.is_deleted)
+            .map(|parent| parent.thread_root_id.unwrap_or(parent.message_id)))
+    }
+
+    /// Редактирует сообщение и пишет историю правок.
+    /// Возвращает обновлённое сообщение или `None`, если текст не изменился.
+    /// Удалённое сообщение не редактируется, параллельная правка даёт `Conflict`
+    pub async fn edit_message_with_history(&self, message_id: Uuid, new_content: Option<String>, editor: Uuid) -> Result<Option<Message>, DeleteError> {
+        let msg = self.get_message_by_id(message_id)
+            .await
+            .map_err(DeleteError::from)?
+            .filter(|m| !m.is_deleted)
+            .ok_or(DeleteError::MessageNotFound)?;
 
         let old_content = msg.content.clone().unwrap_or_default();
         let new_content = new_content.unwrap_or_default();
@@ -423,9 +767,30 @@ impl ScyllaDb {
         let now = Utc::now();
 
         if old_content == new_content {
-            return Ok(()); // Нет изменений
+            return Ok(None); // Нет изменений
         }
 
+        self.record_change(msg.chat_id, message_id, now).await?;
+        self.apply_versioned(&self.update_edit_by_id_stmt, (
+            new_content.clone(),
+            now,
+            editor,
+            new_version,
+            message_id,
+            msg.version,
+        )).await?;
+
+        let (stmt, key) = timeline_target(&msg, &self.update_edit_stmt, &self.update_edit_thread_stmt);
+        self.session.execute(stmt, (
+            new_content.clone(),
+            now,
+            editor,
+            new_version,
+            key,
+            msg.created_at,
+            message_id,
+        )).await.context("update edit messages")?;
+
         let edit = MessageEdit {
             message_id,
             edit_id: Uuid::new_v4(),
@@ -438,47 +803,64 @@ impl ScyllaDb {
 
         self.insert_edit(&edit).await?;
 
-        let (chat_id, created_at) = self.get_message_timestamp(message_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("timestamp not found"))?;
+        Ok(Some(Message {
+            content: Some(new_content),
+            edited_at: Some(now),
+            edited_by: Some(editor),
+            version: new_version,
+            ..msg
+        }))
+    }
 
-        self.session.execute(&self.update_edit_stmt, (
-            new_content.clone(),
-            now,
-            editor,
-            new_version,
-            chat_id,
-            created_at,
-            message_id,
-        )).await.context("update edit messages")?;
+    /// Добавляет медиа к сообщению `msg` в прочитанной версии. Возвращает обновлённое сообщение
+    pub async fn attach_media(&self, msg: &Message, urls: Vec<String>, meta: HashMap<String, String>) -> Result<Message, DeleteError> {
+        if msg.is_deleted {
+            return Err(DeleteError::MessageNotFound);
+        }
 
-        self.session.execute(&self.update_edit_by_id_stmt, (
-            new_content,
-            now,
-            editor,
-            new_version,
-            message_id,
-        )).await.context("update edit by id")?;
+        let new_version = msg.version + 1;
 
-        Ok(())
-    }
+        self.record_change(msg.chat_id, msg.message_id, Utc::now()).await?;
+        self.apply_versioned(&self.attach_media_by_id_stmt, (urls.clone(), meta.clone(), new_version, msg.message_id, msg.version))
+            .await?;
 
-    pub async fn attach_media(&self, chat_id: Uuid, created_at: DateTime<Utc>, message_id: Uuid, urls: Vec<String>, meta: HashMap<String, String>) -> Result<()> {
-        self.session.execute(&self.attach_media_stmt, (urls.clone(), meta.clone(), chat_id, created_at, message_id))
+        let (stmt, key) = timeline_target(msg, &self.attach_media_stmt, &self.attach_media_thread_stmt);
+        self.session.execute(stmt, (urls.clone(), meta.clone(), new_version, key, msg.created_at, msg.message_id))
             .await.context("attach media")?;
 
-        self.session.execute(&self.attach_media_by_id_stmt, (urls, meta, message_id))
-            .await.context("attach media by id")?;
+        let mut media_urls = msg.media_urls.clone().unwrap_or_default();
+        media_urls.extend(urls);
+        let mut media_meta = msg.media_meta.clone().unwrap_or_default();
+        media_meta.extend(meta);
 
-        Ok(())
+        Ok(Message {
+            media_urls: Some(media_urls),
+            media_meta: Some(media_meta),
+            version: new_version,
+            ..msg.clone()
+        })
+    }
+
+    /// Условное изменение строки messages_by_id. Если версия уже другая — `Conflict`
+    async fn apply_versioned(&self, stmt: &PreparedStatement, values: impl ValueList) -> Result<(), DeleteError> {
+        let qr = self.session.execute(stmt, values)
+            .await
+            .map_err(|e| DeleteError::from(anyhow::anyhow!(e)))?;
+
+        if lwt_applied(&qr) {
+            Ok(())
+        } else {
+            Err(DeleteError::Conflict)
+        }
     }
 
+    /// Мягкое удаление. Возвращает обновлённое сообщение или `None`, если оно уже удалено
     pub async fn delete_message(
         &self,
         message_id: Uuid,
         requester_user_id: Uuid,
         is_admin: bool,
-    ) -> Result<(), DeleteError> {
+    ) -> Result<Option<Message>, DeleteError> {
         let msg = self.get_message_by_id(message_id)
             .await
             .map_err(DeleteError::from)?
@@ -489,26 +871,31 @@ impl ScyllaDb {
         }
 
         if msg.is_deleted {
-            return Ok(());
+            return Ok(None);
         }
 
-        let (chat_id, created_at) = self.get_message_timestamp(message_id)
-            .await
-            .map_err(DeleteError::from)?
-            .ok_or(DeleteError::MessageNotFound)?;
+        let now = Utc::now();
+        let new_version = msg.version + 1;
 
-        self.session.execute(&self.soft_delete_stmt, (Utc::now(), chat_id, created_at, message_id))
-            .await
-            .map_err(|e| DeleteError::from(anyhow::anyhow!(e)));
+        self.record_change(msg.chat_id, message_id, now).await?;
+        self.apply_versioned(&self.soft_delete_by_id_stmt, (now, new_version, message_id, msg.version))
+            .await?;
 
-        self.session.execute(&self.soft_delete_by_id_stmt, (Utc::now(), message_id))
+        let (stmt, key) = timeline_target(&msg, &self.soft_delete_stmt, &self.soft_delete_thread_stmt);
+        self.session.execute(stmt, (now, new_version, key, msg.created_at, message_id))
             .await
-            .map_err(|e| DeleteError::from(anyhow::anyhow!(e)));
-
-        Ok(())
+            .map_err(|e| DeleteError::from(anyhow::anyhow!(e)))?;
+
+        Ok(Some(Message {
+            is_deleted: true,
+            deleted_at: Some(now),
+            version: new_version,
+            ..msg
+        }))
     }
 
-        pub async fn restore_message(&self, message_id: Uuid, requester_user_id: Uuid, is_admin: bool) -> Result<(), DeleteError> {
+    /// Восстанавливает удалённое сообщение. Возвращает обновлённое сообщение или `None`, если оно не было удалено
+    pub async fn restore_message(&self, message_id: Uuid, requester_user_id: Uuid, is_admin: bool) -> Result<Option<Message>, DeleteError> {
         let msg = self.get_message_by_id(message_id)
             .await
             .map_err(DeleteError::from)?
@@ -519,48 +906,78 @@ impl ScyllaDb {
         }
 
         if !msg.is_deleted {
-            return Ok(()); // Уже не удалено
+            return Ok(None); // Уже не удалено
         }
 
-        let (chat_id, created_at) = self.get_message_timestamp(message_id)
-            .await
-            .map_err(DeleteError::from)?
-            .ok_or(DeleteError::MessageNotFound)?;
+        let new_version = msg.version + 1;
 
-        self.session.execute(&self.restore_stmt, (chat_id, created_at, message_id))
-            .await
-            .map_err(|e| DeleteError::from(anyhow::anyhow!(e)));
+        self.record_change(msg.chat_id, message_id, Utc::now()).await?;
+        self.apply_versioned(&self.restore_by_id_stmt, (new_version, message_id, msg.version))
+            .await?;
 
-        self.session.execute(&self.restore_by_id_stmt, (message_id,))
+        let (stmt, key) = timeline_target(&msg, &self.restore_stmt, &self.restore_thread_stmt);
+        self.session.execute(stmt, (new_version, key, msg.created_at, message_id))
             .await
-            .map_err(|e| DeleteError::from(anyhow::anyhow!(e)));
-
-        Ok(())
+            .map_err(|e| DeleteError::from(anyhow::anyhow!(e)))?;
+
+        Ok(Some(Message {
+            is_deleted: false,
+            deleted_at: None,
+            version: new_version,
+            ..msg
+        }))
     }
 
-        pub async fn hard_delete_message(&self, message_id: Uuid, _requester_user_id: Uuid, is_admin: bool) -> Result<(), DeleteError> {
+    /// Безвозвратное удаление (только для админов). Возвращает удалённое сообщение.
+    /// Вместе с корнем треда удаляются и все ответы
+    pub async fn hard_delete_message(&self, message_id: Uuid, _requester_user_id: Uuid, is_admin: bool) -> Result<Message, DeleteError> {
         if !is_admin {
             return Err(DeleteError::PermissionDenied);
         }
 
-        let (chat_id, created_at) = self.get_message_timestamp(message_id)
+        let msg = self.get_message_by_id(message_id)
             .await
             .map_err(DeleteError::from)?
             .ok_or(DeleteError::MessageNotFound)?;
 
-        // Удаляем из основных таблиц
-        self.session.execute(&self.hard_delete_main_stmt, (chat_id, created_at, message_id))
-            .await
-            .map_err(|e| DeleteError::from(anyhow::anyhow!(e)));
+        self.record_change(msg.chat_id, message_id, Utc::now()).await?;
+
+        match msg.thread_root_id {
+            // Ответ убираем из ленты треда
+            Some(thread_root_id) => {
+                self.threads.remove_reply(thread_root_id, msg.created_at, message_id).await?;
+            }
+            // Удаляем из основных таблиц вместе с тредом
+            None => {
+                self.session.execute(&self.hard_delete_main_stmt, (msg.chat_id, msg.created_at, message_id))
+                    .await
+                    .map_err(|e| DeleteError::from(anyhow::anyhow!(e)))?;
 
+                for reply_id in self.threads.reply_ids(message_id).await? {
+                    self.purge_message_data(reply_id).await?;
+                }
+                self.threads.purge(message_id).await?;
+            }
+        }
+
+        self.purge_message_data(message_id).await?;
+
+        Ok(msg)
+    }
+
+    /// Удаляет строку по message_id, историю правок и реакции
+    async fn purge_message_data(&self, message_id: Uuid) -> Result<(), DeleteError> {
         self.session.execute(&self.hard_delete_by_id_stmt, (message_id,))
             .await
-            .map_err(|e| DeleteError::from(anyhow::anyhow!(e)));
+            .map_err(|e| DeleteError::from(anyhow::anyhow!(e)))?;
 
         // Удаляем историю правок
         self.session.execute(&self.hard_delete_edits_stmt, (message_id,))
             .await
-            .map_err(|e| DeleteError::from(anyhow::anyhow!(e)));
+            .map_err(|e| DeleteError::from(anyhow::anyhow!(e)))?;
+
+        // Удаляем реакции
+        self.reactions.purge(message_id).await?;
 
         Ok(())
     }
@@ -646,5 +1063,14 @@ impl ScyllaDb {
         Ok(!rows.rows.map(|r| r.is_empty()).unwrap_or(true))
     }
 
+    /// Отмечает билет на WebSocket использованным. `false` — билет уже предъявляли
+    pub async fn consume_ws_ticket(&self, jti: Uuid) -> Result<bool, ScyllaError> {
+        let qr = self.session
+            .execute(&self.consume_ws_ticket_stmt, (jti, Utc::now()))
+            .await?;
+
+        Ok(lwt_applied(&qr))
+    }
+
     
 }
@@ -1,36 +1,13 @@
 // src/db/mod.rs
 
 pub mod messages;
-
-use rdkafka::ClientConfig;
-use crate::config::Config;
-use std::sync::Arc;
-use anyhow::Result;
-
-#[derive(Clone)]
-pub struct Db {
-    pub scylla: messages::ScyllaDb,
-    pub kafka: Arc<rdkafka::producer::FutureProducer>,
-}
-
-impl Db {
-    pub async fn connect(config: &Config) -> Result<Self> {
-        // 1. Scylla
-        let scylla = messages::ScyllaDb::connect(&config.scylla_nodes, &config.scylla_keyspace)
-            .await
-            .map_err(|e| anyhow::anyhow!("Scylla connect error: {}", e))?;
-
-        // 2. Kafka
-        let kafka = ClientConfig::new()
-            .set("bootstrap.servers", &config.kafka_brokers) // ✅ &Vec<String> → join
-            .set("message.timeout.ms", "5000")
-            .create::<rdkafka::producer::FutureProducer>()
-            .map_err(|e| anyhow::anyhow!("Kafka producer create error: {}", e))?;
-
-        let kafka = Arc::new(kafka);
-
-        Ok(Db { scylla, kafka })
-    }
-}
-
-pub use messages::{ScyllaDb};
\ No newline at end of file
+pub mod chats;
+pub mod reactions;
+pub mod read_markers;
+pub mod threads;
+pub mod presence;
+
+pub use messages::{ScyllaDb};
+pub use chats::ChatsDb;
+pub use read_markers::ReadMarkersDb;
+pub use presence::PresenceDb;
\ No newline at end of file
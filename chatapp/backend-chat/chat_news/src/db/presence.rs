@@ -0,0 +1,134 @@
+// src/db/presence.rs
+
+use std::sync::Arc;
+use scylla::{Session, IntoTypedRows, prepared_statement::PreparedStatement};
+
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use anyhow::{Result, Context};
+
+use crate::models::PresenceStatus;
+
+#[derive(Clone)]
+pub struct PresenceDb {
+    pub session: Arc<Session>,
+
+    set_last_seen_stmt: PreparedStatement,
+    get_last_seen_stmt: PreparedStatement,
+
+    // Живые статусы по инстансам
+    set_live_stmt: PreparedStatement,
+    clear_live_stmt: PreparedStatement,
+    get_live_stmt: PreparedStatement,
+}
+
+/// Статус пользователя на одном инстансе и время его последней активности там
+#[derive(Debug, Clone, Copy)]
+pub struct LivePresence {
+    pub status: PresenceStatus,
+    pub last_active: DateTime<Utc>,
+}
+
+fn status_to_str(status: PresenceStatus) -> &'static str {
+    match status {
+        PresenceStatus::Online => "online",
+        PresenceStatus::Away => "away",
+        PresenceStatus::Offline => "offline",
+    }
+}
+
+fn status_from_str(status: &str) -> Option<PresenceStatus> {
+    match status {
+        "online" => Some(PresenceStatus::Online),
+        "away" => Some(PresenceStatus::Away),
+        "offline" => Some(PresenceStatus::Offline),
+        _ => None,
+    }
+}
+
+impl PresenceDb {
+    pub async fn new(session: Arc<Session>) -> Result<Self> {
+        let set_last_seen_stmt = session.prepare(
+            "INSERT INTO user_presence (user_id, last_seen) VALUES (?, ?)"
+        ).await.context("prepare set_last_seen")?;
+
+        let get_last_seen_stmt = session.prepare(
+            "SELECT last_seen FROM user_presence WHERE user_id = ?"
+        ).await.context("prepare get_last_seen")?;
+
+        let set_live_stmt = session.prepare(
+            "INSERT INTO user_presence_live (user_id, instance_id, status, last_active) VALUES (?, ?, ?, ?) USING TTL ?"
+        ).await.context("prepare set_live_presence")?;
+
+        let clear_live_stmt = session.prepare(
+            "DELETE FROM user_presence_live WHERE user_id = ? AND instance_id = ?"
+        ).await.context("prepare clear_live_presence")?;
+
+        let get_live_stmt = session.prepare(
+            "SELECT status, last_active FROM user_presence_live WHERE user_id = ?"
+        ).await.context("prepare get_live_presence")?;
+
+        Ok(Self {
+            session,
+
+            set_last_seen_stmt,
+            get_last_seen_stmt,
+            set_live_stmt,
+            clear_live_stmt,
+            get_live_stmt,
+        })
+    }
+
+    pub async fn set_last_seen(&self, user_id: Uuid, last_seen: DateTime<Utc>) -> Result<()> {
+        self.session.execute(&self.set_last_seen_stmt, (user_id, last_seen))
+            .await.context("set last seen")?;
+        Ok(())
+    }
+
+    pub async fn get_last_seen(&self, user_id: Uuid) -> Result<Option<DateTime<Utc>>> {
+        let qr = self.session.execute(&self.get_last_seen_stmt, (user_id,))
+            .await.context("query last seen")?;
+
+        if let Some(rows) = qr.rows {
+            if let Some(row) = rows.into_typed::<(Option<DateTime<Utc>>,)>().next() {
+                let (last_seen,) = row?;
+                return Ok(last_seen);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Статус пользователя на инстансе `instance_id`; строка живёт `ttl_secs`, если её не обновить
+    pub async fn set_live(&self, user_id: Uuid, instance_id: &str, live: LivePresence, ttl_secs: i32) -> Result<()> {
+        self.session
+            .execute(&self.set_live_stmt, (user_id, instance_id, status_to_str(live.status), live.last_active, ttl_secs))
+            .await.context("set live presence")?;
+        Ok(())
+    }
+
+    /// У пользователя не осталось соединений на инстансе `instance_id`
+    pub async fn clear_live(&self, user_id: Uuid, instance_id: &str) -> Result<()> {
+        self.session.execute(&self.clear_live_stmt, (user_id, instance_id))
+            .await.context("clear live presence")?;
+        Ok(())
+    }
+
+    /// Статусы пользователя на всех инстансах, где у него есть соединения
+    pub async fn get_live(&self, user_id: Uuid) -> Result<Vec<LivePresence>> {
+        let qr = self.session.execute(&self.get_live_stmt, (user_id,))
+            .await.context("query live presence")?;
+
+        let mut live = Vec::new();
+        if let Some(rows) = qr.rows {
+            for row in rows.into_typed::<(Option<String>, Option<DateTime<Utc>>)>() {
+                let (status, last_active) = row?;
+                if let (Some(status), Some(last_active)) = (status.as_deref().and_then(status_from_str), last_active) {
+                    live.push(LivePresence { status, last_active });
+                }
+            }
+        }
+
+        Ok(live)
+    }
+}
@@ -0,0 +1,350 @@
+// src/db/reactions.rs
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use scylla::{
+    Session, IntoTypedRows, QueryResult, prepared_statement::PreparedStatement,
+    frame::value::Counter,
+};
+use futures_util::StreamExt;
+
+use uuid::Uuid;
+use chrono::Utc;
+use anyhow::{Result, Context};
+use serde::{Serialize, Deserialize};
+
+/// Максимальная длина эмодзи в байтах (с учётом модификаторов и ZWJ-последовательностей)
+pub const MAX_EMOJI_LEN: usize = 32;
+
+/// Сколько разных эмодзи один пользователь может поставить на одно сообщение
+pub const MAX_REACTIONS_PER_USER: usize = 20;
+
+/// Ошибки постановки реакции
+#[derive(Debug)]
+pub enum ReactionError {
+    /// Пользователь уже поставил на сообщение максимум разных эмодзи
+    TooManyReactions,
+    InternalError(anyhow::Error),
+}
+
+impl From<anyhow::Error> for ReactionError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::InternalError(e)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: i64,
+    pub reacted_by: Vec<Uuid>,
+}
+
+#[derive(Clone)]
+pub struct ReactionsDb {
+    pub session: Arc<Session>,
+
+    insert_reaction_stmt: PreparedStatement,
+    delete_reaction_stmt: PreparedStatement,
+    increment_stmt: PreparedStatement,
+    decrement_stmt: PreparedStatement,
+    get_reactors_stmt: PreparedStatement,
+    get_counts_stmt: PreparedStatement,
+    get_count_stmt: PreparedStatement,
+    get_reactors_batch_stmt: PreparedStatement,
+    get_counts_batch_stmt: PreparedStatement,
+    purge_reactions_stmt: PreparedStatement,
+    purge_counts_stmt: PreparedStatement,
+}
+
+/// Результат LWT-запроса: первая колонка `[applied]`
+pub(crate) fn lwt_applied(qr: &QueryResult) -> bool {
+    qr.rows
+        .as_ref()
+        .and_then(|rows| rows.first())
+        .and_then(|row| row.columns.first())
+        .and_then(|col| col.as_ref())
+        .and_then(|val| val.as_boolean())
+        .unwrap_or(false)
+}
+
+/// Пиктографические символы, из которых строятся эмодзи
+fn is_pictographic(c: char) -> bool {
+    matches!(c as u32,
+        0x1F000..=0x1FAFF       // маджонг, карты, флаги, смайлы, транспорт, символы
+        | 0x2600..=0x27BF       // разные символы и дингбаты
+        | 0x2300..=0x23FF       // ⌚ ⏰ ⏩ …
+        | 0x2B00..=0x2BFF       // ⭐ ⬆ …
+        | 0x2190..=0x21FF       // стрелки
+        | 0x25A0..=0x25FF       // геометрические фигуры
+        | 0x2934 | 0x2935 | 0x3030 | 0x303D | 0x3297 | 0x3299
+        | 0x00A9 | 0x00AE | 0x203C | 0x2049 | 0x2122 | 0x2139
+        | 0x20E3                // keycap: 1️⃣, #️⃣
+    )
+}
+
+/// Служебные символы внутри эмодзи-последовательностей
+fn is_emoji_component(c: char) -> bool {
+    matches!(c as u32,
+        0x200D                  // ZWJ
+        | 0xFE0E | 0xFE0F       // селекторы варианта
+        | 0xE0020..=0xE007F     // теги (флаги регионов)
+    ) || matches!(c, '0'..='9' | '#' | '*')
+}
+
+/// Проверяет, что строка — один эмодзи: только пиктограммы и служебные символы
+/// эмодзи-последовательностей, хотя бы одна пиктограмма, не длиннее `MAX_EMOJI_LEN` байт
+pub fn is_valid_emoji(emoji: &str) -> bool {
+    emoji.len() <= MAX_EMOJI_LEN
+        && emoji.chars().any(is_pictographic)
+        && emoji.chars().all(|c| is_pictographic(c) || is_emoji_component(c))
+}
+
+/// Собирает реакции по эмодзи; если счётчика нет, считаем по списку поставивших
+fn group_reactions(counts: &HashMap<String, i64>, reactors: BTreeMap<String, Vec<Uuid>>) -> Vec<Reaction> {
+    reactors
+        .into_iter()
+        .map(|(emoji, reacted_by)| Reaction {
+            count: counts.get(&emoji).copied().unwrap_or(reacted_by.len() as i64).max(0),
+            emoji,
+            reacted_by,
+        })
+        .collect()
+}
+
+impl ReactionsDb {
+    pub async fn new(session: Arc<Session>) -> Result<Self> {
+        let insert_reaction_stmt = session.prepare(
+            "INSERT INTO message_reactions (message_id, emoji, user_id, reacted_at) VALUES (?, ?, ?, ?) IF NOT EXISTS"
+        ).await.context("prepare insert_reaction")?;
+
+        let delete_reaction_stmt = session.prepare(
+            "DELETE FROM message_reactions WHERE message_id = ? AND emoji = ? AND user_id = ? IF EXISTS"
+        ).await.context("prepare delete_reaction")?;
+
+        let increment_stmt = session.prepare(
+            "UPDATE message_reaction_counts SET count = count + 1 WHERE message_id = ? AND emoji = ?"
+        ).await.context("prepare increment_reaction")?;
+
+        let decrement_stmt = session.prepare(
+            "UPDATE message_reaction_counts SET count = count - 1 WHERE message_id = ? AND emoji = ?"
+        ).await.context("prepare decrement_reaction")?;
+
+        let get_reactors_stmt = session.prepare(
+            "SELECT emoji, user_id FROM message_reactions WHERE message_id = ?"
+        ).await.context("prepare get_reactors")?;
+
+        let get_counts_stmt = session.prepare(
+            "SELECT emoji, count FROM message_reaction_counts WHERE message_id = ?"
+        ).await.context("prepare get_reaction_counts")?;
+
+        let get_count_stmt = session.prepare(
+            "SELECT count FROM message_reaction_counts WHERE message_id = ? AND emoji = ?"
+        ).await.context("prepare get_reaction_count")?;
+
+        let get_reactors_batch_stmt = session.prepare(
+            "SELECT message_id, emoji, user_id FROM message_reactions WHERE message_id IN ?"
+        ).await.context("prepare get_reactors_batch")?;
+
+        let get_counts_batch_stmt = session.prepare(
+            "SELECT message_id, emoji, count FROM message_reaction_counts WHERE message_id IN ?"
+        ).await.context("prepare get_reaction_counts_batch")?;
+
+        let purge_reactions_stmt = session.prepare(
+            "DELETE FROM message_reactions WHERE message_id = ?"
+        ).await.context("prepare purge_reactions")?;
+
+        let purge_counts_stmt = session.prepare(
+            "DELETE FROM message_reaction_counts WHERE message_id = ?"
+        ).await.context("prepare purge_reaction_counts")?;
+
+        Ok(Self {
+            session,
+
+            insert_reaction_stmt,
+            delete_reaction_stmt,
+            increment_stmt,
+            decrement_stmt,
+            get_reactors_stmt,
+            get_counts_stmt,
+            get_count_stmt,
+            get_reactors_batch_stmt,
+            get_counts_batch_stmt,
+            purge_reactions_stmt,
+            purge_counts_stmt,
+        })
+    }
+
+    /// Ставит реакцию. Возвращает `None`, если пользователь уже ставил этот эмодзи,
+    /// иначе — новое значение счётчика. Новый эмодзи сверх `MAX_REACTIONS_PER_USER`
+    /// отклоняется с `ReactionError::TooManyReactions`
+    pub async fn add_reaction(&self, message_id: Uuid, user_id: Uuid, emoji: &str) -> Result<Option<i64>, ReactionError> {
+        let own = self.user_emojis(message_id, user_id).await?;
+        if own.iter().any(|e| e == emoji) {
+            return Ok(None);
+        }
+        if own.len() >= MAX_REACTIONS_PER_USER {
+            return Err(ReactionError::TooManyReactions);
+        }
+
+        let qr = self.session
+            .execute(&self.insert_reaction_stmt, (message_id, emoji, user_id, Utc::now()))
+            .await
+            .context("insert reaction")?;
+
+        // Счётчик меняем только если реакция действительно добавлена,
+        // иначе повторные запросы разъедут его с множеством реакций
+        if !lwt_applied(&qr) {
+            return Ok(None);
+        }
+
+        self.session.execute(&self.increment_stmt, (message_id, emoji))
+            .await.context("increment reaction count")?;
+
+        Ok(Some(self.get_count(message_id, emoji).await?))
+    }
+
+    /// Снимает реакцию. Возвращает `None`, если такой реакции не было,
+    /// иначе — новое значение счётчика
+    pub async fn remove_reaction(&self, message_id: Uuid, user_id: Uuid, emoji: &str) -> Result<Option<i64>> {
+        let qr = self.session
+            .execute(&self.delete_reaction_stmt, (message_id, emoji, user_id))
+            .await
+            .context("delete reaction")?;
+
+        if !lwt_applied(&qr) {
+            return Ok(None);
+        }
+
+        self.session.execute(&self.decrement_stmt, (message_id, emoji))
+            .await.context("decrement reaction count")?;
+
+        Ok(Some(self.get_count(message_id, emoji).await?))
+    }
+
+    /// Эмодзи, которые пользователь уже поставил на сообщение
+    async fn user_emojis(&self, message_id: Uuid, user_id: Uuid) -> Result<Vec<String>> {
+        let mut rows = self.session.execute_iter(self.get_reactors_stmt.clone(), (message_id,))
+            .await.context("query reactors")?
+            .into_typed::<(String, Uuid)>();
+
+        let mut emojis = Vec::new();
+        while let Some(row) = rows.next().await {
+            let (emoji, reactor) = row?;
+            if reactor == user_id {
+                emojis.push(emoji);
+            }
+        }
+        Ok(emojis)
+    }
+
+    async fn get_count(&self, message_id: Uuid, emoji: &str) -> Result<i64> {
+        let qr = self.session.execute(&self.get_count_stmt, (message_id, emoji))
+            .await.context("query reaction count")?;
+
+        if let Some(rows) = qr.rows {
+            if let Some(row) = rows.into_typed::<(Option<Counter>,)>().next() {
+                let (count,) = row?;
+                return Ok(count.map(|c| c.0).unwrap_or(0).max(0));
+            }
+        }
+
+        Ok(0)
+    }
+
+    /// Реакции на сообщение, сгруппированные по эмодзи
+    pub async fn get_reactions(&self, message_id: Uuid) -> Result<Vec<Reaction>> {
+        let counts_qr = self.session.execute(&self.get_counts_stmt, (message_id,))
+            .await.context("query reaction counts")?;
+
+        let mut counts: HashMap<String, i64> = HashMap::new();
+        if let Some(rows) = counts_qr.rows {
+            for row in rows.into_typed::<(String, Option<Counter>)>() {
+                let (emoji, count) = row?;
+                counts.insert(emoji, count.map(|c| c.0).unwrap_or(0));
+            }
+        }
+
+        let reactors_qr = self.session.execute(&self.get_reactors_stmt, (message_id,))
+            .await.context("query reactors")?;
+
+        let mut reactors: BTreeMap<String, Vec<Uuid>> = BTreeMap::new();
+        if let Some(rows) = reactors_qr.rows {
+            for row in rows.into_typed::<(String, Uuid)>() {
+                let (emoji, user_id) = row?;
+                reactors.entry(emoji).or_default().push(user_id);
+            }
+        }
+
+        Ok(group_reactions(&counts, reactors))
+    }
+
+    /// Реакции для пачки сообщений: два запроса на всю пачку, результаты читаются постранично
+    pub async fn get_reactions_for(&self, message_ids: &[Uuid]) -> Result<HashMap<Uuid, Vec<Reaction>>> {
+        if message_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut counts: HashMap<Uuid, HashMap<String, i64>> = HashMap::new();
+        let mut rows = self.session.execute_iter(self.get_counts_batch_stmt.clone(), (message_ids.to_vec(),))
+            .await.context("query reaction counts batch")?
+            .into_typed::<(Uuid, String, Option<Counter>)>();
+        while let Some(row) = rows.next().await {
+            let (message_id, emoji, count) = row?;
+            counts.entry(message_id).or_default().insert(emoji, count.map(|c| c.0).unwrap_or(0));
+        }
+
+        let mut reactors: HashMap<Uuid, BTreeMap<String, Vec<Uuid>>> = HashMap::new();
+        let mut rows = self.session.execute_iter(self.get_reactors_batch_stmt.clone(), (message_ids.to_vec(),))
+            .await.context("query reactors batch")?
+            .into_typed::<(Uuid, String, Uuid)>();
+        while let Some(row) = rows.next().await {
+            let (message_id, emoji, user_id) = row?;
+            reactors.entry(message_id).or_default().entry(emoji).or_default().push(user_id);
+        }
+
+        let empty = HashMap::new();
+        Ok(reactors
+            .into_iter()
+            .map(|(message_id, by_emoji)| {
+                let counts = counts.get(&message_id).unwrap_or(&empty);
+                (message_id, group_reactions(counts, by_emoji))
+            })
+            .collect())
+    }
+
+    /// Удаляет все реакции сообщения (при безвозвратном удалении)
+    pub async fn purge(&self, message_id: Uuid) -> Result<()> {
+        self.session.execute(&self.purge_reactions_stmt, (message_id,))
+            .await.context("purge reactions")?;
+
+        self.session.execute(&self.purge_counts_stmt, (message_id,))
+            .await.context("purge reaction counts")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_emoji_sequences() {
+        for emoji in ["👍", "❤️", "👍🏽", "👨‍👩‍👧", "🇷🇺", "1️⃣", "🏴󠁧󠁢󠁳󠁣󠁴󠁿", "⭐"] {
+            assert!(is_valid_emoji(emoji), "{emoji} should be accepted");
+        }
+    }
+
+    #[test]
+    fn rejects_plain_text_and_markup() {
+        for emoji in ["", "lol", "<b>x</b>", "1", "👍 👍", "👍lol", "\u{200D}\u{FE0F}"] {
+            assert!(!is_valid_emoji(emoji), "{emoji:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn rejects_too_long_sequences() {
+        assert!(!is_valid_emoji(&"👍".repeat(MAX_EMOJI_LEN / 4 + 1)));
+    }
+}
@@ -0,0 +1,182 @@
+// src/db/read_markers.rs
+
+use std::sync::Arc;
+use scylla::{Session, IntoTypedRows, prepared_statement::PreparedStatement};
+
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use anyhow::{Result, Context};
+use serde::{Serialize, Deserialize};
+
+use crate::db::reactions::lwt_applied;
+
+/// Сколько непрочитанных максимум считаем; дальше клиент показывает "999+"
+pub const MAX_UNREAD_COUNT: i32 = 999;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadMarker {
+    pub chat_id: Uuid,
+    pub user_id: Uuid,
+    pub last_read_message_id: Uuid,
+    pub last_read_created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct ReadMarkersDb {
+    pub session: Arc<Session>,
+
+    // Отметка только сдвигается вперёд: обе записи условные
+    insert_marker_stmt: PreparedStatement,
+    advance_marker_stmt: PreparedStatement,
+    get_marker_stmt: PreparedStatement,
+    get_chat_markers_stmt: PreparedStatement,
+    messages_after_stmt: PreparedStatement,
+    messages_all_stmt: PreparedStatement,
+}
+
+impl ReadMarkersDb {
+    pub async fn new(session: Arc<Session>) -> Result<Self> {
+        let insert_marker_stmt = session.prepare(
+            "INSERT INTO chat_read_markers (chat_id, user_id, last_read_message_id, last_read_created_at, updated_at) VALUES (?, ?, ?, ?, ?) IF NOT EXISTS"
+        ).await.context("prepare insert_read_marker")?;
+
+        let advance_marker_stmt = session.prepare(
+            "UPDATE chat_read_markers SET last_read_message_id = ?, last_read_created_at = ?, updated_at = ? \
+            WHERE chat_id = ? AND user_id = ? IF last_read_created_at < ?"
+        ).await.context("prepare advance_read_marker")?;
+
+        let get_marker_stmt = session.prepare(
+            "SELECT chat_id, user_id, last_read_message_id, last_read_created_at, updated_at FROM chat_read_markers WHERE chat_id = ? AND user_id = ?"
+        ).await.context("prepare get_read_marker")?;
+
+        let get_chat_markers_stmt = session.prepare(
+            "SELECT chat_id, user_id, last_read_message_id, last_read_created_at, updated_at FROM chat_read_markers WHERE chat_id = ?"
+        ).await.context("prepare get_chat_read_markers")?;
+
+        let messages_after_stmt = session.prepare(
+            "SELECT user_id, is_deleted FROM messages WHERE chat_id = ? AND created_at > ? LIMIT ?"
+        ).await.context("prepare messages_after")?;
+
+        let messages_all_stmt = session.prepare(
+            "SELECT user_id, is_deleted FROM messages WHERE chat_id = ? LIMIT ?"
+        ).await.context("prepare messages_all")?;
+
+        Ok(Self {
+            session,
+
+            insert_marker_stmt,
+            advance_marker_stmt,
+            get_marker_stmt,
+            get_chat_markers_stmt,
+            messages_after_stmt,
+            messages_all_stmt,
+        })
+    }
+
+    pub async fn get_marker(&self, chat_id: Uuid, user_id: Uuid) -> Result<Option<ReadMarker>> {
+        let qr = self.session.execute(&self.get_marker_stmt, (chat_id, user_id))
+            .await.context("query read marker")?;
+
+        if let Some(rows) = qr.rows {
+            if let Some(row) = rows.into_typed::<(Uuid, Uuid, Uuid, DateTime<Utc>, DateTime<Utc>)>().next() {
+                let (chat_id, user_id, last_read_message_id, last_read_created_at, updated_at) = row?;
+                return Ok(Some(ReadMarker { chat_id, user_id, last_read_message_id, last_read_created_at, updated_at }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Отметки прочтения всех участников чата
+    pub async fn get_chat_markers(&self, chat_id: Uuid) -> Result<Vec<ReadMarker>> {
+        let qr = self.session.execute(&self.get_chat_markers_stmt, (chat_id,))
+            .await.context("query chat read markers")?;
+
+        let mut markers = Vec::new();
+        if let Some(rows) = qr.rows {
+            for row in rows.into_typed::<(Uuid, Uuid, Uuid, DateTime<Utc>, DateTime<Utc>)>() {
+                let (chat_id, user_id, last_read_message_id, last_read_created_at, updated_at) = row?;
+                markers.push(ReadMarker { chat_id, user_id, last_read_message_id, last_read_created_at, updated_at });
+            }
+        }
+
+        Ok(markers)
+    }
+
+    /// Сдвигает отметку прочтения вперёд. Возвращает новую отметку или `None`,
+    /// если пользователь уже прочитал это сообщение (или более позднее).
+    /// Запись условная, поэтому параллельные отметки с разных устройств не откатывают её назад
+    pub async fn mark_read(
+        &self,
+        chat_id: Uuid,
+        user_id: Uuid,
+        message_id: Uuid,
+        message_created_at: DateTime<Utc>,
+    ) -> Result<Option<ReadMarker>> {
+        let current = self.get_marker(chat_id, user_id).await?;
+        if let Some(current) = &current {
+            if current.last_read_created_at >= message_created_at {
+                return Ok(None);
+            }
+        }
+
+        let marker = ReadMarker {
+            chat_id,
+            user_id,
+            last_read_message_id: message_id,
+            last_read_created_at: message_created_at,
+            updated_at: Utc::now(),
+        };
+
+        // Первая отметка в чате; если её успели создать параллельно — сдвигаем по условию
+        if current.is_none() {
+            let qr = self.session.execute(&self.insert_marker_stmt, (
+                marker.chat_id,
+                marker.user_id,
+                marker.last_read_message_id,
+                marker.last_read_created_at,
+                marker.updated_at,
+            )).await.context("insert read marker")?;
+
+            if lwt_applied(&qr) {
+                return Ok(Some(marker));
+            }
+        }
+
+        let qr = self.session.execute(&self.advance_marker_stmt, (
+            marker.last_read_message_id,
+            marker.last_read_created_at,
+            marker.updated_at,
+            marker.chat_id,
+            marker.user_id,
+            marker.last_read_created_at,
+        )).await.context("advance read marker")?;
+
+        Ok(lwt_applied(&qr).then_some(marker))
+    }
+
+    /// Количество непрочитанных сообщений от других участников (не больше `MAX_UNREAD_COUNT`)
+    pub async fn unread_count(&self, chat_id: Uuid, user_id: Uuid) -> Result<i32> {
+        let marker = self.get_marker(chat_id, user_id).await?;
+
+        // Чужие сообщения могут оказаться среди своих, поэтому берём с запасом
+        let limit = MAX_UNREAD_COUNT * 2;
+        let qr = match marker {
+            Some(m) => self.session.execute(&self.messages_after_stmt, (chat_id, m.last_read_created_at, limit)).await,
+            None => self.session.execute(&self.messages_all_stmt, (chat_id, limit)).await,
+        }.context("query unread messages")?;
+
+        let mut count = 0;
+        if let Some(rows) = qr.rows {
+            for row in rows.into_typed::<(Uuid, Option<bool>)>() {
+                let (sender, is_deleted) = row?;
+                if sender != user_id && !is_deleted.unwrap_or(false) {
+                    count += 1;
+                }
+            }
+        }
+
+        Ok(count.min(MAX_UNREAD_COUNT))
+    }
+}
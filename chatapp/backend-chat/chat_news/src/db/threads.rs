@@ -0,0 +1,224 @@
+// src/db/threads.rs
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use scylla::{
+    Session, IntoTypedRows, prepared_statement::PreparedStatement,
+    frame::value::Counter,
+};
+use futures_util::StreamExt;
+
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use anyhow::{Result, Context};
+use serde::{Serialize, Deserialize};
+
+use crate::models::ServerEvent;
+
+/// Сводка по треду для корневого сообщения
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadStats {
+    pub reply_count: i64,
+    pub last_reply_at: Option<DateTime<Utc>>,
+    pub last_reply_id: Option<Uuid>,
+}
+
+#[derive(Clone)]
+pub struct ThreadsDb {
+    pub session: Arc<Session>,
+
+    increment_stmt: PreparedStatement,
+    decrement_stmt: PreparedStatement,
+    get_count_stmt: PreparedStatement,
+    last_reply_stmt: PreparedStatement,
+    get_counts_batch_stmt: PreparedStatement,
+    last_replies_batch_stmt: PreparedStatement,
+    reply_ids_stmt: PreparedStatement,
+    delete_reply_stmt: PreparedStatement,
+    purge_thread_stmt: PreparedStatement,
+    purge_count_stmt: PreparedStatement,
+}
+
+impl ThreadsDb {
+    pub async fn new(session: Arc<Session>) -> Result<Self> {
+        let increment_stmt = session.prepare(
+            "UPDATE thread_reply_counts SET reply_count = reply_count + 1 WHERE thread_root_id = ?"
+        ).await.context("prepare increment_thread_replies")?;
+
+        let decrement_stmt = session.prepare(
+            "UPDATE thread_reply_counts SET reply_count = reply_count - 1 WHERE thread_root_id = ?"
+        ).await.context("prepare decrement_thread_replies")?;
+
+        let get_count_stmt = session.prepare(
+            "SELECT reply_count FROM thread_reply_counts WHERE thread_root_id = ?"
+        ).await.context("prepare get_thread_reply_count")?;
+
+        let last_reply_stmt = session.prepare(
+            "SELECT created_at, message_id FROM thread_messages WHERE thread_root_id = ? LIMIT 1"
+        ).await.context("prepare get_last_thread_reply")?;
+
+        let get_counts_batch_stmt = session.prepare(
+            "SELECT thread_root_id, reply_count FROM thread_reply_counts WHERE thread_root_id IN ?"
+        ).await.context("prepare get_thread_reply_counts_batch")?;
+
+        let last_replies_batch_stmt = session.prepare(
+            "SELECT thread_root_id, created_at, message_id FROM thread_messages WHERE thread_root_id IN ? PER PARTITION LIMIT 1"
+        ).await.context("prepare get_last_thread_replies_batch")?;
+
+        let reply_ids_stmt = session.prepare(
+            "SELECT message_id FROM thread_messages WHERE thread_root_id = ?"
+        ).await.context("prepare get_thread_reply_ids")?;
+
+        let delete_reply_stmt = session.prepare(
+            "DELETE FROM thread_messages WHERE thread_root_id = ? AND created_at = ? AND message_id = ?"
+        ).await.context("prepare delete_thread_reply")?;
+
+        let purge_thread_stmt = session.prepare(
+            "DELETE FROM thread_messages WHERE thread_root_id = ?"
+        ).await.context("prepare purge_thread")?;
+
+        let purge_count_stmt = session.prepare(
+            "DELETE FROM thread_reply_counts WHERE thread_root_id = ?"
+        ).await.context("prepare purge_thread_count")?;
+
+        Ok(Self {
+            session,
+
+            increment_stmt,
+            decrement_stmt,
+            get_count_stmt,
+            last_reply_stmt,
+            get_counts_batch_stmt,
+            last_replies_batch_stmt,
+            reply_ids_stmt,
+            delete_reply_stmt,
+            purge_thread_stmt,
+            purge_count_stmt,
+        })
+    }
+
+    /// Учитывает новый ответ в счётчике треда
+    pub async fn increment(&self, thread_root_id: Uuid) -> Result<()> {
+        self.session.execute(&self.increment_stmt, (thread_root_id,))
+            .await.context("increment thread replies")?;
+        Ok(())
+    }
+
+    /// Убирает ответ из ленты треда (при безвозвратном удалении)
+    pub async fn remove_reply(&self, thread_root_id: Uuid, created_at: DateTime<Utc>, message_id: Uuid) -> Result<()> {
+        self.session.execute(&self.delete_reply_stmt, (thread_root_id, created_at, message_id))
+            .await.context("delete thread reply")?;
+
+        self.session.execute(&self.decrement_stmt, (thread_root_id,))
+            .await.context("decrement thread replies")?;
+
+        Ok(())
+    }
+
+    /// Сводка по треду или `None`, если ответов нет
+    pub async fn get_stats(&self, thread_root_id: Uuid) -> Result<Option<ThreadStats>> {
+        let qr = self.session.execute(&self.get_count_stmt, (thread_root_id,))
+            .await.context("query thread reply count")?;
+
+        let mut reply_count = 0;
+        if let Some(rows) = qr.rows {
+            if let Some(row) = rows.into_typed::<(Option<Counter>,)>().next() {
+                let (count,) = row?;
+                reply_count = count.map(|c| c.0).unwrap_or(0).max(0);
+            }
+        }
+
+        if reply_count == 0 {
+            return Ok(None);
+        }
+
+        let qr = self.session.execute(&self.last_reply_stmt, (thread_root_id,))
+            .await.context("query last thread reply")?;
+
+        let mut stats = ThreadStats { reply_count, last_reply_at: None, last_reply_id: None };
+        if let Some(rows) = qr.rows {
+            if let Some(row) = rows.into_typed::<(DateTime<Utc>, Uuid)>().next() {
+                let (created_at, message_id) = row?;
+                stats.last_reply_at = Some(created_at);
+                stats.last_reply_id = Some(message_id);
+            }
+        }
+
+        Ok(Some(stats))
+    }
+
+    /// Событие `thread.updated` с актуальной сводкой по треду
+    pub async fn updated_event(&self, thread_root_id: Uuid) -> Result<ServerEvent> {
+        let stats = self.get_stats(thread_root_id).await?;
+        Ok(ServerEvent::ThreadUpdated {
+            thread_root_id,
+            reply_count: stats.as_ref().map(|s| s.reply_count).unwrap_or(0),
+            last_reply_at: stats.as_ref().and_then(|s| s.last_reply_at),
+            last_reply_id: stats.and_then(|s| s.last_reply_id),
+        })
+    }
+
+    /// Сводки для пачки сообщений: счётчики одним запросом, последние ответы — вторым,
+    /// и только для тредов, где ответы есть
+    pub async fn get_stats_for(&self, message_ids: &[Uuid]) -> Result<HashMap<Uuid, ThreadStats>> {
+        let mut out = HashMap::new();
+        if message_ids.is_empty() {
+            return Ok(out);
+        }
+
+        let mut rows = self.session.execute_iter(self.get_counts_batch_stmt.clone(), (message_ids.to_vec(),))
+            .await.context("query thread reply counts batch")?
+            .into_typed::<(Uuid, Option<Counter>)>();
+        while let Some(row) = rows.next().await {
+            let (thread_root_id, count) = row?;
+            let reply_count = count.map(|c| c.0).unwrap_or(0);
+            if reply_count > 0 {
+                out.insert(thread_root_id, ThreadStats { reply_count, last_reply_at: None, last_reply_id: None });
+            }
+        }
+
+        if out.is_empty() {
+            return Ok(out);
+        }
+
+        let roots: Vec<Uuid> = out.keys().copied().collect();
+        let mut rows = self.session.execute_iter(self.last_replies_batch_stmt.clone(), (roots,))
+            .await.context("query last thread replies batch")?
+            .into_typed::<(Uuid, DateTime<Utc>, Uuid)>();
+        while let Some(row) = rows.next().await {
+            let (thread_root_id, created_at, message_id) = row?;
+            if let Some(stats) = out.get_mut(&thread_root_id) {
+                stats.last_reply_at = Some(created_at);
+                stats.last_reply_id = Some(message_id);
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Идентификаторы всех ответов треда. Тред может быть длинным, поэтому читаем все страницы
+    pub async fn reply_ids(&self, thread_root_id: Uuid) -> Result<Vec<Uuid>> {
+        let mut rows = self.session.execute_iter(self.reply_ids_stmt.clone(), (thread_root_id,))
+            .await.context("query thread reply ids")?
+            .into_typed::<(Uuid,)>();
+
+        let mut ids = Vec::new();
+        while let Some(row) = rows.next().await {
+            let (message_id,) = row?;
+            ids.push(message_id);
+        }
+
+        Ok(ids)
+    }
+
+    /// Удаляет ленту и счётчик треда (при безвозвратном удалении корня)
+    pub async fn purge(&self, thread_root_id: Uuid) -> Result<()> {
+        self.session.execute(&self.purge_thread_stmt, (thread_root_id,))
+            .await.context("purge thread")?;
+
+        self.session.execute(&self.purge_count_stmt, (thread_root_id,))
+            .await.context("purge thread count")?;
+
+        Ok(())
+    }
+}
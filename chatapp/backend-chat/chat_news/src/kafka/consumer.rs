@@ -5,86 +5,296 @@ use rdkafka::{
     Message,
 };
 use tokio_stream::StreamExt;
-use tracing::{info, error, trace};
+use tracing::{info, error, trace, debug, warn};
 use serde_json::from_slice;
 use anyhow::Result;
 use std::sync::Arc;
+use std::time::Duration;
+use chrono::Utc;
 
-// ✅ Достаточно только Headers
-use rdkafka::message::Headers;
+// Заголовки — для трассировки, BorrowedMessage — для коммита оффсетов
+use rdkafka::message::{BorrowedMessage, Headers};
 
 use crate::{
     db::messages::Message as DbMessage,
     db::ScyllaDb,
-    models::ChatEvent,
-    websocket::manager::ConnectionManager,
+    kafka::producer::KafkaProducer,
+    search::SearchIndex,
+    models::{ChatEvent, EventEnvelope, ServerEvent},
+    websocket::manager::{ConnectionManager, RoomControl},
 };
 
-pub async fn run_consumer(
-    brokers: &str,
-    topic: &str,
-    scylla: Arc<ScyllaDb>,
-    ws_manager: Arc<ConnectionManager>,
-) -> Result<()> {
+/// Группа, которая пишет события в Scylla (одна на весь кластер)
+const PERSIST_GROUP_ID: &str = "chat-service-group-v1";
+
+/// События старше этого возраста не рассылаются клиентам (fan-out сильно отстал)
+const MAX_LIVE_EVENT_AGE_SECS: i64 = 60;
+
+/// Пауза между повторами записи в Scylla или Kafka; растёт до `MAX_RETRY_DELAY`
+const RETRY_DELAY: Duration = Duration::from_millis(200);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Откуда читать партицию, для которой у группы нет сохранённого оффсета
+#[derive(Clone, Copy)]
+enum StartFrom {
+    Earliest,
+    Latest,
+}
+
+fn create_consumer(brokers: &str, group_id: &str, topic: &str, start_from: StartFrom) -> Result<StreamConsumer> {
+    let offset_reset = match start_from {
+        StartFrom::Earliest => "earliest",
+        StartFrom::Latest => "latest",
+    };
+
     let consumer: StreamConsumer = rdkafka::ClientConfig::new()
         .set("bootstrap.servers", brokers)
-        .set("group.id", "chat-service-group-v1")
+        .set("group.id", group_id)
         .set("session.timeout.ms", "6000")
         .set("enable.auto.commit", "false")
-        .set("auto.offset.reset", "earliest")
+        .set("auto.offset.reset", offset_reset)
         .set("enable.partition.eof", "false")
         .set("fetch.wait.max.ms", "500")
         .create()?;
 
     consumer.subscribe(&[topic])?;
+    Ok(consumer)
+}
+
+/// Асинхронно коммитит оффсет сообщения. Сбой коммита не теряет событий:
+/// после рестарта оно придёт повторно, а запись в Scylla идемпотентна
+fn commit_offset(consumer: &StreamConsumer, message: &BorrowedMessage<'_>) {
+    if let Err(e) = consumer.commit_message(message, CommitMode::Async) {
+        warn!("Failed to commit Kafka offset {} of partition {}: {:?}", message.offset(), message.partition(), e);
+    }
+}
+
+/// Повторяет операцию, пока она не пройдёт. Пропустить событие нельзя:
+/// следующий коммит оффсета похоронил бы его навсегда
+async fn retry_until_ok<T, F, Fut>(what: &str, mut op: F) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T>>,
+{
+    let mut delay = RETRY_DELAY;
+    loop {
+        match op().await {
+            Ok(value) => return value,
+            Err(e) => {
+                error!("{} failed, retrying in {:?}: {:?}", what, delay, e);
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+        }
+    }
+}
+
+/// Разбирает событие из топика. Сообщения в старом формате (голый `ChatEvent`)
+/// трактуются как `message.created`
+fn parse_event(payload: &[u8]) -> serde_json::Result<EventEnvelope> {
+    match from_slice::<EventEnvelope>(payload) {
+        Ok(envelope) => Ok(envelope),
+        Err(e) => match from_slice::<ChatEvent>(payload) {
+            Ok(legacy) => Ok(EventEnvelope::new(legacy.chat_id, ServerEvent::MessageCreated(legacy))),
+            Err(_) => Err(e),
+        },
+    }
+}
+
+/// Поддерживает поисковый индекс в актуальном состоянии
+async fn update_search_index(scylla: &ScyllaDb, search: &SearchIndex, event: &ServerEvent) -> Result<()> {
+    match event {
+        ServerEvent::MessageCreated(ev) => search.upsert(&DbMessage::from_chat_event(ev.clone())),
+        ServerEvent::MessageEdited { message_id, .. }
+        | ServerEvent::MessageRestored { message_id, .. }
+        | ServerEvent::MessageMediaAttached { message_id, .. } => {
+            // В событии есть не все поля документа — берём актуальную версию из Scylla
+            match scylla.get_message_by_id(*message_id).await? {
+                Some(msg) => search.upsert(&msg),
+                None => search.remove(*message_id),
+            }
+        }
+        ServerEvent::MessageDeleted { message_id, .. } | ServerEvent::MessagePurged { message_id, .. } => {
+            search.remove(*message_id)
+        }
+        _ => Ok(()),
+    }
+}
 
-    info!("Kafka consumer started, subscribed to topic '{}'", topic);
+/// Persistence: сохраняет новые сообщения в Scylla и передаёт все события в `persisted_topic`.
+/// Каждое событие обрабатывает ровно один инстанс; до fan-out доходит только то, что уже записано
+pub async fn run_persister(
+    brokers: &str,
+    topic: &str,
+    scylla: Arc<ScyllaDb>,
+    producer: Arc<KafkaProducer>,
+    persisted: Arc<KafkaProducer>,
+) -> Result<()> {
+    let consumer = create_consumer(brokers, PERSIST_GROUP_ID, topic, StartFrom::Earliest)?;
+    info!("Kafka persister started, group '{}', topic '{}' -> '{}'", PERSIST_GROUP_ID, topic, persisted.topic);
 
     let mut stream = consumer.stream();
 
     while let Some(result) = stream.next().await {
-        match result {
-            Ok(borrowed_message) => {
-                if let Some(payload) = borrowed_message.payload() {
-                    match from_slice::<ChatEvent>(payload) {
-                        Ok(event) => {
-                            let event_clone = event.clone();
-                            let db_msg = DbMessage::from_chat_event(event);
-
-                            if let Err(e) = scylla.insert_message(&db_msg).await {
-                                error!("Failed to insert message {} into Scylla: {:?}", db_msg.message_id, e);
-                                continue;
-                            }
-
-                            info!("Stored message {} for chat {}", db_msg.message_id, db_msg.chat_id);
-
-                            if let Err(e) = ws_manager.broadcast(event_clone).await {
-                                error!("Failed to broadcast event {}: {:?}", db_msg.message_id, e);
-                            }
-
-                            // ✅ Исправлено: убрана аннотация Result
-                            consumer.commit_message(&borrowed_message, CommitMode::Async);
-                        }
-                        Err(e) => {
-                            error!("Failed to parse Chat)))) from Kafka: {:?}", e);
-                            consumer.commit_message(&borrowed_message, CommitMode::Async);
-                        }
-                    }
-                } else {
-                    trace!("Received empty Kafka message (no payload)");
-                    if let Some(headers) = borrowed_message.headers() {
-                        trace!("Empty message with {} headers", headers.iter().count());
-                    }
-                    // ✅ Исправлено здесь тоже
-                    consumer.commit_message(&borrowed_message, CommitMode::Async);
-                }
+        let borrowed_message = match result {
+            Ok(m) => m,
+            Err(e) => {
+                error!("Kafka persister stream error: {:?}", e);
+                continue;
+            }
+        };
+
+        let Some(payload) = borrowed_message.payload() else {
+            trace!("Received empty Kafka message (no payload)");
+            if let Some(headers) = borrowed_message.headers() {
+                trace!("Empty message with {} headers", headers.iter().count());
+            }
+            commit_offset(&consumer, &borrowed_message);
+            continue;
+        };
+
+        let envelope = match parse_event(payload) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                // Битое событие не разберётся и при повторе
+                error!("Failed to parse event from Kafka: {:?}", e);
+                commit_offset(&consumer, &borrowed_message);
+                continue;
+            }
+        };
+
+        // Остальные события пишутся в базу до публикации, новые сообщения — здесь
+        let mut thread_root = None;
+        if let ServerEvent::MessageCreated(ref event) = envelope.event {
+            let db_msg = DbMessage::from_chat_event(event.clone());
+
+            retry_until_ok("insert message into Scylla", || scylla.insert_message(&db_msg)).await;
+            info!("Stored message {} for chat {}", db_msg.message_id, db_msg.chat_id);
+
+            thread_root = db_msg.thread_root_id;
+        }
+
+        retry_until_ok("forward event to persisted topic", || persisted.send(&envelope)).await;
+
+        // Сводка треда изменилась — рассылаем её отдельным событием
+        if let Some(thread_root_id) = thread_root {
+            match scylla.threads.updated_event(thread_root_id).await {
+                Ok(event) => producer.publish_or_warn(envelope.chat_id, event).await,
+                Err(e) => error!("Failed to load thread {} stats: {:?}", thread_root_id, e),
             }
+        }
+
+        commit_offset(&consumer, &borrowed_message);
+    }
+
+    Ok(())
+}
+
+/// Fan-out: читает уже сохранённые события. У каждого инстанса своя группа, поэтому событие
+/// доходит до всех реплик. Оффсеты не сохраняются: после рестарта чтение идёт с конца топика,
+/// пропущенное клиенты добирают через resync. Здесь же обновляется локальный поисковый индекс
+/// и его отметка, с которой после рестарта начинается догонка из Scylla
+pub async fn run_fanout(
+    brokers: &str,
+    persisted_topic: &str,
+    instance_id: &str,
+    scylla: Arc<ScyllaDb>,
+    search: Arc<SearchIndex>,
+    ws_manager: Arc<ConnectionManager>,
+) -> Result<()> {
+    let group_id = format!("chat-service-fanout-{}", instance_id);
+    let consumer = create_consumer(brokers, &group_id, persisted_topic, StartFrom::Latest)?;
+    info!("Kafka fan-out started, group '{}', topic '{}'", group_id, persisted_topic);
+
+    let mut stream = consumer.stream();
+
+    while let Some(result) = stream.next().await {
+        let borrowed_message = match result {
+            Ok(m) => m,
             Err(e) => {
-                error!("Kafka consumer stream error: {:?}", e);
+                error!("Kafka fan-out stream error: {:?}", e);
                 continue;
             }
+        };
+
+        if let Some(payload) = borrowed_message.payload() {
+            match parse_event(payload) {
+                Ok(envelope) => {
+                    let (event_id, kind) = (envelope.event_id, envelope.kind());
+                    match update_search_index(&scylla, &search, &envelope.event).await {
+                        Ok(()) => search.mark_indexed_until(envelope.occurred_at),
+                        Err(e) => error!("Failed to update search index for event {} ({}): {:?}", event_id, kind, e),
+                    }
+
+                    let age = Utc::now() - envelope.occurred_at;
+                    if age.num_seconds() > MAX_LIVE_EVENT_AGE_SECS {
+                        debug!("Skipping stale event {} ({}), age {}s", event_id, kind, age.num_seconds());
+                        continue;
+                    }
+
+                    let chat_id = envelope.chat_id;
+
+                    // Новый участник ещё не подписан на комнату — доставляем ему напрямую на все устройства
+                    // и подписываем его открытые соединения
+                    if let ServerEvent::MemberJoined { user_id, .. } = envelope.event {
+                        ws_manager.send_to_user(user_id, envelope.clone()).await;
+                        ws_manager.control_user(user_id, RoomControl::Join(chat_id)).await;
+                    }
+
+                    // Удалённый участник отписывается уже после того, как получит само событие
+                    let left = match envelope.event {
+                        ServerEvent::MemberLeft { user_id, .. } => Some(user_id),
+                        _ => None,
+                    };
+
+                    if let Err(e) = ws_manager.broadcast(envelope).await {
+                        error!("Failed to broadcast event {} ({}): {:?}", event_id, kind, e);
+                    }
+
+                    if let Some(user_id) = left {
+                        ws_manager.control_user(user_id, RoomControl::Leave(chat_id)).await;
+                    }
+                }
+                Err(e) => error!("Failed to parse event from Kafka: {:?}", e),
+            }
         }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn parses_envelope() {
+        let event = ChatEvent::new_message(Uuid::new_v4(), Uuid::new_v4(), Some("hi".into()), None, None);
+        let envelope = EventEnvelope::new(event.chat_id, ServerEvent::MessageCreated(event));
+        let payload = serde_json::to_vec(&envelope).unwrap();
+
+        let parsed = parse_event(&payload).unwrap();
+        assert_eq!(parsed.event_id, envelope.event_id);
+        assert_eq!(parsed.kind(), "message.created");
+    }
+
+    #[test]
+    fn legacy_chat_event_becomes_message_created() {
+        let legacy = ChatEvent::new_message(Uuid::new_v4(), Uuid::new_v4(), Some("old".into()), None, None);
+        let payload = serde_json::to_vec(&legacy).unwrap();
+
+        let parsed = parse_event(&payload).unwrap();
+        assert_eq!(parsed.chat_id, legacy.chat_id);
+        match parsed.event {
+            ServerEvent::MessageCreated(event) => assert_eq!(event.message_id, legacy.message_id),
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert!(parse_event(b"{\"hello\":1}").is_err());
+        assert!(parse_event(b"not json").is_err());
+    }
+}
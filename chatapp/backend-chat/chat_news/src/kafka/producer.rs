@@ -3,11 +3,16 @@
 use rdkafka::producer::{FutureProducer, FutureRecord};
 use rdkafka::util::Timeout;
 use std::time::Duration;
-use tracing::{info, error};
+use tracing::{info, error, warn};
 use anyhow::Result;
 use serde_json::to_string;
 
-use crate::models::ChatEvent;
+use crate::models::{EventEnvelope, ServerEvent};
+
+/// Сколько раз `publish_or_warn` пробует отправить событие
+const PUBLISH_ATTEMPTS: u32 = 3;
+/// Пауза перед повтором растёт линейно с номером попытки
+const PUBLISH_RETRY_DELAY: Duration = Duration::from_millis(500);
 
 pub struct KafkaProducer {
     pub inner: FutureProducer,
@@ -28,10 +33,11 @@ impl KafkaProducer {
         })
     }
 
-    pub async fn send(&self, event: &ChatEvent) -> Result<()> {
+    pub async fn send(&self, event: &EventEnvelope) -> Result<()> {
         let payload = to_string(event)
-            .map_err(|e| anyhow::anyhow!("Failed to serialize ChatEvent: {}", e))?;
+            .map_err(|e| anyhow::anyhow!("Failed to serialize EventEnvelope: {}", e))?;
 
+        // Ключ по chat_id сохраняет порядок событий внутри чата
         let key = event.chat_id.to_string();
 
         // ✅ Исправлено: убрали .timestamp() — rdkafka сам ставит timestamp
@@ -41,8 +47,8 @@ impl KafkaProducer {
 
         match self.inner.send(record, Timeout::After(Duration::from_secs(5))).await {
             Ok((partition, offset)) => {
-                info!("Event sent to topic={} partition={} offset={} chat_id={}",
-                      self.topic, partition, offset, event.chat_id);
+                info!("Event {} sent to topic={} partition={} offset={} chat_id={}",
+                      event.kind(), self.topic, partition, offset, event.chat_id);
                 Ok(())
             }
             Err((kafka_err, _msg)) => {
@@ -51,6 +57,35 @@ impl KafkaProducer {
             }
         }
     }
+
+    /// Оборачивает событие в конверт и отправляет его в топик чата
+    pub async fn publish(&self, chat_id: uuid::Uuid, event: ServerEvent) -> Result<EventEnvelope> {
+        let envelope = EventEnvelope::new(chat_id, event);
+        self.send(&envelope).await?;
+        Ok(envelope)
+    }
+
+    /// Публикует событие об уже сохранённом изменении. Откатить запись в базе уже нельзя,
+    /// поэтому при сбое брокера отправка повторяется с тем же `event_id`; после последней
+    /// попытки событие только логируется. Изменения сообщений к этому моменту уже
+    /// отмечены в `message_changes` (см. `ScyllaDb::record_change`), так что клиенты
+    /// догонят их через resync; для остальных событий потеря окончательна
+    pub async fn publish_or_warn(&self, chat_id: uuid::Uuid, event: ServerEvent) {
+        let envelope = EventEnvelope::new(chat_id, event);
+
+        for attempt in 1..=PUBLISH_ATTEMPTS {
+            match self.send(&envelope).await {
+                Ok(()) => return,
+                Err(e) if attempt < PUBLISH_ATTEMPTS => {
+                    warn!("Failed to publish {} for chat {} (attempt {}): {:?}", envelope.kind(), chat_id, attempt, e);
+                    tokio::time::sleep(PUBLISH_RETRY_DELAY * attempt).await;
+                }
+                Err(e) => {
+                    error!("Dropping {} for chat {} after {} attempts: {:?}", envelope.kind(), chat_id, attempt, e);
+                }
+            }
+        }
+    }
 }
 
 impl Clone for KafkaProducer {
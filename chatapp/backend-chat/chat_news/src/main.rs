@@ -6,12 +6,16 @@ mod db;
 mod kafka;
 mod websocket;
 mod auth;
+mod api;
+mod search;
+mod user;
 
 use axum::{
     Router,
     routing::get,
     response::IntoResponse,
-    extract::{State, WebSocketUpgrade},
+    extract::{Query, State, WebSocketUpgrade},
+    http::HeaderMap,
     Extension,
 };
 use std::sync::Arc;
@@ -22,11 +26,13 @@ use sqlx::PgPool;
 
 // Импорты из ваших модулей
 use crate::{
+    auth::JwksCache,
     config::Config,
-    db::ScyllaDb,
+    db::{ScyllaDb, ChatsDb, ReadMarkersDb, PresenceDb},
     kafka::producer::KafkaProducer,
-    websocket::gateway::ws_handler,
-    auth::AuthUser,
+    search::SearchIndex,
+    user::presence::PresenceTracker,
+    websocket::gateway::{ws_handler, WsParams},
 };
 
 /// Глобальное состояние приложения
@@ -34,18 +40,24 @@ use crate::{
 pub struct AppState {
     pub config: Arc<Config>,
     pub scylla: Arc<ScyllaDb>,
+    pub chats: Arc<ChatsDb>,
+    pub read_markers: Arc<ReadMarkersDb>,
+    pub search: Arc<SearchIndex>,
+    pub presence: Arc<PresenceTracker>,
     pub kafka_producer: Arc<KafkaProducer>,
     pub ws_manager: Arc<websocket::manager::ConnectionManager>,
     pub postgres_pool: PgPool,
+    pub jwks: Arc<JwksCache>,
 }
 
 /// Обработчик WebSocket-подключения
 async fn ws_route(
     ws: WebSocketUpgrade,
-    user: AuthUser,
+    Query(params): Query<WsParams>,
+    headers: HeaderMap,
     State(state): State<Arc<AppState>>,
 ) -> impl IntoResponse {
-    ws_handler(ws, user.0.id, state).await
+    ws_handler(ws, params, &headers, state).await
 }
 
 /// Точка входа
@@ -73,45 +85,118 @@ async fn main() -> Result<()> {
     );
     tracing::info!("✅ Connected to ScyllaDB");
 
+    let chats = Arc::new(ChatsDb::new(scylla.session.clone()).await?);
+    tracing::info!("✅ Chats storage prepared");
+
+    let read_markers = Arc::new(ReadMarkersDb::new(scylla.session.clone()).await?);
+    tracing::info!("✅ Read markers storage prepared");
+
+    // Открываем поисковый индекс
+    let search = SearchIndex::open(&config.search_index_dir)?;
+    search.spawn_committer();
+    tracing::info!("✅ Search index opened at '{}'", config.search_index_dir);
+
+    // Старые сообщения попадают в индекс из Scylla, новые — из fan-out
+    search::backfill::spawn_backfill(scylla.clone(), search.clone(), config.search_reindex_on_start);
+
     // Подключаемся к PostgreSQL
     let postgres_pool = PgPool::connect(&config.postgres_url).await?;
     tracing::info!("✅ Connected to PostgreSQL");
 
+    // Публичные ключи auth-service для проверки access-токенов
+    let jwks = JwksCache::new(&config.jwks_url);
+    if let Err(e) = jwks.refresh().await {
+        // Не падаем: ключи подтянутся при первом токене
+        tracing::warn!("⚠️ JWKS not loaded yet: {:?}", e);
+    } else {
+        tracing::info!("✅ JWKS loaded from {}", config.jwks_url);
+    }
+    jwks.spawn_refresher(config.jwks_refresh_interval);
+
     // Создаём Kafka Producer
     let kafka_producer = Arc::new(
         KafkaProducer::new(&config.kafka_brokers, &config.kafka_chat_topic)?
     );
     tracing::info!("✅ Kafka producer created");
 
+    // Persister передаёт сохранённые события в отдельный топик, fan-out читает только его
+    let persisted_producer = Arc::new(
+        KafkaProducer::new(&config.kafka_brokers, &config.kafka_persisted_topic)?
+    );
+
     // Создаём WebSocket Connection Manager
     let ws_manager = Arc::new(websocket::manager::ConnectionManager::new());
     tracing::info!("✅ WebSocket manager initialized");
 
+    // Отзыв сессий в auth-service сразу закрывает их WebSocket-соединения
+    auth::spawn_revocation_listener(postgres_pool.clone(), ws_manager.clone());
+    tracing::info!("✅ Session revocation listener started");
+
+    // Присутствие пользователей
+    let presence_db = Arc::new(PresenceDb::new(scylla.session.clone()).await?);
+    let presence = PresenceTracker::new(
+        ws_manager.clone(),
+        presence_db,
+        kafka_producer.clone(),
+        config.instance_id.clone(),
+    );
+    presence.spawn_sweeper();
+    tracing::info!("✅ Presence tracker started");
+
     // Создаём общее состояние приложения
     let app_state = Arc::new(AppState {
         config: config.clone(),
         scylla: scylla.clone(),
+        chats: chats.clone(),
+        read_markers: read_markers.clone(),
+        search: search.clone(),
+        presence: presence.clone(),
         kafka_producer: kafka_producer.clone(),
         ws_manager: ws_manager.clone(),
         postgres_pool: postgres_pool.clone(),
+        jwks: jwks.clone(),
     });
 
-    // Запускаем Kafka Consumer в фоне
+    // Запускаем Kafka Consumer'ы в фоне: запись в Scylla и доставка по WebSocket
     {
         let brokers = config.kafka_brokers.clone();
         let topic = config.kafka_chat_topic.clone();
         let scylla_ref = scylla.clone();
+        let producer_ref = kafka_producer.clone();
+        let persisted_ref = persisted_producer.clone();
+
+        task::spawn(async move {
+            tracing::info!("📦 Starting Kafka persister for topic '{}'", topic);
+            if let Err(e) = kafka::consumer::run_persister(
+                &brokers,
+                &topic,
+                scylla_ref,
+                producer_ref,
+                persisted_ref,
+            ).await {
+                tracing::error!("💀 Kafka persister crashed: {:?}", e);
+            }
+        });
+    }
+    {
+        let brokers = config.kafka_brokers.clone();
+        let topic = config.kafka_persisted_topic.clone();
+        let instance_id = config.instance_id.clone();
+        let scylla_ref = scylla.clone();
+        let search_ref = search.clone();
         let ws_manager_ref = ws_manager.clone();
 
         task::spawn(async move {
-            tracing::info!("📦 Starting Kafka consumer for topic '{}'", topic);
-            if let Err(e) = kafka::consumer::run_consumer(
+            tracing::info!("📡 Starting Kafka fan-out for instance '{}'", instance_id);
+            if let Err(e) = kafka::consumer::run_fanout(
                 &brokers,
                 &topic,
+                &instance_id,
                 scylla_ref,
+                search_ref,
                 ws_manager_ref,
             ).await {
-                tracing::error!("💀 Kafka consumer crashed: {:?}", e);
+                tracing::error!("💀 Kafka fan-out crashed: {:?}", e);
             }
         });
     }
@@ -120,6 +205,8 @@ async fn main() -> Result<()> {
     let app = Router::new()
         .route("/ws", get(ws_route))
         .route("/health", get(|| async { "OK" }))
+        .route("/metrics", get(|| async { websocket::metrics::METRICS.render() }))
+        .nest("/api/v1", api::router())
         .with_state(app_state.clone())
         // Extension остаётся для совместимости, если используется где-то ещё
         .layer(Extension(app_state.clone()));
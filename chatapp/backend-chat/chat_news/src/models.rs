@@ -1,44 +1,8 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct WsInMessage {
-    pub chat_id: Uuid,
-    pub content: Option<String>,
-    pub media_url: Option<String>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Claims {
-    pub sub: String,
-    pub exp: i64,
-    pub iat: i64,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct RefreshToken {
-    pub id: Uuid,
-    pub user_id: Uuid,
-    pub token: String,
-    pub expires_at: DateTime<Utc>,
-    pub created_at: DateTime<Utc>,
-    pub revoked: bool,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct RefreshRequest {
-    
-}
-
-#[derive(Debug, Serialize)]
-pub struct TokenPairResponse {
-    pub access_token: String,
-    pub access_expires: i64,
-    pub refresh_token: String,
-    pub refresh_expires: i64,
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatEvent {
     pub chat_id: Uuid,
@@ -53,7 +17,341 @@ pub struct ChatEvent {
     pub deleted_at: Option<DateTime<Utc>>,
     pub is_deleted: Option<bool>,
     pub version: Option<usize>,
+    #[serde(default)]
+    pub reply_to: Option<Uuid>,
+    #[serde(default)]
+    pub thread_root_id: Option<Uuid>,
+}
+
+impl ChatEvent {
+    /// Событие нового сообщения с новым message_id и текущим временем
+    pub fn new_message(
+        chat_id: Uuid,
+        user_id: Uuid,
+        content: Option<String>,
+        media_urls: Option<Vec<String>>,
+        media_meta: Option<serde_json::Value>,
+    ) -> Self {
+        Self {
+            chat_id,
+            message_id: Uuid::new_v4(),
+            user_id,
+            content,
+            media_urls,
+            media_meta,
+            created_at: Utc::now(),
+            edited_at: None,
+            edited_by: None,
+            deleted_at: None,
+            is_deleted: Some(false),
+            version: Some(0),
+            reply_to: None,
+            thread_root_id: None,
+        }
+    }
+
+    /// Нет ни текста, ни вложений — такое сообщение не принимаем ни по REST, ни по WebSocket
+    pub fn is_empty(&self) -> bool {
+        let has_text = self.content.as_deref().is_some_and(|t| !t.trim().is_empty());
+        let has_media = self.media_urls.as_ref().is_some_and(|m| !m.is_empty());
+        !has_text && !has_media
+    }
+
+    /// Помечает сообщение как ответ в треде
+    pub fn in_thread(mut self, reply_to: Uuid, thread_root_id: Uuid) -> Self {
+        self.reply_to = Some(reply_to);
+        self.thread_root_id = Some(thread_root_id);
+        self
+    }
+}
+
+/// Сетевой статус пользователя
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresenceStatus {
+    Online,
+    Away,
+    Offline,
 }
 
-#[derive(Clone)]
-pub struct UserUuid(pub Uuid);
\ No newline at end of file
+/// Текущая версия схемы событий реального времени.
+/// Клиенты должны игнорировать события с неизвестным `kind`.
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// Событие чата: {"v":1,"event_id":...,"chat_id":...,"occurred_at":...,"kind":"message.created","data":{...}}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventEnvelope {
+    pub v: u32,
+    pub event_id: Uuid,
+    pub chat_id: Uuid,
+    pub occurred_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: ServerEvent,
+    /// Соединение-источник эфемерного события (не сериализуется, только для комнат)
+    #[serde(skip)]
+    pub origin: Option<Uuid>,
+}
+
+impl EventEnvelope {
+    pub fn new(chat_id: Uuid, event: ServerEvent) -> Self {
+        Self {
+            v: EVENT_SCHEMA_VERSION,
+            event_id: Uuid::new_v4(),
+            chat_id,
+            occurred_at: Utc::now(),
+            event,
+            origin: None,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        self.event.kind()
+    }
+}
+
+/// Виды событий реального времени
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "data")]
+pub enum ServerEvent {
+    #[serde(rename = "message.created")]
+    MessageCreated(ChatEvent),
+    #[serde(rename = "message.edited")]
+    MessageEdited {
+        message_id: Uuid,
+        content: Option<String>,
+        edited_by: Uuid,
+        edited_at: DateTime<Utc>,
+        version: i64,
+    },
+    #[serde(rename = "message.deleted")]
+    MessageDeleted {
+        message_id: Uuid,
+        deleted_by: Uuid,
+        deleted_at: DateTime<Utc>,
+        version: i64,
+    },
+    #[serde(rename = "message.restored")]
+    MessageRestored {
+        message_id: Uuid,
+        restored_by: Uuid,
+        version: i64,
+    },
+    #[serde(rename = "message.media_attached")]
+    MessageMediaAttached {
+        message_id: Uuid,
+        media_urls: Vec<String>,
+        media_meta: HashMap<String, String>,
+        version: i64,
+    },
+    #[serde(rename = "message.purged")]
+    MessagePurged {
+        message_id: Uuid,
+        purged_by: Uuid,
+    },
+    #[serde(rename = "reaction.added")]
+    ReactionAdded {
+        message_id: Uuid,
+        user_id: Uuid,
+        emoji: String,
+        count: i64,
+    },
+    #[serde(rename = "reaction.removed")]
+    ReactionRemoved {
+        message_id: Uuid,
+        user_id: Uuid,
+        emoji: String,
+        count: i64,
+    },
+    #[serde(rename = "thread.updated")]
+    ThreadUpdated {
+        thread_root_id: Uuid,
+        reply_count: i64,
+        last_reply_at: Option<DateTime<Utc>>,
+        last_reply_id: Option<Uuid>,
+    },
+    #[serde(rename = "typing.started")]
+    TypingStarted {
+        user_id: Uuid,
+    },
+    #[serde(rename = "typing.stopped")]
+    TypingStopped {
+        user_id: Uuid,
+    },
+    #[serde(rename = "presence.changed")]
+    PresenceChanged {
+        user_id: Uuid,
+        status: PresenceStatus,
+        last_seen: Option<DateTime<Utc>>,
+    },
+    #[serde(rename = "message.read")]
+    MessagesRead {
+        user_id: Uuid,
+        last_read_message_id: Uuid,
+        read_at: DateTime<Utc>,
+    },
+    #[serde(rename = "member.joined")]
+    MemberJoined {
+        user_id: Uuid,
+        added_by: Uuid,
+    },
+    #[serde(rename = "member.left")]
+    MemberLeft {
+        user_id: Uuid,
+        removed_by: Uuid,
+    },
+    #[serde(rename = "chat.updated")]
+    ChatUpdated {
+        title: Option<String>,
+        avatar_url: Option<String>,
+        updated_by: Uuid,
+    },
+}
+
+impl ServerEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ServerEvent::MessageCreated(_) => "message.created",
+            ServerEvent::MessageEdited { .. } => "message.edited",
+            ServerEvent::MessageDeleted { .. } => "message.deleted",
+            ServerEvent::MessageRestored { .. } => "message.restored",
+            ServerEvent::MessageMediaAttached { .. } => "message.media_attached",
+            ServerEvent::MessagePurged { .. } => "message.purged",
+            ServerEvent::ReactionAdded { .. } => "reaction.added",
+            ServerEvent::ReactionRemoved { .. } => "reaction.removed",
+            ServerEvent::ThreadUpdated { .. } => "thread.updated",
+            ServerEvent::TypingStarted { .. } => "typing.started",
+            ServerEvent::TypingStopped { .. } => "typing.stopped",
+            ServerEvent::PresenceChanged { .. } => "presence.changed",
+            ServerEvent::MessagesRead { .. } => "message.read",
+            ServerEvent::MemberJoined { .. } => "member.joined",
+            ServerEvent::MemberLeft { .. } => "member.left",
+            ServerEvent::ChatUpdated { .. } => "chat.updated",
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn message() -> ChatEvent {
+        ChatEvent::new_message(Uuid::new_v4(), Uuid::new_v4(), Some("hi".into()), None, None)
+    }
+
+    #[test]
+    fn message_without_text_or_media_is_empty() {
+        let chat_id = Uuid::new_v4();
+        let user_id = Uuid::new_v4();
+
+        assert!(ChatEvent::new_message(chat_id, user_id, None, None, None).is_empty());
+        assert!(ChatEvent::new_message(chat_id, user_id, Some("  \n".into()), Some(vec![]), None).is_empty());
+        assert!(!ChatEvent::new_message(chat_id, user_id, Some("hi".into()), None, None).is_empty());
+        assert!(!ChatEvent::new_message(chat_id, user_id, None, Some(vec!["https://cdn/x.png".into()]), None).is_empty());
+    }
+
+    #[test]
+    fn envelope_has_flat_kind_and_data() {
+        let event = message();
+        let envelope = EventEnvelope::new(event.chat_id, ServerEvent::MessageCreated(event.clone()));
+
+        let value = serde_json::to_value(&envelope).unwrap();
+        assert_eq!(value["v"], json!(EVENT_SCHEMA_VERSION));
+        assert_eq!(value["event_id"], json!(envelope.event_id));
+        assert_eq!(value["chat_id"], json!(event.chat_id));
+        assert_eq!(value["kind"], "message.created");
+        assert_eq!(value["data"]["message_id"], json!(event.message_id));
+        assert_eq!(value["data"]["content"], "hi");
+        assert!(value.get("event").is_none());
+    }
+
+    #[test]
+    fn origin_is_never_serialized() {
+        let mut envelope = EventEnvelope::new(Uuid::new_v4(), ServerEvent::TypingStarted { user_id: Uuid::new_v4() });
+        envelope.origin = Some(Uuid::new_v4());
+
+        let value = serde_json::to_value(&envelope).unwrap();
+        assert!(value.get("origin").is_none());
+
+        let back: EventEnvelope = serde_json::from_value(value).unwrap();
+        assert_eq!(back.origin, None);
+    }
+
+    #[test]
+    fn envelope_round_trip() {
+        let message_id = Uuid::new_v4();
+        let envelope = EventEnvelope::new(Uuid::new_v4(), ServerEvent::ReactionAdded {
+            message_id,
+            user_id: Uuid::new_v4(),
+            emoji: "👍".into(),
+            count: 2,
+        });
+
+        let back: EventEnvelope = serde_json::from_str(&serde_json::to_string(&envelope).unwrap()).unwrap();
+        assert_eq!(back.event_id, envelope.event_id);
+        assert_eq!(back.chat_id, envelope.chat_id);
+        assert_eq!(back.occurred_at, envelope.occurred_at);
+        match back.event {
+            ServerEvent::ReactionAdded { message_id: id, emoji, count, .. } => {
+                assert_eq!(id, message_id);
+                assert_eq!(emoji, "👍");
+                assert_eq!(count, 2);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn serialized_kind_matches_kind() {
+        let user_id = Uuid::new_v4();
+        let events = vec![
+            ServerEvent::MessageCreated(message()),
+            ServerEvent::MessagePurged { message_id: Uuid::new_v4(), purged_by: user_id },
+            ServerEvent::TypingStopped { user_id },
+            ServerEvent::PresenceChanged { user_id, status: PresenceStatus::Away, last_seen: None },
+            ServerEvent::MessagesRead { user_id, last_read_message_id: Uuid::new_v4(), read_at: Utc::now() },
+            ServerEvent::ChatUpdated { title: Some("t".into()), avatar_url: None, updated_by: user_id },
+        ];
+
+        for event in events {
+            let value = serde_json::to_value(EventEnvelope::new(Uuid::new_v4(), event.clone())).unwrap();
+            assert_eq!(value["kind"], event.kind());
+        }
+    }
+
+    #[test]
+    fn presence_status_is_lowercase() {
+        let value = serde_json::to_value(ServerEvent::PresenceChanged {
+            user_id: Uuid::new_v4(),
+            status: PresenceStatus::Online,
+            last_seen: None,
+        }).unwrap();
+
+        assert_eq!(value["data"]["status"], "online");
+    }
+
+    #[test]
+    fn unknown_kind_is_rejected() {
+        let value = json!({
+            "v": EVENT_SCHEMA_VERSION,
+            "event_id": Uuid::new_v4(),
+            "chat_id": Uuid::new_v4(),
+            "occurred_at": Utc::now(),
+            "kind": "message.teleported",
+            "data": {},
+        });
+
+        assert!(serde_json::from_value::<EventEnvelope>(value).is_err());
+    }
+
+    #[test]
+    fn envelope_without_kind_is_rejected() {
+        let value: Value = json!({
+            "v": EVENT_SCHEMA_VERSION,
+            "event_id": Uuid::new_v4(),
+            "chat_id": Uuid::new_v4(),
+            "occurred_at": Utc::now(),
+        });
+
+        assert!(serde_json::from_value::<EventEnvelope>(value).is_err());
+    }
+}
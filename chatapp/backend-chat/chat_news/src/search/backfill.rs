@@ -0,0 +1,97 @@
+// src/search/backfill.rs
+
+use std::sync::Arc;
+
+use futures_util::StreamExt;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use tracing::{error, info};
+
+use crate::db::messages::ScyllaDb;
+use crate::search::SearchIndex;
+
+/// Как часто сообщаем о ходе перестройки
+const PROGRESS_EVERY: u64 = 10_000;
+
+/// Догонка начинается чуть раньше отметки: `occurred_at` ставят разные поды, часы у них расходятся
+const CATCH_UP_OVERLAP: Duration = Duration::minutes(5);
+
+/// Сколько живёт журнал `message_changes` (default_time_to_live в 0008_message_changes.cql).
+/// Если индекс отстал сильнее, правки уже не восстановить — перестраиваем целиком
+const CHANGES_RETENTION: Duration = Duration::days(7);
+
+/// Индексирует все сообщения из Scylla. Индекс у каждого инстанса свой, а fan-out приносит
+/// только новые события — без этого новый под не находит сообщения, написанные до его запуска
+pub async fn reindex_from_scylla(scylla: &ScyllaDb, search: &SearchIndex) -> Result<u64> {
+    let mut messages = Box::pin(scylla.scan_messages().await?);
+    let mut indexed = 0;
+
+    while let Some(msg) = messages.next().await {
+        search.upsert(&msg?)?;
+        indexed += 1;
+
+        if indexed % PROGRESS_EVERY == 0 {
+            info!("search backfill: {} messages indexed", indexed);
+            tokio::task::yield_now().await;
+        }
+    }
+
+    Ok(indexed)
+}
+
+/// Переиндексирует сообщения, созданные или изменённые после `since`: то, что fan-out
+/// пропустил, пока инстанс не работал (после рестарта он читает топик с конца)
+pub async fn catch_up_from_scylla(scylla: &ScyllaDb, search: &SearchIndex, since: DateTime<Utc>) -> Result<u64> {
+    let message_ids = scylla.messages_since(since - CATCH_UP_OVERLAP).await?;
+    let mut indexed = 0;
+
+    for message_id in message_ids {
+        match scylla.get_message_by_id(message_id).await? {
+            Some(msg) => search.upsert(&msg)?,
+            None => search.remove(message_id)?,
+        }
+        indexed += 1;
+
+        if indexed % PROGRESS_EVERY == 0 {
+            info!("search catch-up: {} messages indexed", indexed);
+            tokio::task::yield_now().await;
+        }
+    }
+
+    Ok(indexed)
+}
+
+/// Приводит индекс в актуальное состояние в фоне: целиком перестраивает, если он пуст,
+/// без отметки, слишком отстал или перестройку попросили явно, иначе догоняет с отметки.
+/// Поиск работает и во время перестройки, просто находит пока не всё
+pub fn spawn_backfill(scylla: Arc<ScyllaDb>, search: Arc<SearchIndex>, force: bool) {
+    let started_at = Utc::now();
+    let since = search
+        .indexed_until()
+        .filter(|since| !force && !search.is_empty() && started_at - *since < CHANGES_RETENTION);
+
+    // До конца перестройки сохранённая отметка не двигается: упади инстанс посередине,
+    // следующий запуск начнёт с того же места
+    search.hold_watermark(since);
+
+    tokio::spawn(async move {
+        let result = match since {
+            Some(since) => {
+                info!("search catch-up started from {}", since);
+                catch_up_from_scylla(&scylla, &search, since).await
+            }
+            None => {
+                info!("search backfill started");
+                reindex_from_scylla(&scylla, &search).await
+            }
+        };
+
+        match result {
+            Ok(indexed) => {
+                search.release_watermark(started_at);
+                info!("search backfill finished: {} messages indexed", indexed);
+            }
+            Err(e) => error!("search backfill failed: {:?}", e),
+        }
+    });
+}
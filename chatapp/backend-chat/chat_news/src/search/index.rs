@@ -0,0 +1,353 @@
+// src/search/index.rs
+
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::time::Duration;
+
+use tantivy::{
+    Index, IndexReader, IndexWriter, ReloadPolicy, Term, doc,
+    collector::TopDocs,
+    directory::MmapDirectory,
+    query::{BooleanQuery, Occur, Query, QueryParser, RangeQuery, TermQuery},
+    schema::{Field, IndexRecordOption, Schema, FAST, INDEXED, STORED, STRING, TEXT},
+};
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use anyhow::{Result, Context};
+use tracing::{error, warn};
+
+use crate::db::messages::Message;
+
+/// Память под буфер индексатора
+const WRITER_HEAP_BYTES: usize = 50_000_000;
+
+/// Как часто сбрасываем накопленные изменения на диск
+const COMMIT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Фильтры поиска. `chat_ids` — чаты, доступные пользователю
+#[derive(Debug, Default)]
+pub struct SearchFilter {
+    pub chat_ids: Vec<Uuid>,
+    pub sender_id: Option<Uuid>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub has_media: Option<bool>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub message_id: Uuid,
+    pub chat_id: Uuid,
+    pub score: f32,
+}
+
+#[derive(Clone, Copy)]
+struct Fields {
+    message_id: Field,
+    chat_id: Field,
+    user_id: Field,
+    content: Field,
+    created_at: Field,
+    has_media: Field,
+}
+
+/// Нет отметки: индекс ни разу не догонялся до конца
+const NO_WATERMARK: i64 = 0;
+
+/// Отметка не удерживается
+const NOT_HELD: i64 = i64::MAX;
+
+/// Полнотекстовый индекс сообщений (tantivy на локальном диске)
+pub struct SearchIndex {
+    index: Index,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    fields: Fields,
+    dirty: AtomicBool,
+    /// До какого момента (мкс) в индекс попали все события. Хранится в payload коммита,
+    /// чтобы после рестарта догнать пропущенное из Scylla
+    indexed_until: AtomicI64,
+    /// Пока идёт перестройка, на диск пишется не больше этой отметки:
+    /// события fan-out новее, но более старые сообщения ещё не проиндексированы
+    held_at: AtomicI64,
+}
+
+fn build_schema() -> (Schema, Fields) {
+    let mut builder = Schema::builder();
+    let fields = Fields {
+        message_id: builder.add_text_field("message_id", STRING | STORED),
+        chat_id: builder.add_text_field("chat_id", STRING | STORED),
+        user_id: builder.add_text_field("user_id", STRING),
+        content: builder.add_text_field("content", TEXT),
+        created_at: builder.add_date_field("created_at", INDEXED | FAST),
+        has_media: builder.add_u64_field("has_media", INDEXED),
+    };
+    (builder.build(), fields)
+}
+
+fn to_tantivy_date(dt: DateTime<Utc>) -> tantivy::DateTime {
+    tantivy::DateTime::from_timestamp_micros(dt.timestamp_micros())
+}
+
+impl SearchIndex {
+    pub fn open(path: &str) -> Result<Arc<Self>> {
+        std::fs::create_dir_all(path).context("create search index dir")?;
+
+        let (schema, fields) = build_schema();
+        let dir = MmapDirectory::open(Path::new(path)).context("open search index dir")?;
+        let index = Index::open_or_create(dir, schema).context("open search index")?;
+
+        let indexed_until = index.load_metas().context("load search index metas")?
+            .payload
+            .and_then(|payload| payload.parse().ok())
+            .unwrap_or(NO_WATERMARK);
+
+        let writer = index.writer(WRITER_HEAP_BYTES).context("create index writer")?;
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommit)
+            .try_into()
+            .context("create index reader")?;
+
+        Ok(Arc::new(Self {
+            index,
+            reader,
+            writer: Mutex::new(writer),
+            fields,
+            dirty: AtomicBool::new(false),
+            indexed_until: AtomicI64::new(indexed_until),
+            held_at: AtomicI64::new(NOT_HELD),
+        }))
+    }
+
+    /// Фоновый коммит: изменения становятся видны поиску с задержкой до `COMMIT_INTERVAL`
+    pub fn spawn_committer(self: &Arc<Self>) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(COMMIT_INTERVAL);
+            loop {
+                ticker.tick().await;
+                if !this.dirty.swap(false, Ordering::AcqRel) {
+                    continue;
+                }
+
+                let index = this.clone();
+                match tokio::task::spawn_blocking(move || index.commit()).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => {
+                        error!("search index commit failed: {:?}", e);
+                        this.dirty.store(true, Ordering::Release);
+                    }
+                    Err(e) => error!("search index commit task failed: {:?}", e),
+                }
+            }
+        });
+    }
+
+    fn commit(&self) -> Result<()> {
+        let watermark = self.indexed_until.load(Ordering::Acquire).min(self.held_at.load(Ordering::Acquire));
+
+        let mut writer = self.writer.lock().map_err(|_| anyhow::anyhow!("index writer poisoned"))?;
+        let mut commit = writer.prepare_commit().context("prepare search index commit")?;
+        commit.set_payload(&watermark.to_string());
+        commit.commit().context("commit search index")?;
+        Ok(())
+    }
+
+    /// Момент, до которого индекс содержит все события (по данным последнего коммита и fan-out)
+    pub fn indexed_until(&self) -> Option<DateTime<Utc>> {
+        match self.indexed_until.load(Ordering::Acquire) {
+            NO_WATERMARK => None,
+            micros => DateTime::from_timestamp_micros(micros),
+        }
+    }
+
+    /// Отмечает, что события до `at` включительно уже в индексе
+    pub fn mark_indexed_until(&self, at: DateTime<Utc>) {
+        let previous = self.indexed_until.fetch_max(at.timestamp_micros(), Ordering::AcqRel);
+        if previous < at.timestamp_micros() {
+            self.dirty.store(true, Ordering::Release);
+        }
+    }
+
+    /// Не даёт сохранённой отметке уйти дальше `at` (`None` — сбросить её), пока идёт перестройка
+    pub fn hold_watermark(&self, at: Option<DateTime<Utc>>) {
+        let micros = at.map(|t| t.timestamp_micros()).unwrap_or(NO_WATERMARK);
+        self.held_at.store(micros, Ordering::Release);
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    /// Перестройка, начатая в `started_at`, закончилась: всё, что было до неё, уже в индексе
+    pub fn release_watermark(&self, started_at: DateTime<Utc>) {
+        self.held_at.store(NOT_HELD, Ordering::Release);
+        self.mark_indexed_until(started_at);
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    fn message_term(&self, message_id: Uuid) -> Term {
+        Term::from_field_text(self.fields.message_id, &message_id.to_string())
+    }
+
+    /// Индексирует сообщение, заменяя предыдущую версию. Удалённые сообщения убираются из индекса
+    pub fn upsert(&self, msg: &Message) -> Result<()> {
+        if msg.is_deleted {
+            return self.remove(msg.message_id);
+        }
+
+        let f = self.fields;
+        let has_media = msg.media_urls.as_ref().map(|m| !m.is_empty()).unwrap_or(false);
+        let writer = self.writer.lock().map_err(|_| anyhow::anyhow!("index writer poisoned"))?;
+
+        writer.delete_term(self.message_term(msg.message_id));
+        writer.add_document(doc!(
+            f.message_id => msg.message_id.to_string(),
+            f.chat_id => msg.chat_id.to_string(),
+            f.user_id => msg.user_id.to_string(),
+            f.content => msg.content.clone().unwrap_or_default(),
+            f.created_at => to_tantivy_date(msg.created_at),
+            f.has_media => u64::from(has_media),
+        )).context("index message")?;
+
+        self.dirty.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Индекс ещё ничего не содержит (новый инстанс или пустой каталог)
+    pub fn is_empty(&self) -> bool {
+        self.reader.searcher().num_docs() == 0
+    }
+
+    pub fn remove(&self, message_id: Uuid) -> Result<()> {
+        let writer = self.writer.lock().map_err(|_| anyhow::anyhow!("index writer poisoned"))?;
+        writer.delete_term(self.message_term(message_id));
+        self.dirty.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Ищет сообщения по тексту в пределах `filter.chat_ids`, по релевантности
+    pub fn search(&self, text: &str, filter: &SearchFilter, limit: usize, offset: usize) -> Result<Vec<SearchHit>> {
+        if filter.chat_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let f = self.fields;
+        let parser = QueryParser::for_index(&self.index, vec![f.content]);
+        // Пользовательский ввод не должен ломать поиск синтаксическими ошибками
+        let (text_query, errors) = parser.parse_query_lenient(text);
+        if !errors.is_empty() {
+            warn!("search query parsed with {} errors", errors.len());
+        }
+
+        let term_query = |field: Field, value: &str| -> Box<dyn Query> {
+            Box::new(TermQuery::new(Term::from_field_text(field, value), IndexRecordOption::Basic))
+        };
+
+        let chats: Vec<(Occur, Box<dyn Query>)> = filter.chat_ids
+            .iter()
+            .map(|id| (Occur::Should, term_query(f.chat_id, &id.to_string())))
+            .collect();
+
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![
+            (Occur::Must, text_query),
+            (Occur::Must, Box::new(BooleanQuery::new(chats))),
+        ];
+
+        if let Some(sender_id) = filter.sender_id {
+            clauses.push((Occur::Must, term_query(f.user_id, &sender_id.to_string())));
+        }
+
+        if filter.from.is_some() || filter.to.is_some() {
+            let lower = filter.from.map(|d| Bound::Included(to_tantivy_date(d))).unwrap_or(Bound::Unbounded);
+            let upper = filter.to.map(|d| Bound::Included(to_tantivy_date(d))).unwrap_or(Bound::Unbounded);
+            clauses.push((Occur::Must, Box::new(RangeQuery::new_date_bounds("created_at".to_string(), lower, upper))));
+        }
+
+        if let Some(has_media) = filter.has_media {
+            clauses.push((Occur::Must, Box::new(TermQuery::new(
+                Term::from_field_u64(f.has_media, u64::from(has_media)),
+                IndexRecordOption::Basic,
+            ))));
+        }
+
+        let query = BooleanQuery::new(clauses);
+        let searcher = self.reader.searcher();
+        let top_docs = searcher
+            .search(&query, &TopDocs::with_limit(limit).and_offset(offset))
+            .context("search messages")?;
+
+        let mut hits = Vec::with_capacity(top_docs.len());
+        for (score, address) in top_docs {
+            let doc = searcher.doc(address).context("load search doc")?;
+            let message_id = doc.get_first(f.message_id).and_then(|v| v.as_text()).and_then(|s| Uuid::parse_str(s).ok());
+            let chat_id = doc.get_first(f.chat_id).and_then(|v| v.as_text()).and_then(|s| Uuid::parse_str(s).ok());
+            if let (Some(message_id), Some(chat_id)) = (message_id, chat_id) {
+                hits.push(SearchHit { message_id, chat_id, score });
+            }
+        }
+
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_index_dir() -> String {
+        std::env::temp_dir().join(format!("search-index-{}", Uuid::new_v4())).to_string_lossy().into_owned()
+    }
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(secs, 0).unwrap()
+    }
+
+    #[test]
+    fn watermark_survives_reopen() {
+        let dir = temp_index_dir();
+
+        let index = SearchIndex::open(&dir).unwrap();
+        assert_eq!(index.indexed_until(), None);
+        index.mark_indexed_until(at(1_700_000_000));
+        index.mark_indexed_until(at(1_600_000_000));
+        index.commit().unwrap();
+        drop(index);
+
+        let index = SearchIndex::open(&dir).unwrap();
+        assert_eq!(index.indexed_until(), Some(at(1_700_000_000)));
+        drop(index);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn held_watermark_is_not_persisted_past_hold() {
+        let dir = temp_index_dir();
+
+        let index = SearchIndex::open(&dir).unwrap();
+        index.hold_watermark(None);
+        index.mark_indexed_until(at(1_700_000_000));
+        index.commit().unwrap();
+        drop(index);
+
+        let index = SearchIndex::open(&dir).unwrap();
+        assert_eq!(index.indexed_until(), None);
+        index.hold_watermark(Some(at(1_600_000_000)));
+        index.mark_indexed_until(at(1_700_000_000));
+        index.commit().unwrap();
+        drop(index);
+
+        let index = SearchIndex::open(&dir).unwrap();
+        assert_eq!(index.indexed_until(), Some(at(1_600_000_000)));
+        index.release_watermark(at(1_650_000_000));
+        index.commit().unwrap();
+        drop(index);
+
+        let index = SearchIndex::open(&dir).unwrap();
+        assert_eq!(index.indexed_until(), Some(at(1_650_000_000)));
+        drop(index);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
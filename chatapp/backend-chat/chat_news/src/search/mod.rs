@@ -0,0 +1,6 @@
+// src/search/mod.rs
+
+pub mod backfill;
+pub mod index;
+
+pub use index::{SearchIndex, SearchFilter};
@@ -0,0 +1,3 @@
+// src/user/mod.rs
+
+pub mod presence;
@@ -0,0 +1,258 @@
+// src/user/presence.rs
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use chrono::{DateTime, Utc};
+use futures_util::future::join_all;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::db::PresenceDb;
+use crate::db::presence::LivePresence;
+use crate::kafka::producer::KafkaProducer;
+use crate::models::{PresenceStatus, ServerEvent};
+use crate::websocket::manager::ConnectionManager;
+
+/// Через сколько минут без активности пользователь считается отошедшим
+const AWAY_AFTER_MINUTES: i64 = 5;
+
+/// Как часто проверяем, не пора ли перевести пользователей в `away`, и продлеваем их статус в Scylla
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Сколько живёт статус инстанса в Scylla без продления: переживает пару пропущенных
+/// heartbeat, а пользователи упавшего инстанса через это время уходят в `offline`
+const LIVE_TTL_SECS: i32 = 90;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Presence {
+    pub user_id: Uuid,
+    pub status: PresenceStatus,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+/// Живые соединения пользователя на этом инстансе
+struct Entry {
+    connections: usize,
+    last_active: DateTime<Utc>,
+    status: PresenceStatus,
+}
+
+/// Отслеживает присутствие по WebSocket-подключениям и активности клиентов.
+/// Соединения пользователя могут быть на разных инстансах: каждый пишет свой статус
+/// в Scylla, а итоговый статус собирается оттуда и рассылается через Kafka во все реплики
+pub struct PresenceTracker {
+    entries: RwLock<HashMap<Uuid, Entry>>,
+    manager: Arc<ConnectionManager>,
+    store: Arc<PresenceDb>,
+    producer: Arc<KafkaProducer>,
+    instance_id: String,
+}
+
+impl PresenceTracker {
+    pub fn new(
+        manager: Arc<ConnectionManager>,
+        store: Arc<PresenceDb>,
+        producer: Arc<KafkaProducer>,
+        instance_id: String,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            entries: RwLock::new(HashMap::new()),
+            manager,
+            store,
+            producer,
+            instance_id,
+        })
+    }
+
+    /// Фоновый перевод неактивных пользователей в `away` и продление статусов в Scylla
+    pub fn spawn_sweeper(self: &Arc<Self>) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(SWEEP_INTERVAL);
+            loop {
+                ticker.tick().await;
+                this.sweep().await;
+            }
+        });
+    }
+
+    async fn sweep(&self) {
+        let idle_since = Utc::now() - chrono::Duration::minutes(AWAY_AFTER_MINUTES);
+
+        let (live, went_away): (Vec<(Uuid, LivePresence)>, Vec<Uuid>) = {
+            let mut entries = self.entries.write().await;
+            let mut went_away = Vec::new();
+            let live = entries
+                .iter_mut()
+                .map(|(user_id, e)| {
+                    if e.status == PresenceStatus::Online && e.last_active < idle_since {
+                        e.status = PresenceStatus::Away;
+                        went_away.push(*user_id);
+                    }
+                    (*user_id, LivePresence { status: e.status, last_active: e.last_active })
+                })
+                .collect();
+            (live, went_away)
+        };
+
+        // Heartbeat: без продления статус истечёт, как будто инстанс упал
+        join_all(live.into_iter().map(|(user_id, presence)| self.store_live(user_id, presence))).await;
+
+        for user_id in went_away {
+            self.notify(user_id).await;
+        }
+    }
+
+    async fn store_live(&self, user_id: Uuid, presence: LivePresence) {
+        if let Err(e) = self.store.set_live(user_id, &self.instance_id, presence, LIVE_TTL_SECS).await {
+            warn!("Failed to store presence for {}: {:?}", user_id, e);
+        }
+    }
+
+    /// Новое соединение пользователя
+    pub async fn connect(&self, user_id: Uuid) {
+        let now = Utc::now();
+        let changed = {
+            let mut entries = self.entries.write().await;
+            let entry = entries.entry(user_id).or_insert(Entry {
+                connections: 0,
+                last_active: now,
+                status: PresenceStatus::Offline,
+            });
+            entry.connections += 1;
+            entry.last_active = now;
+            std::mem::replace(&mut entry.status, PresenceStatus::Online) != PresenceStatus::Online
+        };
+
+        if changed {
+            self.store_live(user_id, LivePresence { status: PresenceStatus::Online, last_active: now }).await;
+            self.notify(user_id).await;
+        }
+    }
+
+    /// Любой кадр от клиента (команда, ping/pong) — признак активности
+    pub async fn touch(&self, user_id: Uuid) {
+        let now = Utc::now();
+        let changed = {
+            let mut entries = self.entries.write().await;
+            match entries.get_mut(&user_id) {
+                Some(entry) => {
+                    entry.last_active = now;
+                    std::mem::replace(&mut entry.status, PresenceStatus::Online) == PresenceStatus::Away
+                }
+                None => false,
+            }
+        };
+
+        if changed {
+            self.store_live(user_id, LivePresence { status: PresenceStatus::Online, last_active: now }).await;
+            self.notify(user_id).await;
+        }
+    }
+
+    /// Соединение закрыто. Последнее соединение на инстансе снимает его статус;
+    /// в `offline` пользователь уходит, когда соединений не осталось нигде
+    pub async fn disconnect(&self, user_id: Uuid) {
+        let now = Utc::now();
+        let went_offline = {
+            let mut entries = self.entries.write().await;
+            match entries.get_mut(&user_id) {
+                Some(entry) if entry.connections > 1 => {
+                    entry.connections -= 1;
+                    false
+                }
+                Some(_) => {
+                    entries.remove(&user_id);
+                    true
+                }
+                None => false,
+            }
+        };
+
+        if went_offline {
+            if let Err(e) = self.store.set_last_seen(user_id, now).await {
+                warn!("Failed to store last seen for {}: {:?}", user_id, e);
+            }
+            if let Err(e) = self.store.clear_live(user_id, &self.instance_id).await {
+                warn!("Failed to clear presence for {}: {:?}", user_id, e);
+            }
+            self.notify(user_id).await;
+        }
+    }
+
+    /// Текущий статус пользователя по всем инстансам; для офлайн-пользователей — время последнего визита
+    pub async fn get(&self, user_id: Uuid) -> anyhow::Result<Presence> {
+        let live = self.store.get_live(user_id).await?;
+        let status = combine(&live);
+
+        let last_seen = match status {
+            PresenceStatus::Offline => self.store.get_last_seen(user_id).await?,
+            _ => live.iter().map(|l| l.last_active).max(),
+        };
+
+        Ok(Presence { user_id, status, last_seen })
+    }
+
+    pub async fn get_many(&self, user_ids: &[Uuid]) -> anyhow::Result<Vec<Presence>> {
+        join_all(user_ids.iter().map(|id| self.get(*id))).await.into_iter().collect()
+    }
+
+    /// Рассылает итоговый статус пользователя во все его чаты через Kafka, чтобы событие
+    /// дошло до участников на всех инстансах. Чаты берём из соединений пользователя на этом инстансе
+    async fn notify(&self, user_id: Uuid) {
+        let presence = match self.get(user_id).await {
+            Ok(presence) => presence,
+            Err(e) => {
+                warn!("Failed to load presence for {}: {:?}", user_id, e);
+                return;
+            }
+        };
+
+        let chat_ids = self.manager.get_user_chats(user_id).await;
+        join_all(chat_ids.into_iter().map(|chat_id| {
+            self.producer.publish_or_warn(chat_id, ServerEvent::PresenceChanged {
+                user_id,
+                status: presence.status,
+                last_seen: presence.last_seen,
+            })
+        })).await;
+    }
+}
+
+/// Итоговый статус по инстансам: `online`, если пользователь активен хоть где-то
+fn combine(live: &[LivePresence]) -> PresenceStatus {
+    if live.iter().any(|l| l.status == PresenceStatus::Online) {
+        PresenceStatus::Online
+    } else if live.is_empty() {
+        PresenceStatus::Offline
+    } else {
+        PresenceStatus::Away
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn live(status: PresenceStatus) -> LivePresence {
+        LivePresence { status, last_active: Utc::now() }
+    }
+
+    #[test]
+    fn online_anywhere_wins() {
+        assert_eq!(combine(&[live(PresenceStatus::Away), live(PresenceStatus::Online)]), PresenceStatus::Online);
+    }
+
+    #[test]
+    fn away_everywhere_is_away() {
+        assert_eq!(combine(&[live(PresenceStatus::Away), live(PresenceStatus::Away)]), PresenceStatus::Away);
+    }
+
+    #[test]
+    fn no_instances_is_offline() {
+        assert_eq!(combine(&[]), PresenceStatus::Offline);
+    }
+}
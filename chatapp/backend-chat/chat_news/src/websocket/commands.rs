@@ -0,0 +1,326 @@
+// src/websocket/commands.rs
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{AppState, models::{ChatEvent, EventEnvelope, ServerEvent}, db::messages::{DeleteError, Message}, db::reactions::{is_valid_emoji, ReactionError}};
+use crate::websocket::resync::{ChatCursor, ResyncReason};
+
+/// Корреляционный id команды: {"type": "...", "id": "...", "payload": {...}}
+#[derive(Debug, Deserialize)]
+pub struct CommandEnvelope {
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+/// Входящие команды клиента
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum ClientCommand {
+    #[serde(rename = "message:send")]
+    SendMessage {
+        chat_id: Uuid,
+        #[serde(alias = "content")]
+        text: Option<String>,
+        #[serde(default)]
+        media_urls: Option<Vec<String>>,
+        #[serde(default)]
+        reply_to: Option<Uuid>,
+    },
+    #[serde(rename = "message:edit")]
+    EditMessage {
+        message_id: Uuid,
+        #[serde(alias = "content")]
+        text: Option<String>,
+    },
+    #[serde(rename = "message:delete")]
+    DeleteMessage {
+        message_id: Uuid,
+    },
+    #[serde(rename = "reaction:add")]
+    AddReaction {
+        message_id: Uuid,
+        emoji: String,
+    },
+    #[serde(rename = "reaction:remove")]
+    RemoveReaction {
+        message_id: Uuid,
+        emoji: String,
+    },
+    #[serde(rename = "typing:start")]
+    TypingStart {
+        chat_id: Uuid,
+    },
+    #[serde(rename = "typing:stop")]
+    TypingStop {
+        chat_id: Uuid,
+    },
+    #[serde(rename = "chat:read")]
+    MarkRead {
+        chat_id: Uuid,
+        message_id: Uuid,
+    },
+    #[serde(rename = "subscribe")]
+    Subscribe {
+        chat_id: Uuid,
+    },
+    #[serde(rename = "unsubscribe")]
+    Unsubscribe {
+        chat_id: Uuid,
+    },
+    /// Аутентификация первым кадром и повторная — после обновления access-токена
+    #[serde(rename = "auth")]
+    Auth {
+        token: String,
+    },
+    /// После переподключения: дослать пропущенное начиная с курсоров
+    #[serde(rename = "chat:resync")]
+    Resync {
+        cursors: Vec<ChatCursor>,
+    },
+}
+
+/// Исходящие кадры сервера
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerFrame {
+    Event {
+        payload: Box<EventEnvelope>,
+    },
+    Ack {
+        id: Option<String>,
+        result: serde_json::Value,
+    },
+    Error {
+        id: Option<String>,
+        error: String,
+    },
+    /// Соединение начало получать события чата
+    Subscribed {
+        chat_id: Uuid,
+    },
+    /// Соединение больше не получает события чата
+    Unsubscribed {
+        chat_id: Uuid,
+        reason: UnsubscribeReason,
+    },
+    /// Пропущенное за время разрыва: новые сообщения от старых к новым, изменённые старые
+    /// (в актуальном состоянии) и удалённые безвозвратно. Дальше идут живые события
+    Replay {
+        chat_id: Uuid,
+        messages: Vec<Message>,
+        updated: Vec<Message>,
+        purged: Vec<Uuid>,
+    },
+    /// События чата потеряны — клиенту нужно перечитать чат через REST
+    ResyncRequired {
+        chat_id: Uuid,
+        reason: ResyncReason,
+    },
+    /// Последний кадр перед закрытием соединения сервером
+    SessionEnded {
+        reason: SessionEndReason,
+    },
+}
+
+/// Почему сервер закрывает аутентифицированное соединение
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionEndReason {
+    /// Access-токен истёк, а новый так и не прислали кадром `auth`
+    TokenExpired,
+    /// Сессия отозвана (выход, смена пароля) или истекла
+    SessionRevoked,
+}
+
+/// Почему соединение отписано от чата
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnsubscribeReason {
+    /// По команде клиента
+    Requested,
+    /// Пользователь больше не участник чата
+    Removed,
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+    BadRequest(&'static str),
+    NotFound,
+    Forbidden,
+    Conflict,
+    Unauthorized(String),
+    Unsupported,
+    Internal(anyhow::Error),
+}
+
+impl CommandError {
+    pub fn message(&self) -> String {
+        match self {
+            CommandError::BadRequest(msg) => msg.to_string(),
+            CommandError::NotFound => "not found".into(),
+            CommandError::Forbidden => "permission denied".into(),
+            CommandError::Conflict => "message was modified concurrently".into(),
+            CommandError::Unauthorized(msg) => msg.clone(),
+            CommandError::Unsupported => "unsupported command".into(),
+            CommandError::Internal(_) => "internal error".into(),
+        }
+    }
+}
+
+impl From<DeleteError> for CommandError {
+    fn from(e: DeleteError) -> Self {
+        match e {
+            DeleteError::MessageNotFound => Self::NotFound,
+            DeleteError::PermissionDenied => Self::Forbidden,
+            DeleteError::Conflict => Self::Conflict,
+            DeleteError::InternalError(e) => Self::Internal(e),
+        }
+    }
+}
+
+impl From<ReactionError> for CommandError {
+    fn from(e: ReactionError) -> Self {
+        match e {
+            ReactionError::TooManyReactions => Self::BadRequest("too many reactions"),
+            ReactionError::InternalError(e) => Self::Internal(e),
+        }
+    }
+}
+
+impl From<anyhow::Error> for CommandError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Internal(e)
+    }
+}
+
+/// Проверяет, что пользователь состоит в чате
+pub async fn ensure_member(state: &AppState, chat_id: Uuid, user_id: Uuid) -> Result<(), CommandError> {
+    match state.scylla.is_user_in_chat(chat_id, user_id).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(CommandError::Forbidden),
+        Err(e) => Err(CommandError::Internal(anyhow::anyhow!("{:?}", e))),
+    }
+}
+
+/// Выполняет команду, работающую с сообщениями, и возвращает результат для ack
+pub async fn execute(state: &AppState, user_id: Uuid, cmd: ClientCommand) -> Result<serde_json::Value, CommandError> {
+    match cmd {
+        ClientCommand::SendMessage { chat_id, text, media_urls, reply_to } => {
+            let mut event = ChatEvent::new_message(chat_id, user_id, text, media_urls, None);
+            if event.is_empty() {
+                return Err(CommandError::BadRequest("empty message"));
+            }
+
+            ensure_member(state, chat_id, user_id).await?;
+
+            if let Some(reply_to) = reply_to {
+                let thread_root_id = state.scylla.resolve_thread_root(chat_id, reply_to).await?
+                    .ok_or(CommandError::NotFound)?;
+                event = event.in_thread(reply_to, thread_root_id);
+            }
+            state.kafka_producer.publish(chat_id, ServerEvent::MessageCreated(event.clone())).await?;
+
+            Ok(json!({ "message_id": event.message_id, "created_at": event.created_at, "thread_root_id": event.thread_root_id }))
+        }
+        ClientCommand::EditMessage { message_id, text } => {
+            let msg = state.scylla.get_message_by_id(message_id).await?
+                .ok_or(CommandError::NotFound)?;
+            ensure_member(state, msg.chat_id, user_id).await?;
+
+            if msg.user_id != user_id {
+                return Err(CommandError::Forbidden);
+            }
+
+            let version = match state.scylla.edit_message_with_history(message_id, text, user_id).await? {
+                Some(updated) => {
+                    state.kafka_producer.publish_or_warn(updated.chat_id, updated.edited_event()).await;
+                    updated.version
+                }
+                None => msg.version,
+            };
+            Ok(json!({ "message_id": message_id, "version": version }))
+        }
+        ClientCommand::DeleteMessage { message_id } => {
+            let msg = state.scylla.get_message_by_id(message_id).await?
+                .ok_or(CommandError::NotFound)?;
+            ensure_member(state, msg.chat_id, user_id).await?;
+
+            let is_admin = matches!(
+                state.chats.get_member(msg.chat_id, user_id).await?,
+                Some(member) if member.role.can_manage()
+            );
+
+            match state.scylla.delete_message(message_id, user_id, is_admin).await {
+                Ok(Some(updated)) => {
+                    state.kafka_producer.publish_or_warn(updated.chat_id, updated.deleted_event(user_id)).await;
+                    Ok(json!({ "message_id": message_id, "version": updated.version }))
+                }
+                Ok(None) => Ok(json!({ "message_id": message_id, "version": msg.version })),
+                Err(e) => Err(e.into()),
+            }
+        }
+        ClientCommand::AddReaction { message_id, emoji } => {
+            if !is_valid_emoji(&emoji) {
+                return Err(CommandError::BadRequest("invalid emoji"));
+            }
+
+            let msg = state.scylla.get_message_by_id(message_id).await?
+                .ok_or(CommandError::NotFound)?;
+            ensure_member(state, msg.chat_id, user_id).await?;
+
+            if msg.is_deleted {
+                return Err(CommandError::NotFound);
+            }
+
+            if let Some(count) = state.scylla.add_reaction(&msg, user_id, &emoji).await? {
+                state.kafka_producer.publish_or_warn(msg.chat_id, ServerEvent::ReactionAdded {
+                    message_id,
+                    user_id,
+                    emoji: emoji.clone(),
+                    count,
+                }).await;
+            }
+            Ok(json!({ "message_id": message_id, "emoji": emoji }))
+        }
+        ClientCommand::RemoveReaction { message_id, emoji } => {
+            let msg = state.scylla.get_message_by_id(message_id).await?
+                .ok_or(CommandError::NotFound)?;
+            ensure_member(state, msg.chat_id, user_id).await?;
+
+            if let Some(count) = state.scylla.remove_reaction(&msg, user_id, &emoji).await? {
+                state.kafka_producer.publish_or_warn(msg.chat_id, ServerEvent::ReactionRemoved {
+                    message_id,
+                    user_id,
+                    emoji: emoji.clone(),
+                    count,
+                }).await;
+            }
+            Ok(json!({ "message_id": message_id, "emoji": emoji }))
+        }
+        ClientCommand::MarkRead { chat_id, message_id } => {
+            ensure_member(state, chat_id, user_id).await?;
+
+            let msg = state.scylla.get_message_by_id(message_id).await?
+                .filter(|m| m.chat_id == chat_id)
+                .ok_or(CommandError::NotFound)?;
+
+            if let Some(marker) = state.read_markers.mark_read(chat_id, user_id, message_id, msg.created_at).await? {
+                state.kafka_producer.publish_or_warn(chat_id, ServerEvent::MessagesRead {
+                    user_id,
+                    last_read_message_id: marker.last_read_message_id,
+                    read_at: marker.updated_at,
+                }).await;
+            }
+            Ok(json!({ "chat_id": chat_id, "message_id": message_id }))
+        }
+        // Подписки, индикаторы набора и аутентификация обрабатываются в самом соединении
+        ClientCommand::Subscribe { .. }
+        | ClientCommand::Unsubscribe { .. }
+        | ClientCommand::Resync { .. }
+        | ClientCommand::Auth { .. }
+        | ClientCommand::TypingStart { .. }
+        | ClientCommand::TypingStop { .. } => Err(CommandError::Unsupported),
+    }
+}
@@ -1,20 +1,46 @@
 // src/websocket/gateway.rs
 
-use axum::response::Response;
+use axum::response::{IntoResponse, Response};
 use axum::extract::ws::WebSocketUpgrade;
-use uuid::Uuid;
+use axum::http::HeaderMap;
+use headers::{authorization::Bearer, Authorization, HeaderMapExt};
+use serde::Deserialize;
 use std::sync::Arc;
 use crate::AppState;
+use crate::auth::{self, Authenticated};
 
 use crate::websocket::handler::handle_websocket; 
 
-/// Обработчик HTTP-апгрейда до WebSocket
+/// Параметры подключения: `GET /ws?ticket=...`
+#[derive(Debug, Default, Deserialize)]
+pub struct WsParams {
+    pub ticket: Option<String>,
+}
+
+/// Обработчик HTTP-апгрейда до WebSocket.
+/// Аутентификация: билет из `POST /api/v1/ws/ticket`, заголовок `Authorization`
+/// или (если нет ни того ни другого) первый кадр `auth` уже после апгрейда
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
-    user_id: Uuid,
+    params: WsParams,
+    headers: &HeaderMap,
     state: Arc<AppState>,
 ) -> Response {
+    let auth: Option<Authenticated> = if let Some(ticket) = params.ticket.as_deref() {
+        match auth::authenticate_ws_ticket(&state, ticket).await {
+            Ok(auth) => Some(auth),
+            Err(e) => return e.into_response(),
+        }
+    } else if let Some(bearer) = headers.typed_get::<Authorization<Bearer>>() {
+        match auth::authenticate(&state, bearer.token()).await {
+            Ok(auth) => Some(auth),
+            Err(e) => return e.into_response(),
+        }
+    } else {
+        None
+    };
+
     ws.on_upgrade(move |socket| {
-        handle_websocket(socket, user_id, state)
+        handle_websocket(socket, auth, state)
     })
 }
@@ -1,105 +1,570 @@
 // src/websocket/handler.rs
 
-use axum::extract::ws::{WebSocket, Message as WsMessage};
+use axum::extract::ws::{close_code, CloseFrame, WebSocket, Message as WsMessage};
 use futures_util::{SinkExt, StreamExt};
-use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
-use tracing::{debug, error, info};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::time::Instant;
 
-use crate::{AppState, models::ChatEvent};
+use crate::AppState;
+use crate::auth::{self, Authenticated};
+use crate::db::ScyllaDb;
+use crate::models::{EventEnvelope, ServerEvent};
+use crate::websocket::commands::{
+    self, ClientCommand, CommandEnvelope, CommandError, ServerFrame, SessionEndReason, UnsubscribeReason,
+};
+use crate::websocket::manager::{ConnectionManager, RoomControl};
+use crate::websocket::metrics::{METRICS, WsMetrics};
+use crate::websocket::outbound::{Outbound, Push};
+use crate::websocket::resync::{self, ChatCursor, ResyncReason};
+use crate::websocket::typing::TypingState;
 
-#[derive(Deserialize)]
-struct WsCommand {
-    r#type: String,
+/// Сколько ждём кадр `auth`, если соединение открыто без билета и заголовка
+const AUTH_FRAME_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Как часто проверяем, не отозвана ли сессия и не истёк ли access-токен
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Сколько даём отправке дописать последние кадры перед закрытием
+const CLOSE_FLUSH_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Сколько ждём, пока задача комнаты дошлёт `member.left` удалённому пользователю
+const LEAVE_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Подписки соединения: на каждый чат — задача, пересылающая события комнаты в сокет
+struct Subscriptions {
+    conn_id: Uuid,
+    user_id: Uuid,
+    manager: Arc<ConnectionManager>,
+    scylla: Arc<ScyllaDb>,
+    tx: Outbound,
+    tasks: HashMap<Uuid, JoinHandle<()>>,
+}
+
+/// Пересылает события комнаты в соединение. С курсором сначала досылает пропущенное из Scylla:
+/// живые события тем временем копятся в `room_rx` и уходят следом
+async fn forward_room(
+    mut room_rx: broadcast::Receiver<EventEnvelope>,
+    tx: Outbound,
+    scylla: Arc<ScyllaDb>,
     chat_id: Uuid,
+    conn_id: Uuid,
+    user_id: Uuid,
+    cursor: Option<ChatCursor>,
+) {
+    let mut replayed = HashMap::new();
+    // Часть событий не дошла до клиента, а `resync_required` ещё не поставлен в очередь
+    let mut gap = false;
+
+    if let Some(cursor) = cursor {
+        let frame = match resync::missed_messages(&scylla, cursor).await {
+            Ok(Ok(missed)) => {
+                replayed = missed.versions();
+                ServerFrame::Replay { chat_id, messages: missed.messages, updated: missed.updated, purged: missed.purged }
+            }
+            Ok(Err(reason)) => ServerFrame::ResyncRequired { chat_id, reason },
+            Err(e) => {
+                warn!("Failed to load missed messages of chat {} for {}: {:?}", chat_id, user_id, e);
+                ServerFrame::ResyncRequired { chat_id, reason: ResyncReason::Failed }
+            }
+        };
+        match tx.push(frame) {
+            Push::Queued => {}
+            Push::Dropped => gap = true,
+            Push::Closed => return,
+        }
+    }
+
+    loop {
+        let event = match room_rx.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                // Молча терять события нельзя: клиент должен перечитать чат
+                warn!("Connection {} lagged behind chat {}, {} events skipped", conn_id, chat_id, skipped);
+                match tx.push(ServerFrame::ResyncRequired { chat_id, reason: ResyncReason::Lagged }) {
+                    Push::Queued => gap = false,
+                    Push::Dropped => gap = true,
+                    Push::Closed => break,
+                }
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+
+        if event.origin == Some(conn_id) {
+            continue;
+        }
+        // Уже досланное из Scylla не дублируем
+        if !replayed.is_empty() && resync::already_replayed(&event.event, &replayed) {
+            continue;
+        }
+        // Своё удаление из чата — последнее событие комнаты для этого соединения
+        let removed = matches!(event.event, ServerEvent::MemberLeft { user_id: left, .. } if left == user_id);
+
+        // Клиент должен узнать о пропуске раньше, чем получит следующие события
+        if gap {
+            match tx.push(ServerFrame::ResyncRequired { chat_id, reason: ResyncReason::Lagged }) {
+                Push::Queued => gap = false,
+                Push::Dropped => continue,
+                Push::Closed => break,
+            }
+        }
+
+        match tx.push(ServerFrame::Event { payload: Box::new(event) }) {
+            Push::Queued => {}
+            Push::Dropped => gap = true,
+            Push::Closed => break,
+        }
+        if removed {
+            break;
+        }
+    }
+}
+
+impl Subscriptions {
+    fn new(
+        conn_id: Uuid,
+        user_id: Uuid,
+        manager: Arc<ConnectionManager>,
+        scylla: Arc<ScyllaDb>,
+        tx: Outbound,
+    ) -> Self {
+        Self { conn_id, user_id, manager, scylla, tx, tasks: HashMap::new() }
+    }
+
+    fn spawn(&mut self, chat_id: Uuid, room_rx: broadcast::Receiver<EventEnvelope>, cursor: Option<ChatCursor>) {
+        let task = tokio::spawn(forward_room(
+            room_rx,
+            self.tx.clone(),
+            self.scylla.clone(),
+            chat_id,
+            self.conn_id,
+            self.user_id,
+            cursor,
+        ));
+        self.tasks.insert(chat_id, task);
+    }
+
+    /// Подписывает соединение на чат. `false`, если подписка уже была
+    async fn join(&mut self, chat_id: Uuid) -> bool {
+        if self.tasks.contains_key(&chat_id) {
+            return false;
+        }
+
+        self.manager.subscribe_connection_to_chat(self.conn_id, chat_id).await;
+        let room_rx = self.manager.subscribe_to_chat(chat_id).await;
+        self.spawn(chat_id, room_rx, None);
+        true
+    }
+
+    /// Переподписка с досылкой пропущенного. Старая задача останавливается до создания нового
+    /// приёмника, иначе события между ними ушли бы клиенту дважды. Пропущенное в этом окне
+    /// досылается из Scylla: новый приёмник создаётся раньше, чем читается досылка.
+    /// `true`, если подписка на чат появилась впервые
+    async fn resync(&mut self, cursor: ChatCursor) -> bool {
+        let chat_id = cursor.chat_id;
+        let joined = match self.tasks.remove(&chat_id) {
+            Some(old) => {
+                // Дожидаемся отмены: задача может как раз пересылать уже полученные события
+                old.abort();
+                let _ = old.await;
+                false
+            }
+            None => {
+                self.manager.subscribe_connection_to_chat(self.conn_id, chat_id).await;
+                true
+            }
+        };
+
+        let room_rx = self.manager.subscribe_to_chat(chat_id).await;
+        self.spawn(chat_id, room_rx, Some(cursor));
+        joined
+    }
+
+    /// Останавливает доставку событий чата. `false`, если подписки не было
+    async fn leave(&mut self, chat_id: Uuid) -> bool {
+        let Some(task) = self.tasks.remove(&chat_id) else {
+            return false;
+        };
+
+        task.abort();
+        self.manager.unsubscribe_connection_from_chat(self.conn_id, chat_id).await;
+        true
+    }
+
+    /// Отписка после удаления из чата: сначала даём задаче доставить `member.left`
+    async fn drain(&mut self, chat_id: Uuid) -> bool {
+        let Some(mut task) = self.tasks.remove(&chat_id) else {
+            return false;
+        };
+
+        if tokio::time::timeout(LEAVE_DRAIN_TIMEOUT, &mut task).await.is_err() {
+            task.abort();
+        }
+        self.manager.unsubscribe_connection_from_chat(self.conn_id, chat_id).await;
+        true
+    }
+
+    fn abort_all(&mut self) {
+        for (_, task) in self.tasks.drain() {
+            task.abort();
+        }
+    }
 }
 
-#[derive(Serialize)]
-struct WsMessageOut {
-    r#type: String,
-    payload: ChatEvent,
+fn auth_result(auth: &Authenticated) -> serde_json::Value {
+    serde_json::json!({ "user_id": auth.user.id, "expires_at": auth.expires_at })
+}
+
+/// Ждёт первый кадр `auth` и отвечает на него. `None` — клиент не аутентифицировался
+async fn await_auth_frame(ws: &mut WebSocket, state: &AppState) -> Option<Authenticated> {
+    let text = match tokio::time::timeout(AUTH_FRAME_TIMEOUT, ws.recv()).await {
+        Ok(Some(Ok(WsMessage::Text(text)))) => text,
+        Ok(_) => return None,
+        Err(_) => {
+            debug!("No auth frame within {:?}", AUTH_FRAME_TIMEOUT);
+            return None;
+        }
+    };
+
+    let id = serde_json::from_str::<CommandEnvelope>(&text).ok().and_then(|e| e.id);
+    let result = match serde_json::from_str::<ClientCommand>(&text) {
+        Ok(ClientCommand::Auth { token }) => auth::authenticate_any(state, &token).await.map_err(|e| e.error),
+        _ => Err("auth required".to_string()),
+    };
+
+    let frame = match &result {
+        Ok(auth) => ServerFrame::Ack { id, result: auth_result(auth) },
+        Err(error) => ServerFrame::Error { id, error: error.clone() },
+    };
+    if let Ok(payload) = serde_json::to_string(&frame) {
+        let _ = ws.send(WsMessage::Text(payload)).await;
+    }
+
+    result.ok()
 }
 
 pub async fn handle_websocket(
-    ws: WebSocket,
-    user_id: Uuid,
+    mut ws: WebSocket,
+    auth: Option<Authenticated>,
     state: Arc<AppState>,
 ) {
+    let mut session = match auth {
+        Some(auth) => auth,
+        None => match await_auth_frame(&mut ws, &state).await {
+            Some(auth) => auth,
+            None => {
+                let _ = ws.send(WsMessage::Close(None)).await;
+                return;
+            }
+        },
+    };
+    let user_id = session.user.id;
+
     // Разделяем WebSocket на отправку и приём
     let (mut ws_sender, mut ws_receiver) = ws.split();
 
+    // Идентификатор соединения: эфемерные события не возвращаются отправителю
+    let conn_id = Uuid::new_v4();
+    let mut typing = TypingState::new(conn_id, user_id, state.ws_manager.clone());
+
     // Загружаем чаты пользователя
     let user_chats = match state.scylla.get_user_chat_ids(user_id).await {
         Ok(chats) => chats,
         Err(e) => {
             error!("Failed to load user chats for {}: {:?}", user_id, e);
+            // Клиент должен понять, что это сбой сервера, а не закрытие сессии, и переподключиться
+            let _ = ws_sender.send(WsMessage::Close(Some(CloseFrame {
+                code: close_code::ERROR,
+                reason: "failed to load chats".into(),
+            }))).await;
             return;
         }
     };
 
-    // Подписываем пользователя на каждый чат
+    // Очередь исходящих кадров: события чатов, события пользователю и ответы на команды
+    let (outbound, mut event_rx, overflow) =
+        Outbound::channel(state.config.ws_outbound_queue, state.config.ws_slow_consumer_policy);
+    // Изменения состава чатов, пока соединение открыто
+    let (control_tx, mut control_rx) = mpsc::unbounded_channel::<RoomControl>();
+
+    state.ws_manager.register_connection(conn_id, user_id, session.session_id, outbound.clone(), control_tx).await;
+    METRICS.connections_open.fetch_add(1, Ordering::Relaxed);
+    info!(
+        "User {} connected ({}), open connections: {}",
+        user_id,
+        conn_id,
+        state.ws_manager.get_user_connections(user_id).await.len()
+    );
+
+    // Подписываем соединение на каждый чат
+    let mut subscriptions = Subscriptions::new(
+        conn_id,
+        user_id,
+        state.ws_manager.clone(),
+        state.scylla.clone(),
+        outbound.clone(),
+    );
     for chat_id in &user_chats {
-        if state.scylla.is_user_in_chat(*chat_id, user_id).await.unwrap_or(false) {
-            state.ws_manager.subscribe_user_to_chat(user_id, *chat_id).await;
-            debug!("User {} subscribed to chat {}", user_id, chat_id);
-        }
+        subscriptions.join(*chat_id).await;
+        debug!("Connection {} of user {} subscribed to chat {}", conn_id, user_id, chat_id);
     }
 
-    // Канал для получения событий чатов
-    let (event_tx, mut event_rx) = mpsc::channel::<ChatEvent>(32);
+    // Пользователь в сети: оповещаем собеседников
+    state.presence.connect(user_id).await;
 
-    // Запускаем подписку на каждый чат
-    let mut subscription_tasks = Vec::new();
+    // Отправка событий клиенту и серверные ping
+    let ping_interval = state.config.ws_ping_interval;
+    let mut send_task = tokio::spawn(async move {
+        let mut ping = tokio::time::interval_at(Instant::now() + ping_interval, ping_interval);
+        loop {
+            let message = tokio::select! {
+                frame = event_rx.recv() => match frame {
+                    Some(frame) => match serde_json::to_string(&frame) {
+                        Ok(p) => WsMessage::Text(p),
+                        Err(e) => {
+                            error!("JSON serialize error: {:?}", e);
+                            continue;
+                        }
+                    },
+                    None => {
+                        // Все отправители закрыты — соединение завершается сервером
+                        let _ = ws_sender.send(WsMessage::Close(None)).await;
+                        break;
+                    }
+                },
+                _ = ping.tick() => WsMessage::Ping(Vec::new()),
+            };
 
-    for chat_id in &user_chats {
-        let mut room_rx = state.ws_manager.subscribe_to_chat(*chat_id).await;
-        let tx = event_tx.clone(); // клонируем для задачи
+            let is_frame = matches!(message, WsMessage::Text(_));
+            if ws_sender.send(message).await.is_err() {
+                break; // клиент отключился
+            }
+            if is_frame {
+                WsMetrics::inc(&METRICS.frames_sent);
+            }
+        }
+    });
+
+    // Любой кадр от клиента (включая pong на наш ping) продлевает соединение
+    let idle_timeout = state.config.ws_idle_timeout;
+    let idle = tokio::time::sleep(idle_timeout);
+    tokio::pin!(idle);
+
+    let mut session_check = tokio::time::interval_at(Instant::now() + SESSION_CHECK_INTERVAL, SESSION_CHECK_INTERVAL);
+
+    // Обработка входящих сообщений (команд) и изменений состава чатов
+    loop {
+        let result = tokio::select! {
+            control = control_rx.recv() => {
+                let frame = match control {
+                    Some(RoomControl::Join(chat_id)) => {
+                        if !subscriptions.join(chat_id).await {
+                            continue;
+                        }
+                        info!("User {} subscribed to chat {} after being added", user_id, chat_id);
+                        ServerFrame::Subscribed { chat_id }
+                    }
+                    Some(RoomControl::Leave(chat_id)) => {
+                        typing.stop(chat_id).await;
+                        if !subscriptions.drain(chat_id).await {
+                            continue;
+                        }
+                        info!("User {} unsubscribed from chat {} after being removed", user_id, chat_id);
+                        ServerFrame::Unsubscribed { chat_id, reason: UnsubscribeReason::Removed }
+                    }
+                    Some(RoomControl::SessionRevoked(session_id)) => {
+                        // Соединение могло успеть перейти на токен другой сессии
+                        if session_id != session.session_id {
+                            continue;
+                        }
+                        info!("Closing connection {} of user {}: session revoked", conn_id, user_id);
+                        outbound.push(ServerFrame::SessionEnded { reason: SessionEndReason::SessionRevoked });
+                        break;
+                    }
+                    None => break,
+                };
 
-        let task = tokio::spawn(async move {
-            while let Ok(event) = room_rx.recv().await {
-                if tx.send(event).await.is_err() {
-                    break; // канал закрыт
+                if !outbound.send(frame).await {
+                    break;
                 }
+                continue;
             }
-        });
-
-        subscription_tasks.push(task);
-    }
+            result = ws_receiver.next() => match result {
+                Some(result) => {
+                    idle.as_mut().reset(Instant::now() + idle_timeout);
+                    result
+                }
+                None => break,
+            },
+            _ = &mut idle => {
+                info!("Connection {} of user {} idle for {:?}, closing", conn_id, user_id, idle_timeout);
+                WsMetrics::inc(&METRICS.idle_disconnects);
+                break;
+            }
+            _ = session_check.tick() => {
+                let ended = if chrono::Utc::now().timestamp() >= session.expires_at {
+                    Some(SessionEndReason::TokenExpired)
+                } else {
+                    match auth::session_active(&state, session.session_id, user_id).await {
+                        Ok(true) => None,
+                        Ok(false) => Some(SessionEndReason::SessionRevoked),
+                        Err(e) => {
+                            // База недоступна — не рвём соединения, проверим в следующий раз
+                            warn!("Failed to check session {} of user {}: {:?}", session.session_id, user_id, e);
+                            None
+                        }
+                    }
+                };
 
-    // Отправка событий клиенту
-    let send_task = tokio::spawn(async move {
-        while let Some(event) = event_rx.recv().await {
-            let msg = WsMessageOut {
-                r#type: "event".to_string(),
-                payload: event,
-            };
-            let payload = match serde_json::to_string(&msg) {
-                Ok(p) => p,
-                Err(e) => {
-                    error!("JSON serialize error: {:?}", e);
-                    continue;
+                match ended {
+                    Some(reason) => {
+                        info!("Closing connection {} of user {}: {:?}", conn_id, user_id, reason);
+                        outbound.push(ServerFrame::SessionEnded { reason });
+                        break;
+                    }
+                    None => continue,
                 }
-            };
-            if ws_sender.send(WsMessage::Text(payload)).await.is_err() {
-                break; // клиент отключился
             }
-        }
-    });
+            _ = overflow.notified() => {
+                warn!("Connection {} of user {} is not keeping up with outbound frames, closing", conn_id, user_id);
+                WsMetrics::inc(&METRICS.slow_consumer_disconnects);
+                break;
+            }
+        };
 
-    // Обработка входящих сообщений (команд)
-    while let Some(result) = ws_receiver.next().await {
         match result {
             Ok(WsMessage::Text(text)) => {
-                if let Ok(cmd) = serde_json::from_str::<WsCommand>(&text) {
-                    if cmd.r#type == "subscribe" {
-                        if state.scylla.is_user_in_chat(cmd.chat_id, user_id).await.unwrap_or(false) {
-                            state.ws_manager.subscribe_user_to_chat(user_id, cmd.chat_id).await;
-                            info!("User {} subscribed to chat {} via command", user_id, cmd.chat_id);
+                state.presence.touch(user_id).await;
+
+                let id = serde_json::from_str::<CommandEnvelope>(&text).ok().and_then(|e| e.id);
+
+                let cmd = match serde_json::from_str::<ClientCommand>(&text) {
+                    Ok(cmd) => cmd,
+                    Err(e) => {
+                        debug!("Invalid command from user {}: {}", user_id, e);
+                        outbound.send(ServerFrame::Error { id, error: "invalid command".into() }).await;
+                        continue;
+                    }
+                };
+
+                // Кадры о смене подписок уходят клиенту вслед за ответом на команду
+                let mut notices = Vec::new();
+
+                let result = match cmd {
+                    ClientCommand::Subscribe { chat_id } => {
+                        match commands::ensure_member(&state, chat_id, user_id).await {
+                            Ok(()) => {
+                                if subscriptions.join(chat_id).await {
+                                    info!("User {} subscribed to chat {} via command", user_id, chat_id);
+                                    notices.push(ServerFrame::Subscribed { chat_id });
+                                }
+                                Ok(serde_json::json!({ "chat_id": chat_id }))
+                            }
+                            Err(e) => Err(e),
+                        }
+                    }
+                    ClientCommand::Unsubscribe { chat_id } => {
+                        typing.stop(chat_id).await;
+                        if subscriptions.leave(chat_id).await {
+                            info!("User {} unsubscribed from chat {} via command", user_id, chat_id);
+                            notices.push(ServerFrame::Unsubscribed { chat_id, reason: UnsubscribeReason::Requested });
+                        }
+                        Ok(serde_json::json!({ "chat_id": chat_id }))
+                    }
+                    ClientCommand::Auth { token } => {
+                        match auth::authenticate_any(&state, &token).await {
+                            Ok(auth) if auth.user.id == user_id => {
+                                debug!("Connection {} of user {} re-authenticated", conn_id, user_id);
+                                let result = auth_result(&auth);
+                                if auth.session_id != session.session_id {
+                                    state.ws_manager.set_connection_session(conn_id, auth.session_id).await;
+                                }
+                                session = auth;
+                                Ok(result)
+                            }
+                            Ok(_) => Err(CommandError::Forbidden),
+                            Err(e) => Err(CommandError::Unauthorized(e.error)),
+                        }
+                    }
+                    ClientCommand::Resync { cursors } => {
+                        let mut checked = Ok(());
+                        for cursor in &cursors {
+                            if let Err(e) = commands::ensure_member(&state, cursor.chat_id, user_id).await {
+                                checked = Err(e);
+                                break;
+                            }
+                        }
+
+                        match checked {
+                            Ok(()) => {
+                                let mut joined = Vec::new();
+                                for cursor in &cursors {
+                                    if subscriptions.resync(*cursor).await {
+                                        joined.push(cursor.chat_id);
+                                    }
+                                }
+                                debug!("Connection {} resyncing {} chats", conn_id, cursors.len());
+                                notices = joined.into_iter().map(|chat_id| ServerFrame::Subscribed { chat_id }).collect();
+                                Ok(serde_json::json!({ "chat_ids": cursors.iter().map(|c| c.chat_id).collect::<Vec<_>>() }))
+                            }
+                            Err(e) => Err(e),
+                        }
+                    }
+                    ClientCommand::TypingStart { chat_id } | ClientCommand::TypingStop { chat_id }
+                        if !state.ws_manager.is_connection_subscribed(conn_id, chat_id).await =>
+                    {
+                        Err(CommandError::Forbidden)
+                    }
+                    ClientCommand::TypingStart { chat_id } => {
+                        typing.start(chat_id).await;
+                        Ok(serde_json::json!({ "chat_id": chat_id }))
+                    }
+                    ClientCommand::TypingStop { chat_id } => {
+                        typing.stop(chat_id).await;
+                        Ok(serde_json::json!({ "chat_id": chat_id }))
+                    }
+                    cmd => {
+                        // Отправленное сообщение завершает набор
+                        if let ClientCommand::SendMessage { chat_id, .. } = &cmd {
+                            typing.stop(*chat_id).await;
                         }
+                        commands::execute(&state, user_id, cmd).await
+                    }
+                };
+
+                let frame = match result {
+                    Ok(result) => ServerFrame::Ack { id, result },
+                    Err(e) => {
+                        if let CommandError::Internal(ref err) = e {
+                            warn!("Command from user {} failed: {:?}", user_id, err);
+                        }
+                        ServerFrame::Error { id, error: e.message() }
+                    }
+                };
+
+                if !outbound.send(frame).await {
+                    break; // отправка клиенту уже завершилась
+                }
+                let mut closed = false;
+                for notice in notices {
+                    if !outbound.send(notice).await {
+                        closed = true;
+                        break;
                     }
                 }
+                if closed {
+                    break;
+                }
+            }
+            Ok(WsMessage::Ping(_)) | Ok(WsMessage::Pong(_)) => {
+                state.presence.touch(user_id).await;
             }
             Ok(WsMessage::Close(_)) => {
                 info!("WebSocket closed by user {}", user_id);
@@ -113,18 +578,22 @@ pub async fn handle_websocket(
         }
     }
 
-    // Отписка от всех чатов при выходе
-    for chat_id in &user_chats {
-        state.ws_manager.unsubscribe_user_from_chat(user_id, *chat_id).await;
-        debug!("User {} unsubscribed from chat {}", user_id, chat_id);
-    }
+    // Гасим индикаторы набора и отмечаем уход (до отписки от чатов)
+    typing.clear().await;
+    state.presence.disconnect(user_id).await;
 
-    // Останавливаем задачи
-    send_task.abort();
-    for task in subscription_tasks {
-        task.abort();
-    }
+    // Снимаем соединение вместе со всеми его подписками; другие вкладки пользователя не затрагиваются
+    state.ws_manager.unregister_connection(conn_id).await;
+    METRICS.connections_open.fetch_sub(1, Ordering::Relaxed);
+    debug!("Connection {} of user {} closed", conn_id, user_id);
 
-    // Ждём немного, чтобы задачи завершились
-    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    // Останавливаем задачи. Отправка завершится сама, когда закроются все отправители,
+    // успев дописать последние кадры (например, `session_ended`)
+    subscriptions.abort_all();
+    drop(subscriptions);
+    drop(outbound);
+
+    if tokio::time::timeout(CLOSE_FLUSH_TIMEOUT, &mut send_task).await.is_err() {
+        send_task.abort();
+    }
 }
@@ -2,11 +2,12 @@
 
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tokio::sync::{RwLock, broadcast};
+use tokio::sync::{RwLock, broadcast, mpsc};
 use uuid::Uuid;
-use crate::models::ChatEvent;
+use crate::models::{EventEnvelope, ServerEvent};
+use crate::websocket::commands::ServerFrame;
+use crate::websocket::outbound::{Outbound, Push};
 use tracing::debug;
-use tokio::sync::broadcast::error::SendError as BroadcastSendError;
 
 
 /// Максимальный размер буфера рассылки на один чат
@@ -14,21 +15,52 @@ const BROADCAST_CAPACITY: usize = 256;
 
 #[derive(Debug)]
 pub enum BroadcastError {
-    SendFailed(broadcast::error::SendError<ChatEvent>),
+    SendFailed(broadcast::error::SendError<EventEnvelope>),
 }
-/// Логическая "комната" чата — хранит канал рассылки и счётчик подписчиков
+
+/// Команда открытому соединению по инициативе сервера
+#[derive(Debug, Clone, Copy)]
+pub enum RoomControl {
+    /// Пользователя добавили в чат
+    Join(Uuid),
+    /// Пользователя удалили из чата (или он вышел сам)
+    Leave(Uuid),
+    /// Сессия отозвана в auth-service — соединение закрывается
+    SessionRevoked(Uuid),
+}
+
+/// Логическая "комната" чата — хранит канал рассылки и счётчик подписанных соединений
 struct Room {
-    pub tx: broadcast::Sender<ChatEvent>,
+    pub tx: broadcast::Sender<EventEnvelope>,
     subscribers: usize,
 }
 
+/// Одно WebSocket-соединение (вкладка, устройство)
+struct Connection {
+    user_id: Uuid,
+    /// Сессия, по токену которой соединение аутентифицировано
+    session_id: Uuid,
+    chats: HashSet<Uuid>,
+    /// Очередь исходящих кадров — для событий конкретному пользователю
+    tx: Outbound,
+    /// Управление подписками соединения
+    control: mpsc::UnboundedSender<RoomControl>,
+}
+
+#[derive(Default)]
+struct Registry {
+    /// Активные соединения: conn_id → Connection
+    connections: HashMap<Uuid, Connection>,
+    /// Соединения пользователя: user_id → Set<conn_id>
+    user_connections: HashMap<Uuid, HashSet<Uuid>>,
+}
+
 /// Менеджер WebSocket-подключений
 #[derive(Clone)]
 pub struct ConnectionManager {
     /// Активные чаты: chat_id → Room
-    pub(crate) rooms: Arc<RwLock<HashMap<Uuid, Room>>>,
-    /// Подписки пользователей: user_id → Set<chat_id>
-    user_rooms: Arc<RwLock<HashMap<Uuid, HashSet<Uuid>>>>,
+    rooms: Arc<RwLock<HashMap<Uuid, Room>>>,
+    registry: Arc<RwLock<Registry>>,
 }
 
 impl ConnectionManager {
@@ -36,37 +68,90 @@ impl ConnectionManager {
     pub fn new() -> Self {
         Self {
             rooms: Arc::new(RwLock::new(HashMap::new())),
-            user_rooms: Arc::new(RwLock::new(HashMap::new())),
+            registry: Arc::new(RwLock::new(Registry::default())),
         }
     }
 
-    /// Подписывает пользователя на чат
-    pub async fn subscribe_user_to_chat(&self, user_id: Uuid, chat_id: Uuid) {
-        let _tx = self.get_or_create_room(chat_id).await;
+    /// Регистрирует новое соединение пользователя
+    pub async fn register_connection(
+        &self,
+        conn_id: Uuid,
+        user_id: Uuid,
+        session_id: Uuid,
+        tx: Outbound,
+        control: mpsc::UnboundedSender<RoomControl>,
+    ) {
+        let mut registry = self.registry.write().await;
+        registry.connections.insert(conn_id, Connection { user_id, session_id, chats: HashSet::new(), tx, control });
+        registry.user_connections.entry(user_id).or_default().insert(conn_id);
 
-        let mut user_rooms = self.user_rooms.write().await;
-        user_rooms.entry(user_id).or_default().insert(chat_id);
+        debug!("Connection {} of user {} registered", conn_id, user_id);
+    }
 
-        let mut rooms = self.rooms.write().await;
-        if let Some(room) = rooms.get_mut(&chat_id) {
-            room.subscribers += 1;
+    /// Снимает соединение и все его подписки
+    pub async fn unregister_connection(&self, conn_id: Uuid) {
+        let chats = {
+            let mut registry = self.registry.write().await;
+            let Some(conn) = registry.connections.remove(&conn_id) else {
+                return;
+            };
+
+            if let Some(conns) = registry.user_connections.get_mut(&conn.user_id) {
+                conns.remove(&conn_id);
+                if conns.is_empty() {
+                    registry.user_connections.remove(&conn.user_id);
+                }
+            }
+            conn.chats
+        };
+
+        for chat_id in chats {
+            self.release_room(chat_id).await;
         }
 
-        debug!("User {} subscribed to chat {}", user_id, chat_id);
+        debug!("Connection {} unregistered", conn_id);
     }
 
-    /// Отписывает пользователя от чата
-    pub async fn unsubscribe_user_from_chat(&self, user_id: Uuid, chat_id: Uuid) {
-        // Удаляем из списка чатов пользователя
-        let mut user_rooms = self.user_rooms.write().await;
-        if let Some(chats) = user_rooms.get_mut(&user_id) {
-            chats.remove(&chat_id);
-            if chats.is_empty() {
-                user_rooms.remove(&user_id);
+    /// Подписывает соединение на чат. Повторная подписка ничего не меняет
+    pub async fn subscribe_connection_to_chat(&self, conn_id: Uuid, chat_id: Uuid) {
+        let added = {
+            let mut registry = self.registry.write().await;
+            match registry.connections.get_mut(&conn_id) {
+                Some(conn) => conn.chats.insert(chat_id),
+                None => false,
             }
+        };
+
+        if added {
+            let _tx = self.get_or_create_room(chat_id).await;
+
+            let mut rooms = self.rooms.write().await;
+            if let Some(room) = rooms.get_mut(&chat_id) {
+                room.subscribers += 1;
+            }
+
+            debug!("Connection {} subscribed to chat {}", conn_id, chat_id);
         }
+    }
+
+    /// Отписывает соединение от чата, не затрагивая другие вкладки пользователя
+    pub async fn unsubscribe_connection_from_chat(&self, conn_id: Uuid, chat_id: Uuid) {
+        let removed = {
+            let mut registry = self.registry.write().await;
+            match registry.connections.get_mut(&conn_id) {
+                Some(conn) => conn.chats.remove(&chat_id),
+                None => false,
+            }
+        };
+
+        if removed {
+            self.release_room(chat_id).await;
+            debug!("Connection {} unsubscribed from chat {}", conn_id, chat_id);
+        }
+    }
 
-        // Уменьшаем счётчик, удаляем комнату при 0
+    /// Уменьшает счётчик подписчиков, удаляет комнату при 0
+    async fn release_room(&self, chat_id: Uuid) {
         let mut rooms = self.rooms.write().await;
         if let Some(room) = rooms.get_mut(&chat_id) {
             room.subscribers = room.subscribers.saturating_sub(1);
@@ -75,12 +160,10 @@ impl ConnectionManager {
                 debug!("Room {} removed (no subscribers)", chat_id);
             }
         }
-
-        debug!("User {} unsubscribed from chat {}", user_id, chat_id);
     }
 
     /// Возвращает `Sender`, создаёт канал, если чата ещё нет
-    pub async fn get_or_create_room(&self, chat_id: Uuid) -> broadcast::Sender<ChatEvent> {
+    pub async fn get_or_create_room(&self, chat_id: Uuid) -> broadcast::Sender<EventEnvelope> {
         {
             let rooms = self.rooms.read().await;
             if let Some(room) = rooms.get(&chat_id) {
@@ -99,29 +182,210 @@ impl ConnectionManager {
     }
 
     /// Рассылает событие всем в чате
-    pub async fn broadcast(&self, ev: ChatEvent) -> Result<(), BroadcastError> {
-    let chat_id = ev.chat_id;
-    let rooms = self.rooms.read().await;
-    if let Some(room) = rooms.get(&chat_id) {
-        room.tx.send(ev).map_err(|e| BroadcastError::SendFailed(e))?;
+    pub async fn broadcast(&self, ev: EventEnvelope) -> Result<(), BroadcastError> {
+        let chat_id = ev.chat_id;
+        let rooms = self.rooms.read().await;
+        if let Some(room) = rooms.get(&chat_id) {
+            room.tx.send(ev).map_err(BroadcastError::SendFailed)?;
+        }
+        Ok(())
+    }
+
+    /// Рассылает эфемерное событие (без Kafka и Scylla) всем в чате,
+    /// кроме соединения-источника
+    pub async fn broadcast_ephemeral(&self, chat_id: Uuid, event: ServerEvent, origin: Option<Uuid>) {
+        let mut envelope = EventEnvelope::new(chat_id, event);
+        envelope.origin = origin;
+
+        let rooms = self.rooms.read().await;
+        if let Some(room) = rooms.get(&chat_id) {
+            // Ошибка означает лишь отсутствие получателей
+            let _ = room.tx.send(envelope);
+        }
+    }
+
+    /// Отправляет событие во все соединения пользователя на этом инстансе.
+    /// Возвращает число соединений, которым событие доставлено
+    pub async fn send_to_user(&self, user_id: Uuid, ev: EventEnvelope) -> usize {
+        let senders: Vec<Outbound> = {
+            let registry = self.registry.read().await;
+            registry
+                .user_connections
+                .get(&user_id)
+                .into_iter()
+                .flatten()
+                .filter_map(|conn_id| registry.connections.get(conn_id))
+                .map(|conn| conn.tx.clone())
+                .collect()
+        };
+
+        // Без ожидания: медленное соединение не должно тормозить доставку остальным
+        senders
+            .into_iter()
+            .filter(|tx| tx.push(ServerFrame::Event { payload: Box::new(ev.clone()) }) == Push::Queued)
+            .count()
     }
-    Ok(())
-}
 
-    /// Возвращает список чатов, на которые подписан пользователь
+    /// Передаёт изменение подписок во все соединения пользователя на этом инстансе.
+    /// Канал неограниченный: такие команды нельзя терять
+    pub async fn control_user(&self, user_id: Uuid, control: RoomControl) -> usize {
+        let registry = self.registry.read().await;
+        registry
+            .user_connections
+            .get(&user_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|conn_id| registry.connections.get(conn_id))
+            .filter(|conn| conn.control.send(control).is_ok())
+            .count()
+    }
+
+    /// Соединение повторно аутентифицировалось токеном другой сессии
+    pub async fn set_connection_session(&self, conn_id: Uuid, session_id: Uuid) {
+        if let Some(conn) = self.registry.write().await.connections.get_mut(&conn_id) {
+            conn.session_id = session_id;
+        }
+    }
+
+    /// Передаёт команду всем соединениям сессии на этом инстансе
+    pub async fn control_session(&self, session_id: Uuid, control: RoomControl) -> usize {
+        let registry = self.registry.read().await;
+        registry
+            .connections
+            .values()
+            .filter(|conn| conn.session_id == session_id)
+            .filter(|conn| conn.control.send(control).is_ok())
+            .count()
+    }
+
+    /// Возвращает список чатов, на которые подписано хотя бы одно соединение пользователя
     pub async fn get_user_chats(&self, user_id: Uuid) -> Vec<Uuid> {
-        let user_rooms = self.user_rooms.read().await;
-        user_rooms
+        let registry = self.registry.read().await;
+        let chats: HashSet<Uuid> = registry
+            .user_connections
             .get(&user_id)
-            .cloned()
-            .unwrap_or_default()
             .into_iter()
-            .collect()
+            .flatten()
+            .filter_map(|conn_id| registry.connections.get(conn_id))
+            .flat_map(|conn| conn.chats.iter().copied())
+            .collect();
+        chats.into_iter().collect()
+    }
+
+    /// Активные соединения пользователя
+    pub async fn get_user_connections(&self, user_id: Uuid) -> Vec<Uuid> {
+        let registry = self.registry.read().await;
+        registry
+            .user_connections
+            .get(&user_id)
+            .map(|conns| conns.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Подписано ли соединение на чат
+    pub async fn is_connection_subscribed(&self, conn_id: Uuid, chat_id: Uuid) -> bool {
+        let registry = self.registry.read().await;
+        registry
+            .connections
+            .get(&conn_id)
+            .map(|conn| conn.chats.contains(&chat_id))
+            .unwrap_or(false)
     }
 
     /// Подписаться на события чата (всегда возвращает Receiver)
-    pub async fn subscribe_to_chat(&self, chat_id: Uuid) -> broadcast::Receiver<ChatEvent> {
+    pub async fn subscribe_to_chat(&self, chat_id: Uuid) -> broadcast::Receiver<EventEnvelope> {
         let _tx = self.get_or_create_room(chat_id).await;
         _tx.subscribe()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SlowConsumerPolicy;
+
+    struct TestConn {
+        id: Uuid,
+        frames: mpsc::Receiver<ServerFrame>,
+        control: mpsc::UnboundedReceiver<RoomControl>,
+    }
+
+    async fn connect(manager: &ConnectionManager, user_id: Uuid, session_id: Uuid) -> TestConn {
+        let id = Uuid::new_v4();
+        let (tx, frames, _overflow) = Outbound::channel(8, SlowConsumerPolicy::Drop);
+        let (control_tx, control) = mpsc::unbounded_channel();
+        manager.register_connection(id, user_id, session_id, tx, control_tx).await;
+        TestConn { id, frames, control }
+    }
+
+    async fn subscribers(manager: &ConnectionManager, chat_id: Uuid) -> Option<usize> {
+        manager.rooms.read().await.get(&chat_id).map(|room| room.subscribers)
+    }
+
+    #[tokio::test]
+    async fn room_lives_while_any_connection_is_subscribed() {
+        let manager = ConnectionManager::new();
+        let (user_id, chat_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let phone = connect(&manager, user_id, Uuid::new_v4()).await;
+        let laptop = connect(&manager, user_id, Uuid::new_v4()).await;
+
+        manager.subscribe_connection_to_chat(phone.id, chat_id).await;
+        manager.subscribe_connection_to_chat(laptop.id, chat_id).await;
+        // Повторная подписка того же соединения счётчик не меняет
+        manager.subscribe_connection_to_chat(laptop.id, chat_id).await;
+        assert_eq!(subscribers(&manager, chat_id).await, Some(2));
+
+        // Отписка одной вкладки не трогает другую
+        manager.unsubscribe_connection_from_chat(phone.id, chat_id).await;
+        manager.unsubscribe_connection_from_chat(phone.id, chat_id).await;
+        assert_eq!(subscribers(&manager, chat_id).await, Some(1));
+        assert!(!manager.is_connection_subscribed(phone.id, chat_id).await);
+        assert!(manager.is_connection_subscribed(laptop.id, chat_id).await);
+        assert_eq!(manager.get_user_chats(user_id).await, vec![chat_id]);
+
+        manager.unregister_connection(laptop.id).await;
+        assert_eq!(subscribers(&manager, chat_id).await, None);
+        assert!(manager.get_user_chats(user_id).await.is_empty());
+        assert_eq!(manager.get_user_connections(user_id).await, vec![phone.id]);
+    }
+
+    #[tokio::test]
+    async fn unknown_connection_does_not_touch_rooms() {
+        let manager = ConnectionManager::new();
+        let chat_id = Uuid::new_v4();
+
+        manager.subscribe_connection_to_chat(Uuid::new_v4(), chat_id).await;
+        assert_eq!(subscribers(&manager, chat_id).await, None);
+
+        manager.unregister_connection(Uuid::new_v4()).await;
+        manager.unsubscribe_connection_from_chat(Uuid::new_v4(), chat_id).await;
+        assert_eq!(subscribers(&manager, chat_id).await, None);
+    }
+
+    #[tokio::test]
+    async fn user_and_session_targeting() {
+        let manager = ConnectionManager::new();
+        let (user_id, session_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut first = connect(&manager, user_id, session_id).await;
+        let mut second = connect(&manager, user_id, Uuid::new_v4()).await;
+        let mut stranger = connect(&manager, Uuid::new_v4(), Uuid::new_v4()).await;
+
+        let event = EventEnvelope::new(Uuid::new_v4(), ServerEvent::TypingStarted { user_id });
+        assert_eq!(manager.send_to_user(user_id, event).await, 2);
+        assert!(matches!(first.frames.try_recv(), Ok(ServerFrame::Event { .. })));
+        assert!(matches!(second.frames.try_recv(), Ok(ServerFrame::Event { .. })));
+        assert!(stranger.frames.try_recv().is_err());
+
+        // Отзыв сессии касается только её соединений
+        assert_eq!(manager.control_session(session_id, RoomControl::SessionRevoked(session_id)).await, 1);
+        assert!(matches!(first.control.try_recv(), Ok(RoomControl::SessionRevoked(id)) if id == session_id));
+        assert!(second.control.try_recv().is_err());
+
+        // После повторной аутентификации соединение относится к новой сессии
+        let renewed = Uuid::new_v4();
+        manager.set_connection_session(second.id, renewed).await;
+        assert_eq!(manager.control_session(renewed, RoomControl::SessionRevoked(renewed)).await, 1);
+        assert!(second.control.try_recv().is_ok());
+        assert!(stranger.control.try_recv().is_err());
+    }
+}
@@ -0,0 +1,48 @@
+// src/websocket/metrics.rs
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+/// Счётчики WebSocket-доставки (экспортируются на `/metrics` в формате Prometheus)
+pub struct WsMetrics {
+    pub connections_open: AtomicI64,
+    pub frames_sent: AtomicU64,
+    /// Кадры, не поместившиеся в очередь медленного клиента
+    pub frames_dropped: AtomicU64,
+    pub slow_consumer_disconnects: AtomicU64,
+    pub idle_disconnects: AtomicU64,
+}
+
+pub static METRICS: WsMetrics = WsMetrics {
+    connections_open: AtomicI64::new(0),
+    frames_sent: AtomicU64::new(0),
+    frames_dropped: AtomicU64::new(0),
+    slow_consumer_disconnects: AtomicU64::new(0),
+    idle_disconnects: AtomicU64::new(0),
+};
+
+impl WsMetrics {
+    pub fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let gauges = [("chat_ws_connections_open", "Open WebSocket connections", self.connections_open.load(Ordering::Relaxed))];
+        let counters = [
+            ("chat_ws_frames_sent_total", "Frames written to WebSocket clients", &self.frames_sent),
+            ("chat_ws_frames_dropped_total", "Frames dropped because the client outbound queue was full", &self.frames_dropped),
+            ("chat_ws_slow_consumer_disconnects_total", "Connections closed for not keeping up with outbound frames", &self.slow_consumer_disconnects),
+            ("chat_ws_idle_disconnects_total", "Connections closed after the idle timeout", &self.idle_disconnects),
+        ];
+
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}");
+        }
+        for (name, help, counter) in counters {
+            let value = counter.load(Ordering::Relaxed);
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}");
+        }
+        out
+    }
+}
@@ -3,5 +3,10 @@
 pub mod gateway;
 pub mod handler;
 pub mod manager;
+pub mod commands;
+pub mod typing;
+pub mod resync;
+pub mod outbound;
+pub mod metrics;
 
 
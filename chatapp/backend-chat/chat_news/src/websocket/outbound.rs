@@ -0,0 +1,121 @@
+// src/websocket/outbound.rs
+
+use std::sync::Arc;
+use tokio::sync::{Notify, mpsc, mpsc::error::TrySendError};
+
+use crate::config::SlowConsumerPolicy;
+use crate::websocket::commands::ServerFrame;
+use crate::websocket::metrics::{METRICS, WsMetrics};
+
+/// Результат постановки кадра в очередь
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Push {
+    Queued,
+    /// Очередь полна, кадр отброшен
+    Dropped,
+    /// Соединение закрыто (или закрывается как медленное)
+    Closed,
+}
+
+/// Ограниченная очередь исходящих кадров соединения.
+/// Рассылка никогда не ждёт медленного клиента — поведение при переполнении задаёт `SlowConsumerPolicy`
+#[derive(Clone)]
+pub struct Outbound {
+    tx: mpsc::Sender<ServerFrame>,
+    policy: SlowConsumerPolicy,
+    overflow: Arc<Notify>,
+}
+
+impl Outbound {
+    /// Возвращает очередь, её приёмник и сигнал «клиент не успевает, закрываем»
+    pub fn channel(capacity: usize, policy: SlowConsumerPolicy) -> (Self, mpsc::Receiver<ServerFrame>, Arc<Notify>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        let overflow = Arc::new(Notify::new());
+        (Self { tx, policy, overflow: overflow.clone() }, rx, overflow)
+    }
+
+    /// Ставит кадр в очередь без ожидания
+    pub fn push(&self, frame: ServerFrame) -> Push {
+        match self.tx.try_send(frame) {
+            Ok(()) => Push::Queued,
+            Err(TrySendError::Full(_)) => {
+                WsMetrics::inc(&METRICS.frames_dropped);
+                match self.policy {
+                    SlowConsumerPolicy::Drop => Push::Dropped,
+                    SlowConsumerPolicy::Close => {
+                        self.overflow.notify_one();
+                        Push::Closed
+                    }
+                }
+            }
+            Err(TrySendError::Closed(_)) => Push::Closed,
+        }
+    }
+
+    /// Ответ на команду клиента: ждём места в очереди, читающий цикл и так принадлежит этому клиенту.
+    /// `false` — соединение закрыто
+    pub async fn send(&self, frame: ServerFrame) -> bool {
+        self.tx.send(frame).await.is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn frame() -> ServerFrame {
+        ServerFrame::Subscribed { chat_id: Uuid::new_v4() }
+    }
+
+    #[tokio::test]
+    async fn drop_policy_discards_frames_when_full() {
+        let (tx, mut rx, overflow) = Outbound::channel(2, SlowConsumerPolicy::Drop);
+
+        assert_eq!(tx.push(frame()), Push::Queued);
+        assert_eq!(tx.push(frame()), Push::Queued);
+        assert_eq!(tx.push(frame()), Push::Dropped);
+
+        // Соединение не закрывается, после вычитывания очередь снова принимает кадры
+        let closing = tokio::time::timeout(std::time::Duration::from_millis(10), overflow.notified()).await;
+        assert!(closing.is_err());
+        assert!(rx.try_recv().is_ok());
+        assert_eq!(tx.push(frame()), Push::Queued);
+    }
+
+    #[tokio::test]
+    async fn close_policy_signals_overflow() {
+        let (tx, _rx, overflow) = Outbound::channel(1, SlowConsumerPolicy::Close);
+
+        assert_eq!(tx.push(frame()), Push::Queued);
+        assert_eq!(tx.push(frame()), Push::Closed);
+
+        // notify_one сохраняет сигнал, даже если его ещё никто не ждёт
+        let closing = tokio::time::timeout(std::time::Duration::from_millis(10), overflow.notified()).await;
+        assert!(closing.is_ok());
+    }
+
+    #[tokio::test]
+    async fn closed_receiver_reports_closed() {
+        let (tx, rx, _overflow) = Outbound::channel(4, SlowConsumerPolicy::Drop);
+        drop(rx);
+
+        assert_eq!(tx.push(frame()), Push::Closed);
+        assert!(!tx.send(frame()).await);
+    }
+
+    #[tokio::test]
+    async fn send_waits_for_room_instead_of_dropping() {
+        let (tx, mut rx, _overflow) = Outbound::channel(1, SlowConsumerPolicy::Drop);
+        assert_eq!(tx.push(frame()), Push::Queued);
+
+        let sender = tx.clone();
+        let pending = tokio::spawn(async move { sender.send(frame()).await });
+        tokio::task::yield_now().await;
+        assert!(!pending.is_finished());
+
+        assert!(rx.recv().await.is_some());
+        assert!(pending.await.unwrap());
+        assert!(rx.recv().await.is_some());
+    }
+}
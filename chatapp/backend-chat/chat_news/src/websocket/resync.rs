@@ -0,0 +1,192 @@
+// src/websocket/resync.rs
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::db::ScyllaDb;
+use crate::db::messages::Message;
+use crate::models::ServerEvent;
+
+/// Сколько сообщений готовы дослать после разрыва. Дальше клиенту проще перечитать чат через REST
+pub const RESYNC_MAX_MESSAGES: usize = 500;
+
+/// Размер страницы при чтении ленты
+const RESYNC_PAGE_SIZE: i32 = 100;
+
+/// Время событий ставят разные инстансы: журнал изменений читаем с запасом на расхождение часов
+const CLOCK_SKEW_MARGIN_SECS: i64 = 5;
+
+/// Курсор клиента: последнее сообщение чата, которое он успел получить, и `occurred_at`
+/// последнего полученного события чата. Без второго досылаются только новые сообщения
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ChatCursor {
+    pub chat_id: Uuid,
+    pub last_message_id: Uuid,
+    #[serde(default)]
+    pub last_event_at: Option<DateTime<Utc>>,
+}
+
+/// Что клиент пропустил за время разрыва
+#[derive(Debug, Default)]
+pub struct Missed {
+    /// Новые сообщения, от старых к новым
+    pub messages: Vec<Message>,
+    /// Более старые сообщения, изменённые за время разрыва, в актуальном состоянии
+    pub updated: Vec<Message>,
+    /// Сообщения, удалённые безвозвратно
+    pub purged: Vec<Uuid>,
+}
+
+impl Missed {
+    /// Версии досланных сообщений: такие же и более старые живые события уже не нужны
+    pub fn versions(&self) -> HashMap<Uuid, i64> {
+        self.messages
+            .iter()
+            .chain(&self.updated)
+            .map(|m| (m.message_id, m.version))
+            .collect()
+    }
+}
+
+/// Живое событие уже учтено в досланном состоянии сообщения
+pub fn already_replayed(event: &ServerEvent, versions: &HashMap<Uuid, i64>) -> bool {
+    let replayed = |message_id: &Uuid, version: i64| versions.get(message_id).is_some_and(|v| *v >= version);
+
+    match event {
+        ServerEvent::MessageCreated(created) => versions.contains_key(&created.message_id),
+        ServerEvent::MessageEdited { message_id, version, .. }
+        | ServerEvent::MessageDeleted { message_id, version, .. }
+        | ServerEvent::MessageRestored { message_id, version, .. }
+        | ServerEvent::MessageMediaAttached { message_id, version, .. } => replayed(message_id, *version),
+        _ => false,
+    }
+}
+
+/// Почему события чата нельзя восстановить через поток
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResyncReason {
+    /// Соединение не успевало читать, часть событий комнаты пропущена
+    Lagged,
+    /// Сообщение из курсора не найдено в этом чате
+    UnknownCursor,
+    /// Пропущено больше `RESYNC_MAX_MESSAGES` сообщений
+    GapTooLarge,
+    /// Не удалось прочитать пропущенное из Scylla
+    Failed,
+}
+
+/// Сообщения чата новее курсора, от старых к новым (в актуальном состоянии: с правками, удалениями и реакциями),
+/// и изменения более старых сообщений после `last_event_at`
+pub async fn missed_messages(scylla: &ScyllaDb, cursor: ChatCursor) -> Result<Result<Missed, ResyncReason>> {
+    let messages = match new_messages(scylla, cursor).await? {
+        Ok(messages) => messages,
+        Err(reason) => return Ok(Err(reason)),
+    };
+
+    let Some(last_event_at) = cursor.last_event_at else {
+        return Ok(Ok(Missed { messages, ..Missed::default() }));
+    };
+
+    // Новые сообщения уже в актуальном состоянии
+    let known: HashSet<Uuid> = messages.iter().map(|m| m.message_id).collect();
+    let since = last_event_at - Duration::seconds(CLOCK_SKEW_MARGIN_SECS);
+    let changed: Vec<Uuid> = scylla.changed_since(cursor.chat_id, since)
+        .await?
+        .into_iter()
+        .filter(|id| !known.contains(id))
+        .collect();
+
+    if messages.len() + changed.len() > RESYNC_MAX_MESSAGES {
+        return Ok(Err(ResyncReason::GapTooLarge));
+    }
+
+    let updated = scylla.get_messages_by_ids(&changed).await?;
+    let present: HashSet<Uuid> = updated.iter().map(|m| m.message_id).collect();
+    let purged = changed.into_iter().filter(|id| !present.contains(id)).collect();
+
+    Ok(Ok(Missed { messages, updated, purged }))
+}
+
+/// Новые сообщения ленты после `last_message_id`, от старых к новым
+async fn new_messages(scylla: &ScyllaDb, cursor: ChatCursor) -> Result<Result<Vec<Message>, ResyncReason>> {
+    let last = match scylla.get_message_by_id(cursor.last_message_id).await? {
+        Some(msg) if msg.chat_id == cursor.chat_id && msg.thread_root_id.is_none() => msg,
+        _ => return Ok(Err(ResyncReason::UnknownCursor)),
+    };
+
+    let mut missed = Vec::new();
+    let mut paging_state = None;
+
+    // Лента отсортирована от новых к старым: читаем, пока не дойдём до курсора
+    loop {
+        let (page, next) = scylla.fetch_recent_paged(cursor.chat_id, RESYNC_PAGE_SIZE, paging_state).await?;
+
+        for msg in page {
+            if msg.message_id == last.message_id || msg.created_at < last.created_at {
+                missed.reverse();
+                return Ok(Ok(missed));
+            }
+            if missed.len() == RESYNC_MAX_MESSAGES {
+                return Ok(Err(ResyncReason::GapTooLarge));
+            }
+            missed.push(msg);
+        }
+
+        match next {
+            Some(state) => paging_state = Some(state),
+            None => break,
+        }
+    }
+
+    missed.reverse();
+    Ok(Ok(missed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ChatEvent;
+
+    fn edited(message_id: Uuid, version: i64) -> ServerEvent {
+        ServerEvent::MessageEdited {
+            message_id,
+            content: Some("text".into()),
+            edited_by: Uuid::new_v4(),
+            edited_at: Utc::now(),
+            version,
+        }
+    }
+
+    #[test]
+    fn live_events_older_than_replay_are_skipped() {
+        let (replayed_id, other_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let versions = HashMap::from([(replayed_id, 3)]);
+
+        assert!(already_replayed(&edited(replayed_id, 2), &versions));
+        assert!(already_replayed(&edited(replayed_id, 3), &versions));
+        assert!(!already_replayed(&edited(replayed_id, 4), &versions));
+        assert!(!already_replayed(&edited(other_id, 1), &versions));
+
+        let mut created = ChatEvent::new_message(Uuid::new_v4(), Uuid::new_v4(), Some("hi".into()), None, None);
+        created.message_id = replayed_id;
+        assert!(already_replayed(&ServerEvent::MessageCreated(created), &versions));
+
+        // Реакции несут итоговый счётчик, повтор безвреден — их не фильтруем
+        let reaction = ServerEvent::ReactionAdded { message_id: replayed_id, user_id: Uuid::new_v4(), emoji: "👍".into(), count: 1 };
+        assert!(!already_replayed(&reaction, &versions));
+    }
+
+    #[test]
+    fn cursor_without_event_time_is_accepted() {
+        let cursor: ChatCursor = serde_json::from_value(serde_json::json!({
+            "chat_id": Uuid::new_v4(),
+            "last_message_id": Uuid::new_v4(),
+        })).unwrap();
+        assert!(cursor.last_event_at.is_none());
+    }
+}
@@ -0,0 +1,168 @@
+// src/websocket/typing.rs
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::models::ServerEvent;
+use crate::websocket::manager::ConnectionManager;
+
+/// Через сколько секунд тишины индикатор набора гаснет сам
+pub const TYPING_TTL: Duration = Duration::from_secs(6);
+
+/// Активный индикатор: когда погаснет сам и таймер, который его погасит
+struct Typing {
+    deadline: Instant,
+    timer: JoinHandle<()>,
+}
+
+impl Typing {
+    /// Таймер ещё не сработал. После дедлайна `typing.stopped` уже отправлен (или отправляется) таймером
+    fn is_active(&self) -> bool {
+        !self.timer.is_finished() && Instant::now() < self.deadline
+    }
+}
+
+/// Индикаторы набора одного соединения: chat_id → таймер автоматического сброса
+pub struct TypingState {
+    conn_id: Uuid,
+    user_id: Uuid,
+    manager: Arc<ConnectionManager>,
+    timers: HashMap<Uuid, Typing>,
+}
+
+impl TypingState {
+    pub fn new(conn_id: Uuid, user_id: Uuid, manager: Arc<ConnectionManager>) -> Self {
+        Self {
+            conn_id,
+            user_id,
+            manager,
+            timers: HashMap::new(),
+        }
+    }
+
+    /// Пользователь набирает текст. Повторный start только продлевает индикатор
+    pub async fn start(&mut self, chat_id: Uuid) {
+        let already_typing = match self.timers.remove(&chat_id) {
+            Some(typing) if typing.is_active() => {
+                typing.timer.abort();
+                true
+            }
+            // Индикатор уже погас: дожидаемся typing.stopped от таймера, чтобы не обогнать его новым started
+            Some(typing) => {
+                let _ = typing.timer.await;
+                false
+            }
+            None => false,
+        };
+
+        if !already_typing {
+            self.manager
+                .broadcast_ephemeral(chat_id, ServerEvent::TypingStarted { user_id: self.user_id }, Some(self.conn_id))
+                .await;
+        }
+
+        let deadline = Instant::now() + TYPING_TTL;
+        let (manager, user_id, conn_id) = (self.manager.clone(), self.user_id, self.conn_id);
+        let timer = tokio::spawn(async move {
+            tokio::time::sleep_until(deadline).await;
+            manager
+                .broadcast_ephemeral(chat_id, ServerEvent::TypingStopped { user_id }, Some(conn_id))
+                .await;
+        });
+        self.timers.insert(chat_id, Typing { deadline, timer });
+    }
+
+    /// Пользователь перестал набирать (или отправил сообщение)
+    pub async fn stop(&mut self, chat_id: Uuid) {
+        if let Some(typing) = self.timers.remove(&chat_id) {
+            if typing.is_active() {
+                typing.timer.abort();
+                self.manager
+                    .broadcast_ephemeral(chat_id, ServerEvent::TypingStopped { user_id: self.user_id }, Some(self.conn_id))
+                    .await;
+            }
+        }
+    }
+
+    /// Гасит все индикаторы соединения (при отключении)
+    pub async fn clear(&mut self) {
+        let chat_ids: Vec<Uuid> = self.timers.keys().copied().collect();
+        for chat_id in chat_ids {
+            self.stop(chat_id).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::broadcast::{self, error::TryRecvError};
+    use crate::models::EventEnvelope;
+
+    fn typing_events(rx: &mut broadcast::Receiver<EventEnvelope>) -> Vec<&'static str> {
+        let mut kinds = Vec::new();
+        loop {
+            match rx.try_recv() {
+                Ok(envelope) => kinds.push(envelope.kind()),
+                Err(TryRecvError::Empty) => return kinds,
+                Err(e) => panic!("unexpected receive error: {:?}", e),
+            }
+        }
+    }
+
+    async fn setup() -> (TypingState, Uuid, broadcast::Receiver<EventEnvelope>) {
+        let manager = Arc::new(ConnectionManager::new());
+        let chat_id = Uuid::new_v4();
+        let rx = manager.subscribe_to_chat(chat_id).await;
+        (TypingState::new(Uuid::new_v4(), Uuid::new_v4(), manager), chat_id, rx)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn repeated_start_only_extends_indicator() {
+        let (mut typing, chat_id, mut rx) = setup().await;
+
+        typing.start(chat_id).await;
+        tokio::time::sleep(TYPING_TTL / 2).await;
+        typing.start(chat_id).await;
+        assert_eq!(typing_events(&mut rx), ["typing.started"]);
+
+        // Первый таймер отменён, индикатор гаснет через TTL от последнего start
+        tokio::time::sleep(TYPING_TTL / 2 + Duration::from_millis(10)).await;
+        assert!(typing_events(&mut rx).is_empty());
+
+        tokio::time::sleep(TYPING_TTL / 2).await;
+        assert_eq!(typing_events(&mut rx), ["typing.stopped"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn start_after_expiry_starts_again() {
+        let (mut typing, chat_id, mut rx) = setup().await;
+
+        typing.start(chat_id).await;
+        tokio::time::sleep(TYPING_TTL + Duration::from_millis(10)).await;
+        assert_eq!(typing_events(&mut rx), ["typing.started", "typing.stopped"]);
+
+        typing.start(chat_id).await;
+        assert_eq!(typing_events(&mut rx), ["typing.started"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stop_after_expiry_is_silent() {
+        let (mut typing, chat_id, mut rx) = setup().await;
+
+        typing.start(chat_id).await;
+        tokio::time::sleep(TYPING_TTL + Duration::from_millis(10)).await;
+        typing.stop(chat_id).await;
+        assert_eq!(typing_events(&mut rx), ["typing.started", "typing.stopped"]);
+
+        // Явный stop отменяет таймер: второго typing.stopped не будет
+        typing.start(chat_id).await;
+        typing.stop(chat_id).await;
+        tokio::time::sleep(TYPING_TTL * 2).await;
+        assert_eq!(typing_events(&mut rx), ["typing.started", "typing.stopped"]);
+    }
+}